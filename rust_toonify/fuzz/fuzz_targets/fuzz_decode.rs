@@ -0,0 +1,38 @@
+//! Fuzz target checking that the decoder never panics
+//!
+//! Arbitrary bytes are converted lossily to UTF-8 and fed to every decoding
+//! entry point, which must return `Ok` or `Err` but never panic. Run it from
+//! the `rust_toonify` directory with a nightly toolchain:
+//!
+//! ```text
+//! cargo install cargo-fuzz
+//! cargo +nightly fuzz run fuzz_decode
+//! ```
+//!
+//! Crashing inputs are saved under `fuzz/artifacts/fuzz_decode/` and can be
+//! replayed with `cargo +nightly fuzz run fuzz_decode <file>`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use toonify_rs::decoder;
+use toonify_rs::types::DecodeOptions;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decoder::decode_bytes(data);
+
+    let input = String::from_utf8_lossy(data);
+    let _ = decoder::decode(&input);
+    let _ = decoder::decode_lenient(&input);
+    let _ = decoder::decode_multi(&input);
+
+    let options = DecodeOptions::new()
+        .allow_plus_sign(true)
+        .newline_as_separator(true)
+        .allow_non_finite(true);
+    let _ = decoder::decode_with_options(&input, &options);
+
+    let mut stream = decoder::StreamDecoder::new();
+    stream.feed(&input);
+    while let Ok(Some(_)) = stream.try_finish() {}
+});
@@ -1,29 +1,565 @@
 //! TOON format decoder
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::str::Chars;
 
-use crate::types::ToonValue;
+use crate::types::{json_to_toon_value, BareKeywords, DecodeOptions, ToonValue};
 use crate::utils::{self, unescape_str};
 use crate::ToonError;
 
 /// Parse a TOON string into a `ToonValue`
 pub fn decode(input: &str) -> Result<ToonValue, ToonError> {
-    let mut parser = Parser::new(input);
+    decode_with_options(input, &DecodeOptions::default())
+}
+
+/// Parse a TOON string into a `ToonValue` with the given options
+///
+/// Input holding nothing but whitespace and comments is rejected with an
+/// `"empty input"` error.
+pub fn decode_with_options(
+    input: &str,
+    options: &DecodeOptions,
+) -> Result<ToonValue, ToonError> {
+    let mut parser = Parser::new(input, *options);
+    parser.check_not_empty()?;
     parser.parse()
 }
 
+/// Parse a TOON string into `scratch`, reusing its allocations
+///
+/// Arrays and objects already in `scratch` are refilled in place wherever the
+/// input has the same shape, so decoding many similarly shaped documents into
+/// one `scratch` value avoids most of the allocations `decode` would make for
+/// their containers. On error, `scratch` is left partially decoded.
+pub fn decode_into(input: &str, scratch: &mut ToonValue) -> Result<(), ToonError> {
+    let mut parser = Parser::new(input, DecodeOptions::default());
+    parser.check_not_empty()?;
+    parser.parse_into(scratch)
+}
+
+/// Parse a JSON string into a `ToonValue`
+///
+/// Integers that fit in an i64 become `Integer`; all other numbers become
+/// `Number`.
+pub fn decode_json(input: &str) -> Result<ToonValue, ToonError> {
+    let json: serde_json::Value = serde_json::from_str(input)
+        .map_err(|e| ToonError::Deserialization(e.to_string()))?;
+    Ok(json_to_toon_value(json))
+}
+
+/// Parse comma-separated values into an array of rows
+///
+/// With `has_header`, the first row supplies the keys and each following row
+/// becomes an object; otherwise every row becomes an array. Cells may be
+/// double-quoted to hold commas, newlines or `""`-escaped quotes. Unquoted
+/// cells are type-inferred: an empty cell or `null` is null, `true`/`false`
+/// are booleans, numeric text is a number and anything else is a string.
+/// Integers with leading zeros, like the IDs `007` and `-0042`, stay
+/// strings so the zeros aren't lost, and quoted cells are always strings.
+/// Blank lines are skipped.
+pub fn from_csv(input: &str, has_header: bool) -> Result<ToonValue, ToonError> {
+    let mut rows = parse_csv_rows(input)?.into_iter();
+    
+    if !has_header {
+        return Ok(ToonValue::Array(
+            rows.map(|row| ToonValue::Array(row.into_iter().map(csv_cell_value).collect()))
+                .collect(),
+        ));
+    }
+    
+    let header: Vec<String> = match rows.next() {
+        Some(header) => header.into_iter().map(|(cell, _)| cell).collect(),
+        None => return Ok(ToonValue::Array(Vec::new())),
+    };
+    
+    rows.enumerate()
+        .map(|(i, row)| {
+            if row.len() != header.len() {
+                return Err(ToonError::InvalidFormat(format!(
+                    "CSV row {} has {} fields, expected {}",
+                    i + 2,
+                    row.len(),
+                    header.len()
+                )));
+            }
+            Ok(ToonValue::Object(
+                header.iter().cloned().zip(row.into_iter().map(csv_cell_value)).collect(),
+            ))
+        })
+        .collect::<Result<_, _>>()
+        .map(ToonValue::Array)
+}
+
+/// Split CSV input into rows of `(text, was_quoted)` cells
+fn parse_csv_rows(input: &str) -> Result<Vec<Vec<(String, bool)>>, ToonError> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = input.chars().peekable();
+    
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if cell.is_empty() && !quoted => {
+                quoted = true;
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            cell.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => cell.push(c),
+                        None => {
+                            return Err(ToonError::InvalidFormat(format!(
+                                "Unterminated quoted cell in CSV row {}",
+                                rows.len() + 1
+                            )));
+                        }
+                    }
+                }
+            }
+            ',' => row.push((std::mem::take(&mut cell), std::mem::take(&mut quoted))),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            // A blank line holds no row, as at the end of a file ending in
+            // an extra newline
+            '\n' if row.is_empty() && cell.is_empty() && !quoted => {}
+            '\n' => {
+                row.push((std::mem::take(&mut cell), std::mem::take(&mut quoted)));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => cell.push(c),
+        }
+    }
+    
+    // The last row may lack a trailing newline
+    if !cell.is_empty() || quoted || !row.is_empty() {
+        row.push((cell, quoted));
+        rows.push(row);
+    }
+    
+    Ok(rows)
+}
+
+/// Whether `text` is a zero-padded number such as `007` or `-00.5`, where a
+/// number would lose the padding
+fn has_leading_zero(text: &str) -> bool {
+    let digits = text.strip_prefix(['-', '+']).unwrap_or(text).as_bytes();
+    digits.len() > 1 && digits[0] == b'0' && digits[1].is_ascii_digit()
+}
+
+fn csv_cell_value((cell, quoted): (String, bool)) -> ToonValue {
+    if quoted {
+        return ToonValue::String(cell);
+    }
+    
+    match cell.trim() {
+        "" | "null" => ToonValue::Null,
+        "true" => ToonValue::Bool(true),
+        "false" => ToonValue::Bool(false),
+        text if has_leading_zero(text) => ToonValue::String(cell),
+        text => {
+            if let Ok(i) = text.parse::<i64>() {
+                ToonValue::Integer(i)
+            } else if let Some(n) = text.parse::<f64>().ok().filter(|n| n.is_finite()) {
+                ToonValue::Number(n)
+            } else {
+                ToonValue::String(cell)
+            }
+        }
+    }
+}
+
+/// Parse a multi-document TOON string into one `ToonValue` per document
+///
+/// Documents are separated by a line containing only `---` (outside of any
+/// quoted string), as in YAML. Blank segments, such as one before a leading
+/// separator, are skipped. Each document is read as by [`decode_document`],
+/// so it may be a braceless object, and input left after it is an error.
+pub fn decode_multi(input: &str) -> Result<Vec<ToonValue>, ToonError> {
+    split_documents(input)
+        .into_iter()
+        .filter(|doc| !doc.trim().is_empty())
+        .map(decode_document)
+        .collect()
+}
+
+/// Split input on `---` separator lines that are not inside a string
+fn split_documents(input: &str) -> Vec<&str> {
+    let mut docs = Vec::new();
+    let mut rest = input;
+    while let Some((start, end)) = separator_line(rest) {
+        docs.push(&rest[..start]);
+        rest = &rest[end..];
+    }
+    docs.push(rest);
+    docs
+}
+
+/// Find the byte range of the first `---` separator line that is not inside
+/// a string, including its line break
+fn separator_line(input: &str) -> Option<(usize, usize)> {
+    let mut line_start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    
+    for line in input.split_inclusive('\n') {
+        if !in_string && line.trim_end() == "---" {
+            return Some((line_start, line_start + line.len()));
+        }
+        for c in line.chars() {
+            if escaped {
+                escaped = false;
+            } else if in_string && c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = !in_string;
+            } else if c == '#' && !in_string {
+                // The rest of the line is a comment
+                break;
+            }
+        }
+        line_start += line.len();
+    }
+    
+    None
+}
+
+/// Parse a tabular array, as written by the encoder for uniform objects
+///
+/// The input is a header row of field names, either bracketed (`[id, name]`)
+/// or bare (`id, name`), followed by one line per object holding its values
+/// in header order. Field names may be quoted, with escapes, when they
+/// aren't plain identifiers. Blank lines and `#` comments between rows are
+/// ignored. A row with fewer values than the header has `null` for its
+/// missing trailing fields, while one with more is an error.
+pub fn decode_tabular(input: &str) -> Result<ToonValue, ToonError> {
+    let mut parser = Parser::new(input, DecodeOptions::default());
+    parser.parse_tabular()
+}
+
+/// Parse a whole TOON document, as `encode` writes it
+///
+/// `decode` reads a single value and ignores whatever follows it, so it
+/// can't read the forms the encoder uses at the top level. This also reads
+/// a braceless object, written one `key: value` entry per line, and
+/// tabular arrays with a bracketed header, both at the top level and as the
+/// values of top-level entries. Input left after the document is an error.
+pub fn decode_document(input: &str) -> Result<ToonValue, ToonError> {
+    decode_document_with_options(input, &DecodeOptions::default())
+}
+
+/// Parse a whole TOON document, as `encode` writes it, with the given options
+pub fn decode_document_with_options(
+    input: &str,
+    options: &DecodeOptions,
+) -> Result<ToonValue, ToonError> {
+    let mut parser = Parser::new(input, *options);
+    parser.parse_document()
+}
+
+/// Parse a TOON string, recovering from errors where possible
+///
+/// Instead of stopping at the first error, the parser records it, skips
+/// ahead to the next `,` or closing bracket of the enclosing object or array
+/// and carries on, so later errors are reported too. The value is whatever
+/// could be parsed (entries that failed are left out), or `None` if nothing
+/// could be recovered. This is intended for editor tooling and diagnostics.
+pub fn decode_lenient(input: &str) -> (Option<ToonValue>, Vec<ToonError>) {
+    let mut parser = Parser::new(input, DecodeOptions::default());
+    parser.lenient = true;
+    
+    let value = match parser.parse() {
+        Ok(value) => Some(value),
+        Err(err) => {
+            parser.errors.push(err);
+            None
+        }
+    };
+    
+    (value, parser.errors)
+}
+
+/// Parse a TOON string that may have been cut off, such as truncated LLM
+/// output
+///
+/// Hitting the end of input inside arrays and objects closes all of them
+/// and returns the partial structure, so `{a: 1, b: [1, 2` decodes as
+/// `{a: 1, b: [1, 2]}`. An element cut off part way through (a dangling key,
+/// a key without its value, a half-written keyword) is left out. Errors
+/// anywhere else are still reported.
+pub fn decode_truncated(input: &str) -> Result<ToonValue, ToonError> {
+    let mut parser = Parser::new(input, DecodeOptions::default());
+    parser.close_at_eof = true;
+    parser.check_not_empty()?;
+    parser.parse()
+}
+
+/// Parse a TOON string, also returning a trace of the parser's decisions
+///
+/// Each entry describes one step with its position, such as
+/// `entered object at line 1, column 1`, `parsed key "id" at line 1,
+/// column 2` or `parsed 1 at line 1, column 6`, which helps pin down why a
+/// document parses unexpectedly. On error the trace shows how far parsing
+/// got. Only available with the `decode-debug` feature, so other decoding
+/// stays untraced.
+#[cfg(feature = "decode-debug")]
+pub fn decode_debug(input: &str) -> (Result<ToonValue, ToonError>, Vec<String>) {
+    let mut parser = Parser::new(input, DecodeOptions::default());
+    parser.trace = Some(Vec::new());
+    let result = parser.check_not_empty().and_then(|()| parser.parse());
+    (result, parser.trace.unwrap_or_default())
+}
+
+/// Parse raw bytes as UTF-8 TOON into a `ToonValue`
+///
+/// Invalid UTF-8 is reported with the byte offset of the first bad sequence.
+pub fn decode_bytes(input: &[u8]) -> Result<ToonValue, ToonError> {
+    let s = std::str::from_utf8(input).map_err(utf8_error)?;
+    decode(s)
+}
+
+/// Convert a UTF-8 validation failure into a positioned `ToonError`
+pub(crate) fn utf8_error(err: std::str::Utf8Error) -> ToonError {
+    ToonError::InvalidFormat(format!("invalid UTF-8 at byte {}", err.valid_up_to()))
+}
+
+/// Incremental decoder for input that arrives in chunks (e.g. from a socket)
+///
+/// Chunks are buffered with [`StreamDecoder::feed`] until
+/// [`StreamDecoder::try_finish`] finds a complete top-level document.
+/// Whitespace, `#` comments and `---` separator lines between documents are
+/// skipped. Objects, arrays and quoted strings are complete once their
+/// closing delimiter arrives; bare scalars (numbers, keywords, identifiers)
+/// are only complete once followed by whitespace, since more digits could
+/// still be on the way. A braceless object, as `encode` writes a top-level
+/// object, runs up to the next `---` line, since more entries could follow
+/// it. [`StreamDecoder::finish`] decodes the last document once the input
+/// has ended.
+#[derive(Debug, Default)]
+pub struct StreamDecoder {
+    buffer: String,
+}
+
+impl StreamDecoder {
+    /// Create an empty stream decoder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a chunk of input to the internal buffer
+    pub fn feed(&mut self, chunk: &str) {
+        self.buffer.push_str(chunk);
+    }
+
+    /// Decode the next complete document, if one is buffered
+    ///
+    /// Returns `Ok(None)` when more input is needed. Otherwise the document's
+    /// input is removed from the buffer, whether or not it decodes, so any
+    /// following documents can be read by calling this again.
+    pub fn try_finish(&mut self) -> Result<Option<ToonValue>, ToonError> {
+        let Some(start) = document_start(&self.buffer, false) else {
+            return Ok(None);
+        };
+        let Some(len) = document_len(&self.buffer[start..]) else {
+            return Ok(None);
+        };
+
+        let end = start + len;
+        let result = decode_document(&self.buffer[start..end]);
+        self.buffer.drain(..end);
+        result.map(Some)
+    }
+
+    /// Decode whatever is left once the input has ended
+    ///
+    /// Call this after `try_finish` returns `Ok(None)` at the end of the
+    /// stream, so a final document that was waiting for more input (a
+    /// braceless object, or a scalar with no whitespace after it) is read.
+    /// The rest of the buffer is decoded as one document and the buffer is
+    /// emptied. Returns `Ok(None)` if only whitespace, comments and
+    /// separators were left.
+    pub fn finish(&mut self) -> Result<Option<ToonValue>, ToonError> {
+        let buffer = std::mem::take(&mut self.buffer);
+        match document_start(&buffer, true) {
+            Some(start) => decode_document(&buffer[start..]).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Find the byte offset of the next document, past whitespace, comments and
+/// `---` separator lines
+///
+/// Returns `None` if no document has begun, or if a line that may be a
+/// comment or separator hasn't ended yet, unless the input is complete
+/// (`at_eof`).
+fn document_start(input: &str, at_eof: bool) -> Option<usize> {
+    let mut pos = 0;
+    loop {
+        let rest = input[pos..].trim_start();
+        pos = input.len() - rest.len();
+        if rest.is_empty() {
+            return None;
+        }
+        // No document starts with `--`, so such a line can only be a separator
+        if !rest.starts_with('#') && !rest.starts_with("--") {
+            return Some(pos);
+        }
+        
+        let line_len = match rest.find('\n') {
+            Some(i) => i,
+            None if at_eof => rest.len(),
+            None => return None,
+        };
+        if rest.starts_with("--") && rest[..line_len].trim_end() != "---" {
+            return Some(pos);
+        }
+        pos += line_len;
+    }
+}
+
+/// Find the length of the complete document at the start of `doc`, or
+/// `None` if more input is needed
+fn document_len(doc: &str) -> Option<usize> {
+    if starts_with_entry(doc)? {
+        // A separator line only counts once its line break has arrived,
+        // since `----` isn't one
+        return separator_line(doc)
+            .filter(|&(_, end)| doc[..end].ends_with('\n'))
+            .map(|(start, _)| start);
+    }
+    
+    let mut chars = doc.char_indices();
+    let (_, first) = chars.next()?;
+
+    match first {
+        '{' | '[' => {
+            let mut depth = 1usize;
+            let mut in_string = false;
+            let mut escaped = false;
+
+            let mut in_comment = false;
+
+            for (i, c) in chars {
+                if in_comment {
+                    in_comment = c != '\n';
+                    continue;
+                }
+
+                if in_string {
+                    if escaped {
+                        escaped = false;
+                    } else if c == '\\' {
+                        escaped = true;
+                    } else if c == '"' {
+                        in_string = false;
+                    }
+                    continue;
+                }
+
+                match c {
+                    '"' => in_string = true,
+                    '#' => in_comment = true,
+                    '{' | '[' => depth += 1,
+                    '}' | ']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(i + c.len_utf8());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            None
+        }
+        '"' => string_len(doc),
+        _ => chars.find(|(_, c)| c.is_whitespace()).map(|(i, _)| i),
+    }
+}
+
+/// Whether `doc` starts with a `key:` entry, as a braceless object does, or
+/// `None` if the key or what follows it hasn't fully arrived
+fn starts_with_entry(doc: &str) -> Option<bool> {
+    let key_len = match doc.chars().next()? {
+        '"' => string_len(doc)?,
+        c if utils::is_ident_start(c) => doc.find(|c| !utils::is_ident_continue(c))?,
+        _ => return Some(false),
+    };
+    let mut rest = doc[key_len..].chars().skip_while(|&c| c != '\n' && c.is_whitespace());
+    Some(rest.next()? == ':')
+}
+
+/// Find the length of the quoted string at the start of `doc`, or `None`
+/// if its closing quote hasn't arrived
+fn string_len(doc: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in doc.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            return Some(i + 1);
+        }
+    }
+    None
+}
+
+/// Maximum nesting depth of objects and arrays, so hostile input can't
+/// overflow the stack
+const MAX_DEPTH: usize = 128;
+
+/// Record a `format!`-style entry in the parser's trace (see `decode_debug`)
+#[cfg(feature = "decode-debug")]
+macro_rules! trace {
+    ($parser:expr, $($arg:tt)*) => {
+        if let Some(trace) = $parser.trace.as_mut() {
+            trace.push(format!($($arg)*));
+        }
+    };
+}
+
+/// Without the `decode-debug` feature tracing compiles to nothing
+#[cfg(not(feature = "decode-debug"))]
+macro_rules! trace {
+    ($($arg:tt)*) => {};
+}
+
+/// A point in the input to return to after looking ahead
+#[derive(Clone)]
+struct Position<'a> {
+    chars: Chars<'a>,
+    current: Option<char>,
+    line: usize,
+    col: usize,
+}
+
 /// Parser state for the TOON format
 struct Parser<'a> {
     chars: Chars<'a>,
     current: Option<char>,
     line: usize,
     col: usize,
+    options: DecodeOptions,
+    /// Whether to recover from errors inside containers (see `decode_lenient`)
+    lenient: bool,
+    errors: Vec<ToonError>,
+    eof_reported: bool,
+    /// Whether the end of input closes any open containers instead of being
+    /// an error (see `decode_truncated`)
+    close_at_eof: bool,
+    /// Trace of parser decisions, when recording one for `decode_debug`
+    #[cfg(feature = "decode-debug")]
+    trace: Option<Vec<String>>,
+    /// Current container nesting depth
+    depth: usize,
 }
 
 impl<'a> Parser<'a> {
     /// Create a new parser for the given input string
-    fn new(input: &'a str) -> Self {
+    fn new(input: &'a str, options: DecodeOptions) -> Self {
         let mut chars = input.chars();
         let current = chars.next();
         
@@ -32,13 +568,24 @@ impl<'a> Parser<'a> {
             current,
             line: 1,
             col: 1,
+            options,
+            lenient: false,
+            errors: Vec::new(),
+            eof_reported: false,
+            close_at_eof: false,
+            #[cfg(feature = "decode-debug")]
+            trace: None,
+            depth: 0,
         }
     }
     
     /// Advance to the next character
+    ///
+    /// `line` and `col` always give the position of the current character, so
+    /// a newline belongs to the line it ends. Only `\n` starts a new line,
+    /// which counts a `\r\n` pair once and lets `\n` and `\r\n` line
+    /// endings be mixed freely.
     fn next(&mut self) -> Option<char> {
-        self.current = self.chars.next();
-        
         if let Some(c) = self.current {
             if c == '\n' {
                 self.line += 1;
@@ -48,167 +595,764 @@ impl<'a> Parser<'a> {
             }
         }
         
+        self.current = self.chars.next();
         self.current
     }
     
-    /// Skip whitespace characters
+    /// The current position, for `reset`
+    fn position(&self) -> Position<'a> {
+        Position { chars: self.chars.clone(), current: self.current, line: self.line, col: self.col }
+    }
+    
+    /// Return to a position saved with `position`
+    fn reset(&mut self, position: Position<'a>) {
+        self.chars = position.chars;
+        self.current = position.current;
+        self.line = position.line;
+        self.col = position.col;
+    }
+    
+    /// Skip whitespace characters and `#` comments
     fn skip_whitespace(&mut self) {
+        self.skip_whitespace_until_newline();
+    }
+    
+    /// Skip whitespace characters and `#` comments, reporting whether a
+    /// newline was among them
+    ///
+    /// A comment runs from `#` to the end of the line, so it may follow a
+    /// value on the same line; the newline ending it still counts.
+    fn skip_whitespace_until_newline(&mut self) -> bool {
+        let mut saw_newline = false;
         while let Some(c) = self.current {
+            if c == '#' {
+                while !matches!(self.current, Some('\n') | None) {
+                    self.next();
+                }
+                continue;
+            }
             if !c.is_whitespace() {
                 break;
             }
+            saw_newline |= c == '\n';
             self.next();
         }
+        saw_newline
+    }
+    
+    /// Reject a document with no value, only whitespace and comments
+    fn check_not_empty(&mut self) -> Result<(), ToonError> {
+        self.skip_whitespace();
+        match self.current {
+            Some(_) => Ok(()),
+            None => Err(ToonError::InvalidFormat("empty input".to_string())),
+        }
     }
     
     /// Parse the input string into a `ToonValue`
     fn parse(&mut self) -> Result<ToonValue, ToonError> {
         self.skip_whitespace();
         
-        match self.current {
-            Some('{') => self.parse_object(),
-            Some('[') => self.parse_array(),
-            Some('"') => self.parse_string(),
-            Some('t') => self.parse_keyword("true", ToonValue::Bool(true)),
-            Some('f') => self.parse_keyword("false", ToonValue::Bool(false)),
-            Some('n') => self.parse_keyword("null", ToonValue::Null),
+        // Bare words are only checked for keywords in typed mode; otherwise
+        // they are identifiers like any other
+        let typed_keywords = self.options.bare_keywords == BareKeywords::AsTyped;
+        
+        #[cfg(feature = "decode-debug")]
+        let (line, col, container) = (self.line, self.col, matches!(self.current, Some('{' | '[')));
+        
+        // The container and string parsers rely on this dispatch to leave the
+        // opening delimiter as the current character
+        let result = match self.current {
+            Some('{') => self.parse_nested(Self::parse_object),
+            Some('[') => self.parse_nested(Self::parse_array),
+            Some('"') => self.parse_limited(Self::parse_string, self.options.max_string_len, "String"),
+            Some('t') if typed_keywords => self.parse_keyword("true", ToonValue::Bool(true)),
+            Some('f') if typed_keywords => self.parse_keyword("false", ToonValue::Bool(false)),
+            Some('n') if typed_keywords => self.parse_keyword("null", ToonValue::Null),
             Some(c) if c.is_ascii_digit() || c == '-' => self.parse_number(),
-            Some(c) if utils::is_ident_start(c) => self.parse_identifier(),
+            Some('+') if self.options.allow_plus_sign => self.parse_number(),
+            Some('.') if self.options.allow_bare_decimals => self.parse_number(),
+            Some('<') if self.options.allow_heredoc => {
+                self.parse_limited(Self::parse_heredoc, self.options.max_string_len, "String")
+            }
+            Some(c) if utils::is_ident_start(c) => {
+                self.parse_limited(Self::parse_identifier, self.options.max_string_len, "String")
+            }
             Some(c) => Err(ToonError::InvalidFormat(format!(
                 "Unexpected character '{}' at line {}, column {}",
                 c, self.line, self.col
             ))),
             None => Err(ToonError::InvalidFormat("Unexpected end of input".to_string())),
+        };
+        
+        // Containers trace their own steps, and errors bubbling out of them
+        // were already traced where they happened
+        #[cfg(feature = "decode-debug")]
+        match &result {
+            _ if container => {}
+            Ok(value) => trace!(self, "parsed {} at line {}, column {}", value, line, col),
+            Err(err) => trace!(self, "failed at line {}, column {}: {}", line, col, err),
+        }
+        result
+    }
+    
+    /// Parse a string or identifier with `parse`, rejecting strings longer
+    /// than `limit` characters with an error positioned at their start
+    fn parse_limited(
+        &mut self,
+        parse: fn(&mut Self) -> Result<ToonValue, ToonError>,
+        limit: Option<usize>,
+        what: &str,
+    ) -> Result<ToonValue, ToonError> {
+        let (line, col) = (self.line, self.col);
+        let value = parse(self)?;
+        if let (Some(limit), ToonValue::String(s)) = (limit, &value) {
+            let len = s.chars().count();
+            if len > limit {
+                return Err(ToonError::InvalidFormat(format!(
+                    "{} of {} characters exceeds the limit of {} at line {}, column {}",
+                    what, len, limit, line, col
+                )));
+            }
+        }
+        Ok(value)
+    }
+    
+    /// Parse a heredoc block string: `<<END` ending its line, then lines taken
+    /// literally up to one starting with `END`
+    ///
+    /// The line breaks between the lines are kept, but not the one before the
+    /// closing delimiter. Parsing resumes right after the delimiter, so it
+    /// may be followed by a `,` or closing bracket on the same line.
+    fn parse_heredoc(&mut self) -> Result<ToonValue, ToonError> {
+        let (line, col) = (self.line, self.col);
+        self.next();
+        if self.current != Some('<') {
+            return Err(ToonError::InvalidFormat(format!(
+                "Expected '<<' at line {}, column {}",
+                line, col
+            )));
+        }
+        self.next();
+        
+        let mut delimiter = String::new();
+        while let Some(c) = self.current.filter(|&c| utils::is_ident_continue(c)) {
+            delimiter.push(c);
+            self.next();
+        }
+        if !delimiter.starts_with(utils::is_ident_start) {
+            return Err(ToonError::InvalidFormat(format!(
+                "Expected a heredoc delimiter after '<<' at line {}, column {}",
+                line, col
+            )));
+        }
+        while self.current.is_some_and(|c| c != '\n' && c.is_whitespace()) {
+            self.next();
+        }
+        if self.current != Some('\n') {
+            return Err(ToonError::InvalidFormat(format!(
+                "Expected a line break after heredoc delimiter '{}' at line {}, column {}",
+                delimiter, self.line, self.col
+            )));
+        }
+        self.next();
+        
+        let mut text = String::new();
+        loop {
+            let mut indent = String::new();
+            while let Some(c) = self.current.filter(|&c| c != '\n' && c.is_whitespace()) {
+                indent.push(c);
+                self.next();
+            }
+            if self.at_heredoc_delimiter(&delimiter) {
+                for _ in delimiter.chars() {
+                    self.next();
+                }
+                break;
+            }
+            
+            text.push_str(&indent);
+            while let Some(c) = self.current.filter(|&c| c != '\n') {
+                text.push(c);
+                self.next();
+            }
+            if self.current.is_none() {
+                return Err(ToonError::InvalidFormat(format!(
+                    "Unterminated heredoc '{}' starting at line {}, column {}",
+                    delimiter, line, col
+                )));
+            }
+            text.push('\n');
+            self.next();
         }
+        
+        // Drop the line break before the closing delimiter
+        if text.ends_with('\n') {
+            text.pop();
+            if text.ends_with('\r') {
+                text.pop();
+            }
+        }
+        Ok(ToonValue::String(text))
+    }
+    
+    /// Whether the input at the current character is `delimiter` as a whole
+    /// word
+    fn at_heredoc_delimiter(&self, delimiter: &str) -> bool {
+        let Some(c) = self.current else {
+            return false;
+        };
+        let Some(rest) = delimiter.strip_prefix(c) else {
+            return false;
+        };
+        let after = self.chars.as_str();
+        after.starts_with(rest) && !after[rest.len()..].starts_with(utils::is_ident_continue)
+    }
+    
+    /// Parse a container with `parse`, enforcing the nesting limit
+    fn parse_nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ToonError>,
+    ) -> Result<T, ToonError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(ToonError::InvalidFormat(format!(
+                "Nesting deeper than {} levels at line {}, column {}",
+                MAX_DEPTH, self.line, self.col
+            )));
+        }
+        
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
     }
     
     /// Parse a JSON object
     fn parse_object(&mut self) -> Result<ToonValue, ToonError> {
-        assert_eq!(self.current, Some('{'));
-        self.next(); // Skip '{'
-        
         let mut obj = HashMap::new();
+        self.parse_object_into(&mut obj)?;
+        Ok(ToonValue::Object(obj))
+    }
+    
+    /// Parse a JSON object into `obj`
+    ///
+    /// The value of a key already in `obj` is parsed into in place (see
+    /// `parse_into`), keeping the map and its keys; keys the input doesn't
+    /// mention are dropped.
+    fn parse_object_into(&mut self, obj: &mut HashMap<String, ToonValue>) -> Result<(), ToonError> {
+        debug_assert_eq!(self.current, Some('{'));
+        trace!(self, "entered object at line {}, column {}", self.line, self.col);
+        self.next(); // Skip '{'
         
         // Handle empty object
         self.skip_whitespace();
         if self.current == Some('}') {
             self.next();
-            return Ok(ToonValue::Object(obj));
+            obj.clear();
+            return Ok(());
         }
         
+        let mut seen = HashSet::with_capacity(obj.len());
         loop {
-            // Parse key
-            self.skip_whitespace();
-            let key = match self.current {
-                Some('"') => self.parse_string()?,
-                Some(c) if utils::is_ident_start(c) => self.parse_identifier()?,
-                Some(ch) => {
+            match self.parse_object_key() {
+                Ok(key) => {
+                    let parsed = match obj.get_mut(&key) {
+                        Some(slot) => self.parse_into(slot),
+                        None => self.parse().map(|value| {
+                            obj.insert(key.clone(), value);
+                        }),
+                    };
+                    match parsed {
+                        Ok(()) => {
+                            seen.insert(key);
+                        }
+                        Err(err) => self.recover(err, '}')?,
+                    }
+                }
+                Err(err) => self.recover(err, '}')?,
+            }
+            
+            // A newline always separates entries
+            if !self.parse_separator('}', true)? {
+                break;
+            }
+        }
+        
+        if seen.len() < obj.len() {
+            obj.retain(|key, _| seen.contains(key));
+        }
+        trace!(self, "left object with {} entries", obj.len());
+        Ok(())
+    }
+    
+    /// Parse a whole document, which may be a braceless top-level object or
+    /// a tabular array, and must not be followed by anything else
+    fn parse_document(&mut self) -> Result<ToonValue, ToonError> {
+        self.check_not_empty()?;
+        let value = if self.at_entry_key() {
+            self.parse_document_object()?
+        } else {
+            self.parse_document_value(false)?
+        };
+        
+        self.skip_whitespace();
+        match self.current {
+            Some(c) => Err(ToonError::InvalidFormat(format!(
+                "Unexpected character '{}' after the end of the document at line {}, column {}",
+                c, self.line, self.col
+            ))),
+            None => Ok(value),
+        }
+    }
+    
+    /// Parse the entries of a braceless top-level object, one per line
+    fn parse_document_object(&mut self) -> Result<ToonValue, ToonError> {
+        let mut obj = HashMap::new();
+        loop {
+            let key = self.parse_object_key()?;
+            let value = self.parse_document_value(true)?;
+            obj.insert(key, value);
+            
+            let saw_newline = self.skip_whitespace_until_newline();
+            match self.current {
+                None => return Ok(ToonValue::Object(obj)),
+                Some(_) if saw_newline => {}
+                Some(c) => {
                     return Err(ToonError::InvalidFormat(format!(
-                        "Expected string or identifier at line {}, column {}, found '{}'",
-                        self.line, self.col, ch
+                        "Expected a new line before the next entry at line {}, column {}, found '{}'",
+                        self.line, self.col, c
                     )));
                 }
-                None => {
-                    return Err(ToonError::InvalidFormat(
-                        "Unexpected end of input while parsing object".to_string(),
-                    ));
+            }
+        }
+    }
+    
+    /// Parse a value at the top level of a document, reading a bracketed
+    /// header followed by rows as a tabular array
+    ///
+    /// In a top-level object (`in_object`) the rows end at the next
+    /// `key:` entry.
+    fn parse_document_value(&mut self, in_object: bool) -> Result<ToonValue, ToonError> {
+        self.skip_whitespace();
+        if self.current == Some('[') {
+            let start = self.position();
+            if let Ok(fields) = self.parse_tabular_header() {
+                if self.row_follows(in_object) {
+                    let mut rows = Vec::new();
+                    while self.row_follows(in_object) {
+                        self.skip_whitespace();
+                        let cells = self.parse_tabular_row(fields.len())?;
+                        rows.push(ToonValue::Object(fields.iter().cloned().zip(cells).collect()));
+                    }
+                    return Ok(ToonValue::Array(rows));
                 }
-            };
+            }
+            // Not a header, or one with no rows, so an ordinary array
+            self.reset(start);
+        }
+        self.parse()
+    }
+    
+    /// Whether a tabular row follows the current line
+    fn row_follows(&mut self, in_object: bool) -> bool {
+        let start = self.position();
+        self.skip_whitespace();
+        let follows = self.current.is_some() && !(in_object && self.at_entry_key());
+        self.reset(start);
+        follows
+    }
+    
+    /// Whether the input at the current character is a key followed by `:`
+    /// (or `=`, where allowed) on the same line
+    fn at_entry_key(&self) -> bool {
+        let mut rest = self.chars.clone();
+        let mut next = match self.current {
+            Some('"') => {
+                let mut escaped = false;
+                loop {
+                    match rest.next() {
+                        None => return false,
+                        Some(_) if escaped => escaped = false,
+                        Some('\\') => escaped = true,
+                        Some('"') => break rest.next(),
+                        Some(_) => {}
+                    }
+                }
+            }
+            Some(c) if utils::is_ident_start(c) => {
+                let mut next = rest.next();
+                while next.is_some_and(utils::is_ident_continue) {
+                    next = rest.next();
+                }
+                next
+            }
+            _ => return false,
+        };
+        while next.is_some_and(|c| c != '\n' && c.is_whitespace()) {
+            next = rest.next();
+        }
+        next == Some(':') || (next == Some('=') && self.options.allow_equals_separator)
+    }
+    
+    /// Parse a tabular header row followed by its value rows
+    fn parse_tabular(&mut self) -> Result<ToonValue, ToonError> {
+        self.skip_whitespace();
+        let fields = self.parse_tabular_header()?;
+        
+        let mut rows = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.current.is_none() {
+                break;
+            }
             
-            let key = match key {
-                ToonValue::String(s) => s,
-                _ => unreachable!("parse_string and parse_identifier return String"),
+            let cells = self.parse_tabular_row(fields.len())?;
+            rows.push(ToonValue::Object(fields.iter().cloned().zip(cells).collect()));
+        }
+        
+        Ok(ToonValue::Array(rows))
+    }
+    
+    /// Parse the header row of a tabular array into its field names
+    fn parse_tabular_header(&mut self) -> Result<Vec<String>, ToonError> {
+        let bracketed = self.current == Some('[');
+        if bracketed {
+            self.next();
+        }
+        
+        let mut fields = Vec::new();
+        loop {
+            self.skip_inline_whitespace();
+            let limit = self.options.max_key_len;
+            let field = match self.current {
+                Some('"') => self.parse_limited(Self::parse_string, limit, "Key")?,
+                Some(c) if utils::is_ident_start(c) => self.parse_limited(Self::parse_identifier, limit, "Key")?,
+                _ => {
+                    return Err(ToonError::InvalidFormat(format!(
+                        "Expected field name in tabular header at line {}, column {}",
+                        self.line, self.col
+                    )));
+                }
             };
+            fields.push(match field {
+                ToonValue::String(s) => s,
+                other => other.to_string(),
+            });
             
-            // Parse ':'
-            self.skip_whitespace();
-            if self.current != Some(':') {
+            self.skip_inline_whitespace();
+            match self.current {
+                Some(',') => {
+                    self.next();
+                }
+                Some(']') if bracketed => {
+                    self.next();
+                    self.skip_inline_whitespace();
+                    break;
+                }
+                Some('\n') | None if !bracketed => break,
+                _ => {
+                    return Err(ToonError::InvalidFormat(format!(
+                        "Expected ',' or {} in tabular header at line {}, column {}",
+                        if bracketed { "']'" } else { "end of line" },
+                        self.line,
+                        self.col
+                    )));
+                }
+            }
+        }
+        
+        if !matches!(self.current, Some('\n') | None) {
+            return Err(ToonError::InvalidFormat(format!(
+                "Expected end of line after tabular header at line {}, column {}",
+                self.line, self.col
+            )));
+        }
+        
+        Ok(fields)
+    }
+    
+    /// Parse one line of comma-separated cell values into exactly `width`
+    /// cells, padding a short row with nulls
+    fn parse_tabular_row(&mut self, width: usize) -> Result<Vec<ToonValue>, ToonError> {
+        let mut cells = Vec::with_capacity(width);
+        loop {
+            self.skip_inline_whitespace();
+            if matches!(self.current, Some('\n' | ',') | None) {
                 return Err(ToonError::InvalidFormat(format!(
-                    "Expected ':' after key at line {}, column {}",
+                    "Expected value in tabular row at line {}, column {}",
                     self.line, self.col
                 )));
             }
-            self.next();
-            
-            // Parse value
-            self.skip_whitespace();
-            let value = self.parse()?;
-            
-            // Insert into object
-            obj.insert(key, value);
+            if cells.len() == width {
+                return Err(ToonError::InvalidFormat(format!(
+                    "Extra cell beyond the {} header fields at line {}, column {}",
+                    width, self.line, self.col
+                )));
+            }
+            cells.push(self.parse()?);
             
-            // Parse ',' or '}'
-            self.skip_whitespace();
+            self.skip_inline_whitespace();
             match self.current {
                 Some(',') => {
                     self.next();
-                    continue;
                 }
-                Some('}') => {
-                    self.next();
-                    break;
+                Some('\n') | None => {
+                    cells.resize(width, ToonValue::Null);
+                    return Ok(cells);
                 }
                 _ => {
                     return Err(ToonError::InvalidFormat(format!(
-                        "Expected ',' or '}}' at line {}, column {}",
+                        "Expected ',' or end of row at line {}, column {}",
                         self.line, self.col
                     )));
                 }
             }
         }
+    }
+    
+    /// Skip whitespace and comments up to (but not including) a newline
+    fn skip_inline_whitespace(&mut self) {
+        while let Some(c) = self.current {
+            if c == '#' {
+                while !matches!(self.current, Some('\n') | None) {
+                    self.next();
+                }
+            } else if c != '\n' && c.is_whitespace() {
+                self.next();
+            } else {
+                break;
+            }
+        }
+    }
+    
+    /// Parse the `key:` part of an object entry
+    fn parse_object_key(&mut self) -> Result<String, ToonError> {
+        // Parse key
+        self.skip_whitespace();
+        #[cfg(feature = "decode-debug")]
+        let (line, col) = (self.line, self.col);
+        let limit = self.options.max_key_len;
+        let key = match self.current {
+            Some('"') => self.parse_limited(Self::parse_string, limit, "Key")?,
+            Some(c) if utils::is_ident_start(c) => self.parse_limited(Self::parse_identifier, limit, "Key")?,
+            Some(ch) => {
+                return Err(ToonError::InvalidFormat(format!(
+                    "Expected string or identifier at line {}, column {}, found '{}'",
+                    self.line, self.col, ch
+                )));
+            }
+            None => {
+                return Err(ToonError::InvalidFormat(
+                    "Unexpected end of input while parsing object".to_string(),
+                ));
+            }
+        };
         
-        Ok(ToonValue::Object(obj))
+        let key = match key {
+            ToonValue::String(s) => s,
+            // Bare true/false/null keys are still plain string keys
+            other => other.to_string(),
+        };
+        
+        // Parse ':'
+        self.skip_whitespace();
+        match self.current {
+            Some(':') => {
+                self.next();
+            }
+            Some('=') if self.options.allow_equals_separator => {
+                self.next();
+            }
+            Some(ch) => {
+                return Err(ToonError::InvalidFormat(format!(
+                    "Expected ':' after key at line {}, column {}, found '{}'",
+                    self.line, self.col, ch
+                )));
+            }
+            None => {
+                return Err(ToonError::InvalidFormat(format!(
+                    "Unexpected end of input, expected ':' after key '{}'",
+                    key
+                )));
+            }
+        }
+        
+        trace!(self, "parsed key {:?} at line {}, column {}", key, line, col);
+        Ok(key)
     }
     
     /// Parse a JSON array
     fn parse_array(&mut self) -> Result<ToonValue, ToonError> {
-        assert_eq!(self.current, Some('['));
+        let mut arr = Vec::new();
+        self.parse_array_into(&mut arr)?;
+        Ok(ToonValue::Array(arr))
+    }
+    
+    /// Parse a JSON array into `arr`, parsing elements into its existing
+    /// items in place (see `parse_into`) and truncating any left over
+    fn parse_array_into(&mut self, arr: &mut Vec<ToonValue>) -> Result<(), ToonError> {
+        debug_assert_eq!(self.current, Some('['));
+        trace!(self, "entered array at line {}, column {}", self.line, self.col);
         self.next(); // Skip '['
         
-        let mut arr = Vec::new();
+        let mut len = 0;
         
         // Handle empty array
         self.skip_whitespace();
         if self.current == Some(']') {
             self.next();
-            return Ok(ToonValue::Array(arr));
+            arr.clear();
+            return Ok(());
         }
         
         loop {
             // Parse value
             self.skip_whitespace();
-            let value = self.parse()?;
-            arr.push(value);
+            let parsed = match arr.get_mut(len) {
+                Some(slot) => self.parse_into(slot),
+                None => self.parse().map(|value| arr.push(value)),
+            };
+            match parsed {
+                Ok(()) => len += 1,
+                Err(err) => self.recover(err, ']')?,
+            }
             
-            // Parse ',' or ']'
-            self.skip_whitespace();
-            match self.current {
-                Some(',') => {
-                    self.next();
-                    continue;
+            // A newline only separates elements if enabled
+            if !self.parse_separator(']', self.options.newline_as_separator)? {
+                break;
+            }
+        }
+        
+        arr.truncate(len);
+        trace!(self, "left array with {} elements", len);
+        Ok(())
+    }
+    
+    /// Consume the `,` (or newline) after a container element, or the
+    /// closing `closer`
+    ///
+    /// Returns whether another element follows. In lenient mode a missing
+    /// separator is recorded and skipped past like any other error.
+    fn parse_separator(&mut self, closer: char, newline_separates: bool) -> Result<bool, ToonError> {
+        let saw_newline = self.skip_whitespace_until_newline();
+        match self.current {
+            None if self.close_at_eof => Ok(false),
+            Some(',') => {
+                self.next();
+                if self.options.allow_trailing_comma {
+                    self.skip_whitespace();
+                    if self.current == Some(closer) {
+                        self.next();
+                        return Ok(false);
+                    }
                 }
-                Some(']') => {
-                    self.next();
-                    break;
+                Ok(true)
+            }
+            Some(c) if c == closer => {
+                self.next();
+                Ok(false)
+            }
+            Some(_) if saw_newline && newline_separates => Ok(true),
+            _ => {
+                let err = ToonError::InvalidFormat(format!(
+                    "Expected ',' or '{}' at line {}, column {}",
+                    closer, self.line, self.col
+                ));
+                self.recover(err, closer)?;
+                match self.current {
+                    Some(',') => {
+                        self.next();
+                        Ok(true)
+                    }
+                    Some(c) if c == closer => {
+                        self.next();
+                        Ok(false)
+                    }
+                    _ => Ok(false),
                 }
-                _ => {
-                    return Err(ToonError::InvalidFormat(format!(
-                        "Expected ',' or ']' at line {}, column {}",
-                        self.line, self.col
-                    )));
+            }
+        }
+    }
+    
+    /// Parse a value into `slot`
+    ///
+    /// If `slot` already holds an array or object and the input has the same
+    /// kind of container, it is refilled in place so its allocations (and
+    /// those of its nested containers) are reused. Anything else replaces
+    /// `slot` with a freshly parsed value.
+    fn parse_into(&mut self, slot: &mut ToonValue) -> Result<(), ToonError> {
+        self.skip_whitespace();
+        match (self.current, slot) {
+            (Some('['), ToonValue::Array(arr)) => self.parse_nested(|p| p.parse_array_into(arr)),
+            (Some('{'), ToonValue::Object(obj)) => self.parse_nested(|p| p.parse_object_into(obj)),
+            (_, slot) => {
+                *slot = self.parse()?;
+                Ok(())
+            }
+        }
+    }
+    
+    /// Handle an error inside a container
+    ///
+    /// In lenient mode the error is recorded and input is skipped up to the
+    /// next `,` or `closer` at the current nesting level (or the end of
+    /// input), so the caller can resume. Otherwise the error is returned.
+    fn recover(&mut self, err: ToonError, closer: char) -> Result<(), ToonError> {
+        // A truncated element is dropped and its container closed by the
+        // separator check that follows
+        if self.close_at_eof && self.current.is_none() {
+            return Ok(());
+        }
+        if !self.lenient {
+            return Err(err);
+        }
+        
+        // Once the input is exhausted every enclosing container would fail
+        // the same way, so only the first error at EOF is worth reporting
+        if self.current.is_some() || !self.eof_reported {
+            self.eof_reported |= self.current.is_none();
+            self.errors.push(err);
+        }
+        
+        let mut depth = 0usize;
+        while let Some(c) = self.current {
+            match c {
+                '"' => {
+                    // Skip over the string so its contents can't resync us
+                    while let Some(c) = self.next() {
+                        if c == '\\' {
+                            self.next();
+                        } else if c == '"' {
+                            break;
+                        }
+                    }
                 }
+                '#' => {
+                    // Skip the comment so its contents can't resync us either
+                    while !matches!(self.current, Some('\n') | None) {
+                        self.next();
+                    }
+                    continue;
+                }
+                '{' | '[' => depth += 1,
+                '}' | ']' if depth > 0 => depth -= 1,
+                c if depth == 0 && (c == ',' || c == closer) => return Ok(()),
+                _ => {}
             }
+            self.next();
         }
         
-        Ok(ToonValue::Array(arr))
+        Ok(())
     }
     
     /// Parse a string value
     fn parse_string(&mut self) -> Result<ToonValue, ToonError> {
-        assert_eq!(self.current, Some('"'));
+        debug_assert_eq!(self.current, Some('"'));
+        let (line, col) = (self.line, self.col);
         self.next(); // Skip opening '"'
         
-        let mut s = String::new();
+        // Collect the raw contents, keeping escape sequences intact
+        let mut raw = String::new();
         
         while let Some(c) = self.current {
             match c {
@@ -217,73 +1361,57 @@ impl<'a> Parser<'a> {
                     break;
                 }
                 '\\' => {
-                    self.next(); // Skip '\\'
-                    let escaped = match self.current {
-                        Some('"') => '"',
-                        Some('\\') => '\\',
-                        Some('/') => '/',
-                        Some('b') => '\x08',
-                        Some('f') => '\x0c',
-                        Some('n') => '\n',
-                        Some('r') => '\r',
-                        Some('t') => '\t',
-                        Some('u') => {
-                            // Parse unicode escape sequence \uXXXX
-                            self.next(); // Skip 'u'
-                            let hex = self.take_chars(4);
-                            if hex.len() != 4 {
-                                return Err(ToonError::InvalidFormat(
-                                    "Invalid unicode escape sequence".to_string(),
-                                ));
-                            }
-                            
-                            let code = u32::from_str_radix(&hex, 16).map_err(|_| {
-                                ToonError::InvalidFormat("Invalid unicode code point".to_string())
-                            })?;
-                            
-                            std::char::from_u32(code).ok_or_else(|| {
-                                ToonError::InvalidFormat("Invalid unicode code point".to_string())
-                            })?
-                        }
-                        _ => {
-                            return Err(ToonError::InvalidFormat(format!(
-                                "Invalid escape sequence at line {}, column {}",
-                                self.line, self.col
-                            )));
-                        }
-                    };
-                    
-                    s.push(escaped);
-                    self.next();
+                    raw.push(c);
+                    if let Some(escaped) = self.next() {
+                        raw.push(escaped);
+                        self.next();
+                    }
                 }
                 _ => {
-                    s.push(c);
+                    raw.push(c);
                     self.next();
                 }
             }
         }
         
         // Unescape the string
-        let unescaped = unescape_str(&s).map_err(|e| ToonError::Deserialization(e))?;
+        let unescaped = unescape_str(&raw).map_err(|e| {
+            ToonError::InvalidFormat(format!(
+                "{} in string starting at line {}, column {}",
+                e, line, col
+            ))
+        })?;
         
         Ok(ToonValue::String(unescaped))
     }
     
     /// Parse a number value
     fn parse_number(&mut self) -> Result<ToonValue, ToonError> {
+        let (line, col) = (self.line, self.col);
         let mut num_str = String::new();
         let mut has_decimal = false;
         let mut has_exponent = false;
         
-        // Handle sign
-        if self.current == Some('-') {
-            num_str.push('-' as u8 as char);
+        // Handle sign, which must be followed by the number itself
+        if let Some(sign @ ('-' | '+')) = self.current {
+            if sign == '-' {
+                num_str.push('-');
+            }
             self.next();
+            // A point is left to the checks on the fractional part below
+            if !self.current.is_some_and(|c| c.is_ascii_digit() || c == '.') {
+                return Err(ToonError::InvalidFormat(format!(
+                    "Expected digit after '{}' at line {}, column {}",
+                    sign, self.line, self.col
+                )));
+            }
         }
         
         // Parse integer part
+        let mut has_int_digits = false;
         while let Some(c) = self.current {
             if c.is_ascii_digit() {
+                has_int_digits = true;
                 num_str.push(c);
                 self.next();
             } else {
@@ -291,10 +1419,16 @@ impl<'a> Parser<'a> {
             }
         }
         
-        // Parse fractional part
+        // Parse fractional part; `.5` and `5.` need `allow_bare_decimals`
         if self.current == Some('.') {
+            if !has_int_digits && !self.options.allow_bare_decimals {
+                return Err(ToonError::InvalidFormat(format!(
+                    "Expected digit before decimal point at line {}, column {}",
+                    self.line, self.col
+                )));
+            }
             has_decimal = true;
-            num_str.push('.' as u8 as char);
+            num_str.push('.');
             self.next();
             
             let mut has_digits = false;
@@ -308,17 +1442,19 @@ impl<'a> Parser<'a> {
                 }
             }
             
-            if !has_digits {
-                return Err(ToonError::InvalidFormat(
-                    "Expected digit after decimal point".to_string(),
-                ));
+            let bare_point_ok = has_int_digits && self.options.allow_bare_decimals;
+            if !has_digits && !bare_point_ok {
+                return Err(ToonError::InvalidFormat(format!(
+                    "Expected digit after decimal point at line {}, column {}",
+                    self.line, self.col
+                )));
             }
         }
         
         // Parse exponent
-        if self.current == Some('e') || self.current == Some('E') {
+        if let Some(e @ ('e' | 'E')) = self.current {
             has_exponent = true;
-            num_str.push('e' as u8 as char);
+            num_str.push(e);
             self.next();
             
             if self.current == Some('+') || self.current == Some('-') {
@@ -344,14 +1480,41 @@ impl<'a> Parser<'a> {
             }
         }
         
-        // Parse the number
+        if let Some((min, max)) = self.options.number_range {
+            let n = num_str.parse::<f64>()
+                .map_err(|e| ToonError::Deserialization(e.to_string()))?;
+            if !(min..=max).contains(&n) {
+                return Err(ToonError::Deserialization(format!(
+                    "number {} outside the allowed range {} to {} at line {}, column {}",
+                    num_str, min, max, line, col
+                )));
+            }
+        }
+        
+        if self.options.numbers_as_strings {
+            return Ok(ToonValue::String(num_str));
+        }
+        
+        // Parse the number: only integer literals that fit in i64 become
+        // Integer, everything else (including out-of-range integers) is f64
         if has_decimal || has_exponent {
-            num_str.parse::<f64>()
-                .map(ToonValue::Number)
-                .map_err(|e| ToonError::Deserialization(e.to_string()))
+            let n = num_str.parse::<f64>()
+                .map_err(|e| ToonError::Deserialization(e.to_string()))?;
+            
+            // Reject literals that overflowed to infinity or underflowed to zero
+            let mantissa = num_str.split(['e', 'E']).next().unwrap_or_default();
+            let underflow = n == 0.0 && mantissa.contains(|c: char| ('1'..='9').contains(&c));
+            if (n.is_infinite() || underflow) && !self.options.allow_non_finite {
+                return Err(ToonError::Deserialization(format!(
+                    "number out of range at line {}, column {}",
+                    line, col
+                )));
+            }
+            
+            Ok(ToonValue::Number(n))
         } else {
             num_str.parse::<i64>()
-                .map(|n| ToonValue::Number(n as f64))
+                .map(ToonValue::Integer)
                 .or_else(|_| {
                     num_str.parse::<f64>()
                         .map(ToonValue::Number)
@@ -406,6 +1569,9 @@ impl<'a> Parser<'a> {
         }
         
         // Check for reserved keywords
+        if self.options.bare_keywords == BareKeywords::AsString {
+            return Ok(ToonValue::String(ident));
+        }
         match ident.as_str() {
             "true" => Ok(ToonValue::Bool(true)),
             "false" => Ok(ToonValue::Bool(false)),
@@ -439,8 +1605,9 @@ mod tests {
         assert_eq!(decode("null").unwrap(), ToonValue::Null);
         assert_eq!(decode("true").unwrap(), ToonValue::Bool(true));
         assert_eq!(decode("false").unwrap(), ToonValue::Bool(false));
-        assert_eq!(decode("42").unwrap(), ToonValue::Number(42.0));
-        assert_eq!(decode("3.14").unwrap(), ToonValue::Number(3.14));
+        assert_eq!(decode("42").unwrap(), ToonValue::Integer(42));
+        assert_eq!(decode("3.0").unwrap(), ToonValue::Number(3.0));
+        assert_eq!(decode("2.5").unwrap(), ToonValue::Number(2.5));
         assert_eq!(
             decode("\"hello\"").unwrap(),
             ToonValue::String("hello".to_string())
@@ -454,9 +1621,9 @@ mod tests {
         assert_eq!(
             decode("[1, 2, 3]").unwrap(),
             ToonValue::Array(vec![
-                ToonValue::Number(1.0),
-                ToonValue::Number(2.0),
-                ToonValue::Number(3.0),
+                ToonValue::Integer(1),
+                ToonValue::Integer(2),
+                ToonValue::Integer(3),
             ])
         );
         
@@ -475,8 +1642,8 @@ mod tests {
         assert_eq!(decode("{}").unwrap(), ToonValue::Object(HashMap::new()));
         
         let mut expected = HashMap::new();
-        expected.insert("a".to_string(), ToonValue::Number(1.0));
-        expected.insert("b".to_string(), ToonValue::Number(2.0));
+        expected.insert("a".to_string(), ToonValue::Integer(1));
+        expected.insert("b".to_string(), ToonValue::Integer(2));
         
         let result = decode("{\"a\": 1, \"b\": 2}").unwrap();
         assert_eq!(result, ToonValue::Object(expected.clone()));
@@ -503,7 +1670,7 @@ mod tests {
         
         if let Ok(ToonValue::Object(obj)) = result {
             assert_eq!(obj.get("name"), Some(&ToonValue::String("John".to_string())));
-            assert_eq!(obj.get("age"), Some(&ToonValue::Number(30.0)));
+            assert_eq!(obj.get("age"), Some(&ToonValue::Integer(30)));
             
             if let Some(ToonValue::Object(address)) = obj.get("address") {
                 assert_eq!(
@@ -530,4 +1697,781 @@ mod tests {
             panic!("Expected root to be an object");
         }
     }
+    
+    #[test]
+    fn test_stream_decoder_byte_at_a_time() {
+        let input = "{\"name\": \"Jo\\\"hn [x]\", \"scores\": [12345, -6.75e2], \"nested\": {\"ok\": true}}";
+        let mut decoder = StreamDecoder::new();
+        let mut result = None;
+        
+        for (i, c) in input.char_indices() {
+            decoder.feed(&c.to_string());
+            let value = decoder.try_finish().unwrap();
+            if i + c.len_utf8() < input.len() {
+                assert!(value.is_none(), "document completed early at byte {}", i);
+            } else {
+                result = value;
+            }
+        }
+        
+        assert_eq!(result, Some(decode(input).unwrap()));
+    }
+    
+    #[test]
+    fn test_stream_decoder_comments_and_braceless_objects() {
+        // Fed a byte at a time, collecting each document as it completes
+        let stream = |input: &str| {
+            let mut decoder = StreamDecoder::new();
+            let mut values = Vec::new();
+            for c in input.chars() {
+                decoder.feed(&c.to_string());
+                values.extend(decoder.try_finish().unwrap());
+            }
+            values.extend(decoder.finish().unwrap());
+            values
+        };
+        
+        // Comments before and between documents are not data
+        let values = stream("# hello\n{a: 1}\n# another\n[2] # after\n");
+        assert_eq!(values, vec![decode("{a: 1}").unwrap(), decode("[2]").unwrap()]);
+        
+        // Braceless objects run to a `---` line, or to the end of the stream
+        let input = "# users\na: 1\nitems: [id]\n1\n2\n---\n---\n\"c d\": \"x # y\"\nb: [1,\n  2]";
+        assert_eq!(
+            stream(input),
+            vec![
+                decode("{a: 1, items: [{id: 1}, {id: 2}]}").unwrap(),
+                decode("{\"c d\": \"x # y\", b: [1, 2]}").unwrap(),
+            ]
+        );
+        
+        // An entry isn't complete before the end of the stream
+        let mut decoder = StreamDecoder::new();
+        decoder.feed("a: 1\n");
+        assert_eq!(decoder.try_finish().unwrap(), None);
+        decoder.feed("---");
+        assert_eq!(decoder.try_finish().unwrap(), None);
+        decoder.feed("\n");
+        assert_eq!(decoder.try_finish().unwrap(), Some(decode("{a: 1}").unwrap()));
+        
+        // A final scalar needs no trailing whitespace once the stream ends
+        decoder.feed("42");
+        assert_eq!(decoder.try_finish().unwrap(), None);
+        assert_eq!(decoder.finish().unwrap(), Some(ToonValue::Integer(42)));
+        decoder.feed(" # only a comment");
+        assert_eq!(decoder.finish().unwrap(), None);
+        decoder.feed("[1] [2");
+        assert!(decoder.try_finish().unwrap().is_some());
+        assert!(decoder.finish().is_err());
+        assert_eq!(decoder.finish().unwrap(), None);
+    }
+    
+    #[test]
+    fn test_stream_decoder_chunk_boundaries() {
+        let mut decoder = StreamDecoder::new();
+        
+        // Split mid-number: "12" must not be yielded before "34" arrives
+        decoder.feed("12");
+        assert_eq!(decoder.try_finish().unwrap(), None);
+        decoder.feed("34 ");
+        assert_eq!(decoder.try_finish().unwrap(), Some(ToonValue::Integer(1234)));
+        
+        // Split mid-string, followed by a second document in the same chunk
+        decoder.feed("\"hel");
+        assert_eq!(decoder.try_finish().unwrap(), None);
+        decoder.feed("lo\" [1, 2]");
+        assert_eq!(
+            decoder.try_finish().unwrap(),
+            Some(ToonValue::String("hello".to_string()))
+        );
+        assert_eq!(
+            decoder.try_finish().unwrap(),
+            Some(ToonValue::Array(vec![ToonValue::Integer(1), ToonValue::Integer(2)]))
+        );
+        assert_eq!(decoder.try_finish().unwrap(), None);
+        
+        // Complete but malformed documents surface the decode error once,
+        // and the stream carries on with the next document
+        decoder.feed("[1 2]");
+        assert!(decoder.try_finish().is_err());
+        decoder.feed("{a: ] ");
+        decoder.feed("{b: 1} ");
+        let err = decoder.try_finish().unwrap_err().to_string();
+        assert!(err.contains("Unexpected character ']'"), "{}", err);
+        assert_eq!(decoder.try_finish().unwrap(), Some(decode("{b: 1}").unwrap()));
+        assert_eq!(decoder.try_finish().unwrap(), None);
+    }
+    
+    #[test]
+    fn test_decode_bytes() {
+        assert_eq!(
+            decode_bytes(b"[1, 2]").unwrap(),
+            ToonValue::Array(vec![ToonValue::Integer(1), ToonValue::Integer(2)])
+        );
+        
+        // 0xC3 starts a two-byte sequence but 0x28 is not a continuation byte
+        let err = decode_bytes(b"[\"ab\xc3\x28\"]").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid TOON format: invalid UTF-8 at byte 4");
+    }
+    
+    #[test]
+    fn test_plus_sign() {
+        let lenient = DecodeOptions::new().allow_plus_sign(true);
+        assert_eq!(decode_with_options("+5", &lenient).unwrap(), ToonValue::Integer(5));
+        assert_eq!(decode_with_options("+2.5e1", &lenient).unwrap(), ToonValue::Number(25.0));
+        assert_eq!(
+            decode_with_options("[+1, -1]", &lenient).unwrap(),
+            ToonValue::Array(vec![ToonValue::Integer(1), ToonValue::Integer(-1)])
+        );
+        
+        // A sign must be followed by a digit
+        for (input, message) in [
+            ("+", "Expected digit after '+' at line 1, column 2"),
+            ("+-5", "Expected digit after '+' at line 1, column 2"),
+            ("[1, +]", "Expected digit after '+' at line 1, column 6"),
+            ("[-]", "Expected digit after '-' at line 1, column 3"),
+        ] {
+            let err = decode_with_options(input, &lenient).unwrap_err();
+            assert_eq!(err.to_string(), format!("Invalid TOON format: {}", message), "{:?}", input);
+        }
+        let bare = lenient.allow_bare_decimals(true);
+        assert_eq!(decode_with_options("+.5", &bare).unwrap(), ToonValue::Number(0.5));
+        
+        // Strict mode rejects the sign with a positioned error
+        let err = decode("[1, +5]").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid TOON format: Unexpected character '+' at line 1, column 5"
+        );
+    }
+    
+    #[test]
+    fn test_allow_trailing_comma() {
+        let options = DecodeOptions::new().allow_trailing_comma(true);
+        let value = decode_with_options("[\n  {a: 1, b: [2, 3,],},\n  x,\n]", &options).unwrap();
+        assert_eq!(value, decode("[{a: 1, b: [2, 3]}, x]").unwrap());
+        assert_eq!(decode_with_options("[1, # last\n]", &options).unwrap(), decode("[1]").unwrap());
+        
+        // Only one comma, and only after an element
+        assert!(decode_with_options("[1,,]", &options).is_err());
+        assert!(decode_with_options("[,]", &options).is_err());
+        assert!(decode("[1,]").is_err());
+    }
+    
+    #[test]
+    fn test_newline_as_separator() {
+        let options = DecodeOptions::new().newline_as_separator(true);
+        let input = "[\n  \"red\"\n  \"green\"\n  blue\n]";
+        let strings = |items: &[&str]| {
+            ToonValue::Array(items.iter().map(|s| ToonValue::String(s.to_string())).collect())
+        };
+        
+        assert_eq!(
+            decode_with_options(input, &options).unwrap(),
+            strings(&["red", "green", "blue"])
+        );
+        assert!(decode(input).is_err());
+        
+        // Commas still work, mixed with newlines and nested structures
+        let input = "[\n  [1, 2],\n  {a: 1, b: [3\n 4]}\n  x, y\n]";
+        let mut obj = HashMap::new();
+        obj.insert("a".to_string(), ToonValue::Integer(1));
+        obj.insert(
+            "b".to_string(),
+            ToonValue::Array(vec![ToonValue::Integer(3), ToonValue::Integer(4)]),
+        );
+        assert_eq!(
+            decode_with_options(input, &options).unwrap(),
+            ToonValue::Array(vec![
+                ToonValue::Array(vec![ToonValue::Integer(1), ToonValue::Integer(2)]),
+                ToonValue::Object(obj),
+                ToonValue::String("x".to_string()),
+                ToonValue::String("y".to_string()),
+            ])
+        );
+        
+        // Items on the same line still need a comma
+        assert!(decode_with_options("[1 2]", &options).is_err());
+    }
+    
+    #[test]
+    fn test_number_out_of_range() {
+        let err = decode("[1, 1e400]").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Deserialization error: number out of range at line 1, column 5"
+        );
+        assert!(decode("-1e400").is_err());
+        assert!(decode("1e-400").is_err());
+        assert!(decode("2.5e-400").is_err());
+        
+        // Genuine zeros and representable extremes are fine
+        assert_eq!(decode("0.0e-400").unwrap(), ToonValue::Number(0.0));
+        assert_eq!(decode("1e308").unwrap(), ToonValue::Number(1e308));
+        assert_eq!(decode("5e-324").unwrap(), ToonValue::Number(5e-324));
+        
+        let options = DecodeOptions::new().allow_non_finite(true);
+        assert_eq!(
+            decode_with_options("1e400", &options).unwrap(),
+            ToonValue::Number(f64::INFINITY)
+        );
+        assert_eq!(
+            decode_with_options("1e-400", &options).unwrap(),
+            ToonValue::Number(0.0)
+        );
+    }
+    
+    #[test]
+    fn test_parse_string_escapes() {
+        let s = |v: &str| ToonValue::String(v.to_string());
+        assert_eq!(decode(r#""back\\slash""#).unwrap(), s("back\\slash"));
+        assert_eq!(decode(r#""a\/b""#).unwrap(), s("a/b"));
+        assert_eq!(decode(r#""\u0041B""#).unwrap(), s("AB"));
+        assert_eq!(decode(r#""tab\there\0""#).unwrap(), s("tab\there\0"));
+        
+        let err = decode(r#"[1, "bad\qescape"]"#).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid TOON format: Invalid escape sequence in string starting at line 1, column 5"
+        );
+    }
+    
+    #[test]
+    fn test_decode_lenient() {
+        let (value, errors) = decode_lenient("{a: 1, b: @, c: \"x, }\", d: [1 2], e: 5}");
+        
+        let mut expected = HashMap::new();
+        expected.insert("a".to_string(), ToonValue::Integer(1));
+        expected.insert("c".to_string(), ToonValue::String("x, }".to_string()));
+        expected.insert("d".to_string(), ToonValue::Array(vec![ToonValue::Integer(1)]));
+        expected.insert("e".to_string(), ToonValue::Integer(5));
+        assert_eq!(value, Some(ToonValue::Object(expected)));
+        
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].to_string().contains("Unexpected character '@' at line 1, column 11"));
+        assert!(errors[1].to_string().contains("Expected ',' or ']' at line 1, column 31"));
+        
+        // Valid input reports no errors
+        let (value, errors) = decode_lenient("[1, 2]");
+        assert_eq!(value, Some(decode("[1, 2]").unwrap()));
+        assert!(errors.is_empty());
+        
+        // Unterminated containers report end of input once
+        let (value, errors) = decode_lenient("{a: [1, {b: 2");
+        assert!(value.is_some());
+        assert_eq!(errors.len(), 1);
+        
+        let (value, errors) = decode_lenient("");
+        assert_eq!(value, None);
+        assert_eq!(errors.len(), 1);
+    }
+    
+    #[cfg(feature = "decode-debug")]
+    #[test]
+    fn test_decode_debug() {
+        let (result, trace) = decode_debug("{id: 1,\n tags: [a, \"b c\"]}");
+        assert_eq!(result.unwrap(), decode("{id: 1, tags: [a, \"b c\"]}").unwrap());
+        assert_eq!(
+            trace,
+            [
+                "entered object at line 1, column 1",
+                "parsed key \"id\" at line 1, column 2",
+                "parsed 1 at line 1, column 6",
+                "parsed key \"tags\" at line 2, column 2",
+                "entered array at line 2, column 8",
+                "parsed \"a\" at line 2, column 9",
+                "parsed \"b c\" at line 2, column 12",
+                "left array with 2 elements",
+                "left object with 2 entries",
+            ]
+        );
+        
+        let (result, trace) = decode_debug("[1, @]");
+        assert!(result.is_err());
+        assert_eq!(trace.last().unwrap(), "failed at line 1, column 5: Invalid TOON format: Unexpected character '@' at line 1, column 5");
+    }
+    
+    #[test]
+    fn test_decode_truncated() {
+        assert_eq!(decode_truncated("{a: 1, b: [1, 2").unwrap(), decode("{a: 1, b: [1, 2]}").unwrap());
+        assert_eq!(decode_truncated("[1, 2,").unwrap(), decode("[1, 2]").unwrap());
+        assert_eq!(decode_truncated("{").unwrap(), decode("{}").unwrap());
+        
+        // Elements cut off part way through are dropped
+        let cases = [
+            ("{a: {b: [1, {c: tr", "{a: {b: [1, {}]}}"),
+            ("{a: 1, name", "{a: 1}"),
+            ("{a: 1, b: ", "{a: 1}"),
+            ("[x, 1.", "[x]"),
+            ("{rows: [\n  {id: 1},\n  {id: 2, tags: [a, b", "{rows: [{id: 1}, {id: 2, tags: [a, b]}]}"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(decode_truncated(input).unwrap(), decode(expected).unwrap(), "{}", input);
+        }
+        
+        // Complete input decodes as usual, and other errors still fail
+        assert_eq!(decode_truncated("{a: [1]}").unwrap(), decode("{a: [1]}").unwrap());
+        assert!(decode_truncated("{a: @, b: [1").is_err());
+        assert!(decode_truncated("[1 2").is_err());
+        assert!(decode_truncated("  ").is_err());
+    }
+    
+    #[test]
+    fn test_decode_multi() {
+        let input = "{id: 1, msg: \"start\"}\n---\n{id: 2, msg: \"multi\n---\nline\"}\n---\r\n[1, 2]\n";
+        let docs = decode_multi(input).unwrap();
+        
+        assert_eq!(docs.len(), 3);
+        assert_eq!(docs[0]["id"], ToonValue::Integer(1));
+        assert_eq!(docs[1]["msg"], ToonValue::String("multi\n---\nline".to_string()));
+        assert_eq!(docs[2], decode("[1, 2]").unwrap());
+        
+        // A leading separator and a single document both work
+        assert_eq!(decode_multi("---\n42").unwrap(), vec![ToonValue::Integer(42)]);
+        assert_eq!(decode_multi("").unwrap(), vec![]);
+        
+        // Braceless objects, as `encode` writes them, are read whole
+        let docs = decode_multi("a: 1\nb: 2\n---\nitems: [id]\n1\n").unwrap();
+        assert_eq!(docs, vec![decode("{a: 1, b: 2}").unwrap(), decode("{items: [{id: 1}]}").unwrap()]);
+        
+        // Errors in any document are reported, including trailing input
+        assert!(decode_multi("1\n---\n[1 2]").is_err());
+        let err = decode_multi("a: 1\n---\n[1] junk").unwrap_err().to_string();
+        assert!(err.contains("Unexpected character 'j' after the end of the document at line 1, column 5"), "{}", err);
+    }
+    
+    #[test]
+    fn test_decode_comments() {
+        let value = decode("{a: 1 # first\n b: 2}").unwrap();
+        assert_eq!(value["a"], ToonValue::Integer(1));
+        assert_eq!(value["b"], ToonValue::Integer(2));
+        
+        // Full-line and trailing comments, including before commas and closers
+        let value = decode("# header\n[1, # one\n 2 # two\n , x#three\n]").unwrap();
+        assert_eq!(decode("[1, 2, x]").unwrap(), value);
+        
+        // A '#' inside a quoted string is not a comment
+        let value = decode("{tag: \"#rust\" # note, with \"quotes\"\n}").unwrap();
+        assert_eq!(value["tag"], ToonValue::String("#rust".to_string()));
+        
+        // Comments also don't confuse document splitting
+        let docs = decode_multi("1 # \"\n---\n2").unwrap();
+        assert_eq!(docs, vec![ToonValue::Integer(1), ToonValue::Integer(2)]);
+    }
+    
+    #[test]
+    fn test_decode_json() {
+        let value = decode_json("{\"a\": [1, 2.5, -3e2], \"b\": {\"c\": null, \"d\": \"#x\"}}").unwrap();
+        assert_eq!(value, decode("{a: [1, 2.5, -300.0], b: {c: null, d: \"#x\"}}").unwrap());
+        
+        assert!(matches!(decode_json("{a: 1}"), Err(ToonError::Deserialization(_))));
+    }
+    
+    #[test]
+    fn test_decode_nesting_limit() {
+        let ok = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert_eq!(decode(&ok).unwrap().depth(), MAX_DEPTH);
+        
+        // Deeper input is an error rather than a stack overflow
+        for open in ["[", "{a: "] {
+            let deep = open.repeat(100_000);
+            let err = decode(&deep).unwrap_err().to_string();
+            assert!(err.contains("Nesting deeper than 128 levels"), "{}", err);
+            let (_, errors) = decode_lenient(&deep);
+            assert!(!errors.is_empty());
+        }
+    }
+    
+    #[test]
+    fn test_decode_dispatch_paths() {
+        // Each opener reached from the top level, an array item, an object
+        // value and (for strings) an object key
+        for input in ["{}", "[]", "\"s\"", "[{}, [], \"s\"]", "{a: {}, b: [], c: \"s\"}", "{\"k\": 1}"] {
+            assert!(decode(input).is_ok(), "{}", input);
+        }
+        
+        // Mismatched or missing delimiters are errors, not panics
+        for input in ["{]", "[}", "{\"k\" 1}", "[\"a\" \"b\"]", "}", "]", "{", "[", "\""] {
+            let _ = decode(input);
+            let _ = decode_lenient(input);
+        }
+        assert!(decode("}").is_err());
+        assert!(decode("[}").is_err());
+    }
+    
+    #[test]
+    fn test_from_csv() {
+        let input = "id,name,note,active\r\n1,Ann,\"Hello, world\",true\n2,\"Bob \"\"B\"\"\",,false\n3,\"007\",\"two\nlines\",null\n";
+        let value = from_csv(input, true).unwrap();
+        let rows = value.as_array().unwrap();
+        
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0]["id"], ToonValue::Integer(1));
+        assert_eq!(rows[0]["note"], ToonValue::String("Hello, world".to_string()));
+        assert_eq!(rows[0]["active"], ToonValue::Bool(true));
+        assert_eq!(rows[1]["name"], ToonValue::String("Bob \"B\"".to_string()));
+        assert_eq!(rows[1]["note"], ToonValue::Null);
+        assert_eq!(rows[2]["name"], ToonValue::String("007".to_string()));
+        assert_eq!(rows[2]["note"], ToonValue::String("two\nlines".to_string()));
+        
+        let value = from_csv("1.5,x\n-2,\"a,b\"", false).unwrap();
+        assert_eq!(value, decode("[[1.5, x], [-2, \"a,b\"]]").unwrap());
+        
+        // Zero-padded numbers are IDs, not numbers
+        let value = from_csv("00042,-007,0,0.5,00.5,+01", false).unwrap();
+        assert_eq!(value, decode("[[\"00042\", \"-007\", 0, 0.5, \"00.5\", \"+01\"]]").unwrap());
+        
+        // Blank lines, trailing ones included, hold no rows, unlike `""`
+        let value = from_csv("a,b\n1,2\n\n3,4\r\n\r\n\n", true).unwrap();
+        assert_eq!(value, decode("[{a: 1, b: 2}, {a: 3, b: 4}]").unwrap());
+        assert_eq!(from_csv("a\n\n\"\"\n", true).unwrap(), decode("[{a: \"\"}]").unwrap());
+        
+        assert_eq!(from_csv("", true).unwrap(), ToonValue::Array(vec![]));
+        assert!(from_csv("a,b\n1\n", true).is_err());
+        assert!(from_csv("\"open", false).is_err());
+    }
+    
+    #[test]
+    fn test_decode_numbers_as_strings() {
+        let options = DecodeOptions::new().numbers_as_strings(true);
+        let literal = "123.456789012345678";
+        
+        let value = decode_with_options(&format!("{{price: {}, qty: -3, big: 1.5E+300}}", literal), &options).unwrap();
+        assert_eq!(value["price"], ToonValue::String(literal.to_string()));
+        assert_eq!(value["qty"], ToonValue::String("-3".to_string()));
+        assert_eq!(value["big"], ToonValue::String("1.5E+300".to_string()));
+        
+        // Without the flag the digits are lost to f64 rounding
+        let lossy = decode(literal).unwrap();
+        assert_ne!(crate::utils::format_number(lossy.as_number().unwrap()), literal);
+        
+        // The encoder writes the string back quoted, keeping every digit
+        let encoded = crate::encoder::encode(&value["price"]).unwrap();
+        assert_eq!(encoded, format!("\"{}\"", literal));
+        assert_eq!(decode(&encoded).unwrap(), ToonValue::String(literal.to_string()));
+    }
+    
+    #[test]
+    fn test_decode_bare_decimals() {
+        let options = DecodeOptions::new().allow_bare_decimals(true);
+        let value = decode_with_options("[.5, 5., -.25, -3., 1.5]", &options).unwrap();
+        assert_eq!(
+            value,
+            ToonValue::Array(vec![
+                ToonValue::Number(0.5),
+                ToonValue::Number(5.0),
+                ToonValue::Number(-0.25),
+                ToonValue::Number(-3.0),
+                ToonValue::Number(1.5),
+            ])
+        );
+        
+        // A lone point or sign is still not a number
+        assert!(decode_with_options("[-.]", &options).is_err());
+        assert!(decode_with_options(".", &options).is_err());
+        
+        // Strict mode rejects both forms with positioned errors
+        let err = decode("[1, -.5]").unwrap_err().to_string();
+        assert!(err.contains("Expected digit before decimal point at line 1, column 6"), "{}", err);
+        let err = decode("{a: 5.}").unwrap_err().to_string();
+        assert!(err.contains("Expected digit after decimal point at line 1, column 7"), "{}", err);
+        assert!(decode(".5").is_err());
+    }
+    
+    #[test]
+    fn test_decode_into() {
+        let mut scratch = ToonValue::Null;
+        for input in [
+            "{users: [{id: 1, tags: [a, b]}, {id: 2, tags: []}], page: 1}",
+            "{users: [{id: 3, tags: [c]}], next: null}",
+            "{users: [{id: 4, tags: {x: 1}}, {id: 5}, {id: 6, tags: [d, e, g]}]}",
+            "[1, 2]",
+            "{}",
+        ] {
+            decode_into(input, &mut scratch).unwrap();
+            assert_eq!(scratch, decode(input).unwrap(), "{}", input);
+        }
+        
+        // Containers of the same shape keep their allocations
+        let mut scratch = decode("{rows: [[1, 2, 3], [4]]}").unwrap();
+        let row_ptr = |value: &ToonValue| value.pointer("rows[0]").and_then(ToonValue::as_array).unwrap().as_ptr();
+        let before = row_ptr(&scratch);
+        decode_into("{rows: [[7, 8], [9]]}", &mut scratch).unwrap();
+        assert_eq!(row_ptr(&scratch), before);
+        assert_eq!(scratch, decode("{rows: [[7, 8], [9]]}").unwrap());
+        
+        // Objects are refilled in place too, keeping their keys
+        let mut scratch = decode("[{id: 1, tags: [a]}, {id: 2}]").unwrap();
+        let key_ptr = |value: &ToonValue, path: &str| {
+            let obj = value.pointer(path).and_then(ToonValue::as_object).unwrap();
+            obj.get_key_value("id").unwrap().0.as_ptr()
+        };
+        let (first, second) = (key_ptr(&scratch, "[0]"), key_ptr(&scratch, "[1]"));
+        decode_into("[{tags: [b], id: 3}, {id: 4, extra: 5}]", &mut scratch).unwrap();
+        assert_eq!((key_ptr(&scratch, "[0]"), key_ptr(&scratch, "[1]")), (first, second));
+        assert_eq!(scratch, decode("[{tags: [b], id: 3}, {id: 4, extra: 5}]").unwrap());
+        decode_into("[{id: 6}, {extra: 7}]", &mut scratch).unwrap();
+        assert_eq!(scratch, decode("[{id: 6}, {extra: 7}]").unwrap());
+        
+        assert!(decode_into("[1, 2", &mut scratch).is_err());
+    }
+    
+    /// Compares `decode_into` against `decode` on a repeated-shape workload;
+    /// run with `cargo test --release -- --ignored --nocapture bench_decode_into`
+    #[test]
+    #[ignore]
+    fn bench_decode_into() {
+        use std::time::Instant;
+        
+        let row = "{id: 1, name: Alice, tags: [a, b, c], scores: [1.5, 2.5]}";
+        let input = format!("[{}]", vec![row; 1000].join(", "));
+        let iterations = 200;
+        
+        let start = Instant::now();
+        for _ in 0..iterations {
+            std::hint::black_box(decode(&input).unwrap());
+        }
+        let plain = start.elapsed();
+        
+        let mut scratch = ToonValue::Null;
+        let start = Instant::now();
+        for _ in 0..iterations {
+            decode_into(&input, &mut scratch).unwrap();
+            std::hint::black_box(&scratch);
+        }
+        let reused = start.elapsed();
+        
+        println!("decode: {:?}, decode_into: {:?}", plain / iterations, reused / iterations);
+    }
+    
+    #[test]
+    fn test_allow_equals_separator() {
+        let options = DecodeOptions::new().allow_equals_separator(true);
+        let value = decode_with_options("{a = 1, b: 2, c=x}", &options).unwrap();
+        assert_eq!(value, decode("{a: 1, b: 2, c: x}").unwrap());
+        
+        let err = decode("{a = 1}").unwrap_err().to_string();
+        assert!(err.contains("Expected ':' after key at line 1, column 4, found '='"), "{}", err);
+    }
+    
+    #[test]
+    fn test_heredoc() {
+        let options = DecodeOptions::new().allow_heredoc(true);
+        let input = "{body: <<END\nfirst line\n  indented, \"quoted\" # not a comment\n\nEND\n, n: 1}";
+        let value = decode_with_options(input, &options).unwrap();
+        assert_eq!(
+            value["body"],
+            ToonValue::String("first line\n  indented, \"quoted\" # not a comment\n".to_string())
+        );
+        assert_eq!(value["n"], ToonValue::Integer(1));
+        
+        // The delimiter ends the block only as a whole word at a line start,
+        // and may be indented and followed by more input on its line
+        let input = "[<<EOF\nnot EOF here\nEOFS\n  EOF, x, <<EOF\nEOF]";
+        let value = decode_with_options(input, &options).unwrap();
+        assert_eq!(value[0], ToonValue::String("not EOF here\nEOFS".to_string()));
+        assert_eq!(value[2], ToonValue::String(String::new()));
+        
+        // CRLF input keeps its line breaks inside the block
+        let value = decode_with_options("[<<END\r\na\r\nb\r\nEND\r\n]", &options).unwrap();
+        assert_eq!(value[0], ToonValue::String("a\r\nb".to_string()));
+        
+        let err = decode_with_options("[<<END\nno end\n]", &options).unwrap_err().to_string();
+        assert!(err.contains("Unterminated heredoc 'END' starting at line 1, column 2"), "{}", err);
+        let err = decode_with_options("[<<END x\nEND]", &options).unwrap_err().to_string();
+        assert!(err.contains("Expected a line break after heredoc delimiter 'END' at line 1, column 8"), "{}", err);
+        let err = decode_with_options("[<< END\nEND]", &options).unwrap_err().to_string();
+        assert!(err.contains("Expected a heredoc delimiter after '<<' at line 1, column 2"), "{}", err);
+        
+        // Off by default
+        assert!(decode("[<<END\na\nEND]").is_err());
+    }
+    
+    #[test]
+    fn test_max_key_and_string_len() {
+        let options = DecodeOptions::new().max_key_len(Some(4));
+        assert!(decode_with_options("{name: abcdefgh, \"ok\": 1}", &options).is_ok());
+        let err = decode_with_options("{id: 1,\n \"username\": x}", &options).unwrap_err().to_string();
+        assert!(err.contains("Key of 8 characters exceeds the limit of 4 at line 2, column 2"), "{}", err);
+        assert!(decode_with_options("{address: x}", &options).is_err());
+        
+        let options = DecodeOptions::new().max_string_len(Some(3));
+        assert!(decode_with_options("{long_key: abc}", &options).is_ok());
+        let err = decode_with_options("[abc, \"abcd\"]", &options).unwrap_err().to_string();
+        assert!(err.contains("String of 4 characters exceeds the limit of 3 at line 1, column 7"), "{}", err);
+    }
+    
+    #[test]
+    fn test_line_endings() {
+        // `\r\n` counts as one line break, and may be mixed with `\n`
+        for input in ["{a: 1,\r\nb: 2,\r\nc: ?}", "{a: 1,\nb: 2,\r\nc: ?}", "{a: 1,\r\nb: 2,\nc: ?}"] {
+            let err = decode(input).unwrap_err().to_string();
+            assert!(err.contains("Unexpected character '?' at line 3, column 4"), "{:?}: {}", input, err);
+        }
+        
+        let value = decode("{a: 1\r\nb: [x,\r\n y]\r\n}").unwrap();
+        assert_eq!(value, decode("{a: 1, b: [x, y]}").unwrap());
+        let rows = decode_tabular("[id, name]\r\n1, Ann\r\n2, Bob\r\n").unwrap();
+        assert_eq!(rows, decode("[{id: 1, name: Ann}, {id: 2, name: Bob}]").unwrap());
+        
+        let err = decode_tabular("[a, b]\r\n1, 2\r\n3, 4, 5").unwrap_err().to_string();
+        assert!(err.contains("at line 3, column 7"), "{}", err);
+    }
+    
+    #[test]
+    fn test_number_range() {
+        let options = DecodeOptions::new().number_range(Some((0.0, 1000.0)));
+        let value = decode_with_options("{qty: 5, price: 999.5, min: 0, max: 1e3}", &options).unwrap();
+        assert_eq!(value, decode("{qty: 5, price: 999.5, min: 0, max: 1e3}").unwrap());
+        
+        let err = decode_with_options("{qty: 5,\n refund: -2.5}", &options).unwrap_err().to_string();
+        assert!(err.contains("number -2.5 outside the allowed range 0 to 1000 at line 2, column 10"), "{}", err);
+        let err = decode_with_options("[1, 1001]", &options).unwrap_err().to_string();
+        assert!(err.contains("number 1001 outside"), "{}", err);
+        
+        // The range applies to the value, however it is returned
+        let as_strings = options.numbers_as_strings(true);
+        assert!(decode_with_options("[2e3]", &as_strings).is_err());
+        assert_eq!(decode_with_options("7", &as_strings).unwrap(), ToonValue::String("7".to_string()));
+        
+        // Strings holding digits are not numbers
+        assert!(decode_with_options("{id: \"-1\"}", &options).is_ok());
+    }
+    
+    #[test]
+    fn test_bare_keywords() {
+        let input = "{a: true, b: false, c: null, d: [null, \"true\"], null: 1}";
+        let typed = decode(input).unwrap();
+        assert_eq!(typed, decode_with_options(input, &DecodeOptions::new().bare_keywords(BareKeywords::AsTyped)).unwrap());
+        assert_eq!(typed.pointer("a"), Some(&ToonValue::Bool(true)));
+        assert_eq!(typed.pointer("c"), Some(&ToonValue::Null));
+        assert_eq!(typed.pointer("d/1"), Some(&ToonValue::String("true".to_string())));
+        
+        let options = DecodeOptions::new().bare_keywords(BareKeywords::AsString);
+        let strings = decode_with_options(input, &options).unwrap();
+        let s = |v: &str| Some(ToonValue::String(v.to_string()));
+        assert_eq!(strings.pointer("a"), s("true").as_ref());
+        assert_eq!(strings.pointer("b"), s("false").as_ref());
+        assert_eq!(strings.pointer("c"), s("null").as_ref());
+        assert_eq!(strings.pointer("d/0"), s("null").as_ref());
+        assert_eq!(strings.pointer("null"), Some(&ToonValue::Integer(1)));
+        assert_eq!(decode_with_options("null", &options).unwrap(), ToonValue::String("null".to_string()));
+        
+        // Other words starting like a keyword are plain strings in this mode
+        assert_eq!(decode_with_options("[name, truth]", &options).unwrap(), decode("[\"name\", \"truth\"]").unwrap());
+    }
+    
+    #[test]
+    fn test_empty_input() {
+        for input in ["", "   ", "\n\t\r\n", "# comment\n", "  # one\n# two"] {
+            match decode(input) {
+                Err(ToonError::InvalidFormat(msg)) => assert_eq!(msg, "empty input", "{:?}", input),
+                other => panic!("expected empty input error for {:?}, got {:?}", input, other),
+            }
+        }
+        let mut scratch = ToonValue::Null;
+        assert!(matches!(
+            decode_into(" # nothing", &mut scratch),
+            Err(ToonError::InvalidFormat(msg)) if msg == "empty input"
+        ));
+        
+        // A truncated document is still reported as such, not as empty
+        let err = decode("{a: ").unwrap_err().to_string();
+        assert!(!err.contains("empty input"), "{}", err);
+        assert_eq!(decode("# header\n42").unwrap(), ToonValue::Integer(42));
+    }
+    
+    #[test]
+    fn test_missing_colon_errors() {
+        let err = decode("{a").unwrap_err().to_string();
+        assert!(err.contains("Unexpected end of input, expected ':' after key 'a'"), "{}", err);
+        let err = decode("{a x}").unwrap_err().to_string();
+        assert!(err.contains("Expected ':' after key at line 1, column 4, found 'x'"), "{}", err);
+    }
+    
+    #[test]
+    fn test_decode_document() {
+        use crate::encoder::encode_with_key_order;
+        use crate::types::{EncodeOptions, KeyOrder};
+        
+        // Everything `encode` writes at the top level reads back
+        for input in [
+            "{items: [{id: 1, label: A}, {id: 2, label: B}], x: {y: [1, [2, 3]], z: \"q r\"}, e: [], o: {}}",
+            "[{id: 1, label: A}, {id: 2, label: B}]",
+            "{a: [[1], [2]], b: [{id: 1}, [3]], c: [a, b]}",
+            "[a, b]",
+            "{}",
+            "42",
+        ] {
+            let value = decode(input).unwrap();
+            let order = KeyOrder::sorted(&value);
+            for options in [EncodeOptions::new(), EncodeOptions::new().pretty(true)] {
+                let encoded = encode_with_key_order(&value, &options, &order).unwrap();
+                assert_eq!(decode_document(&encoded).unwrap(), value, "{:?}", encoded);
+            }
+        }
+        
+        // Tabular rows in an entry end at the next key
+        let value = decode_document("# users\nitems: [id]\n1\n\n2\nnext: 3").unwrap();
+        assert_eq!(value, decode("{items: [{id: 1}, {id: 2}], next: 3}").unwrap());
+        
+        for (input, message) in [
+            ("[1] 2", "Unexpected character '2' after the end of the document at line 1, column 5"),
+            ("a: 1 b: 2", "Expected a new line before the next entry at line 1, column 6, found 'b'"),
+            ("items: [id]\n1, 2", "Extra cell beyond the 1 header fields at line 2, column 4"),
+            ("", "empty input"),
+        ] {
+            let err = decode_document(input).unwrap_err().to_string();
+            assert!(err.contains(message), "{:?}: {}", input, err);
+        }
+    }
+    
+    #[test]
+    fn test_decode_tabular() {
+        let value = decode_tabular("[id, name]\n1, Alice\n2, \"Bob B\"\n").unwrap();
+        assert_eq!(value, decode("[{id: 1, name: Alice}, {id: 2, name: \"Bob B\"}]").unwrap());
+        
+        // Bare headers, blank lines and comments
+        let value = decode_tabular("# users\nid, name\n\n1, Alice # first\n").unwrap();
+        assert_eq!(value, decode("[{id: 1, name: Alice}]").unwrap());
+        assert_eq!(decode_tabular("[id]").unwrap(), ToonValue::Array(vec![]));
+        
+        // Short rows leave their trailing fields null
+        let value = decode_tabular("[id, name, age]\n1, Alice, 30\n2, Bob\n3").unwrap();
+        assert_eq!(
+            value,
+            decode("[{id: 1, name: Alice, age: 30}, {id: 2, name: Bob, age: null}, {id: 3, name: null, age: null}]").unwrap()
+        );
+        
+        for (input, message) in [
+            ("", "Expected field name in tabular header at line 1, column 1"),
+            ("[id, name\n1, 2", "Expected ',' or ']' in tabular header at line 1, column 10"),
+            ("[id] x\n1", "Expected end of line after tabular header at line 1, column 6"),
+            ("[a, b]\n1, 2\n3, 4, 5", "Extra cell beyond the 2 header fields at line 3, column 7"),
+            ("[a]\n1, [2, 3]", "Extra cell beyond the 1 header fields at line 2, column 4"),
+            ("[a, b]\n1, , 2", "Expected value in tabular row at line 2, column 4"),
+            ("[a]\n1 2", "Expected ',' or end of row at line 2, column 3"),
+        ] {
+            let err = decode_tabular(input).unwrap_err().to_string();
+            assert!(err.contains(message), "{:?}: {}", input, err);
+        }
+    }
+    
+    #[test]
+    fn test_decode_tabular_quoted_header_roundtrip() {
+        use crate::encoder::encode_with_options;
+        use crate::types::EncodeOptions;
+        
+        let rows = decode("[{\"first \\\"nick\\\" name\": Ann, id: 1}, {\"first \\\"nick\\\" name\": Bo, id: 2}]").unwrap();
+        let field = "first \"nick\" name";
+        assert_eq!(rows[0][field], ToonValue::String("Ann".to_string()));
+        
+        for bracketed in [true, false] {
+            let options = EncodeOptions::new().tabular_bracket_header(bracketed);
+            let encoded = encode_with_options(&rows, &options).unwrap();
+            assert!(encoded.contains("\"first \\\"nick\\\" name\""), "{}", encoded);
+            assert_eq!(encoded.starts_with('['), bracketed);
+            assert_eq!(decode_tabular(&encoded).unwrap(), rows);
+        }
+    }
 }
@@ -1,71 +1,886 @@
 //! TOON format encoder
 
+use std::borrow::Cow;
 use std::fmt::Write;
 use std::collections::HashMap;
 
-use crate::types::{ToonValue, EncodeOptions};
+use crate::types::{ToonValue, EncodeOptions, EscapeTable, KeyOrder, LineEnding, TabularFieldOrder};
 use crate::utils::{self, escape_str, format_number};
 use crate::ToonError;
 
 /// Encode a value to a TOON format string
 pub fn encode(value: &ToonValue) -> Result<String, ToonError> {
+    encode_to_string(value, &EncodeOptions::default(), &KeyOrder::None)
+}
+
+/// Encode a value with the given options
+pub fn encode_with_options(
+    value: &ToonValue,
+    options: &EncodeOptions,
+) -> Result<String, ToonError> {
+    encode_to_string(value, options, &KeyOrder::None)
+}
+
+/// Encode a value, emitting object keys in the order captured by `order`
+///
+/// Keys missing from `order` follow the ordered keys in map iteration order.
+pub fn encode_with_key_order(
+    value: &ToonValue,
+    options: &EncodeOptions,
+    order: &KeyOrder,
+) -> Result<String, ToonError> {
+    encode_to_string(value, options, order)
+}
+
+/// Encode a value, writing `# comment` lines before the keys named in
+/// `comments`
+///
+/// Comments are keyed by the path of the key they annotate. Only keys that
+/// start a line can carry one, which are those of a top-level object, so
+/// nested keys need [`EncodeOptions::flatten_keys`] and are then named by
+/// their dotted path (`"address.city"`). A comment with several lines is
+/// written as several `#` lines. A path that matches no such key is an error.
+pub fn encode_with_comments(
+    value: &ToonValue,
+    comments: &HashMap<String, String>,
+    options: &EncodeOptions,
+) -> Result<String, ToonError> {
+    encode_with_comments_ordered(value, comments, options, &KeyOrder::None)
+}
+
+/// Like [`encode_with_comments`], emitting object keys in the order captured
+/// by `order`
+pub(crate) fn encode_with_comments_ordered(
+    value: &ToonValue,
+    comments: &HashMap<String, String>,
+    options: &EncodeOptions,
+    order: &KeyOrder,
+) -> Result<String, ToonError> {
+    let mut output = String::new();
+    encode_limited(value, options, order, Some(comments), &mut output)?;
+    Ok(output)
+}
+
+/// Serialize a value to a strict JSON string
+///
+/// With `indent` set, the output is pretty-printed using that many spaces
+/// per level. Object keys are written in sorted order. NaN and infinities
+/// have no JSON representation and are rejected.
+pub fn to_json_string(value: &ToonValue, indent: Option<usize>) -> Result<String, ToonError> {
+    let mut buffer = Vec::new();
+    to_json_writer(value, indent, &mut buffer)?;
+    Ok(String::from_utf8(buffer).expect("serde_json writes UTF-8"))
+}
+
+/// Serialize a value as strict JSON to an `io::Write`, as [`to_json_string`]
+pub fn to_json_writer<W: std::io::Write>(
+    value: &ToonValue,
+    indent: Option<usize>,
+    writer: W,
+) -> Result<(), ToonError> {
+    let json = to_json_value(value)?;
+    let result = match indent {
+        None => serde_json::to_writer(writer, &json),
+        Some(width) => {
+            let indent = " ".repeat(width);
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+            let mut serializer = serde_json::Serializer::with_formatter(writer, formatter);
+            serde::Serialize::serialize(&json, &mut serializer)
+        }
+    };
+    result.map_err(|e| match e.io_error_kind() {
+        Some(_) => ToonError::Io(e.into()),
+        None => ToonError::Serialization(e.to_string()),
+    })
+}
+
+fn to_json_value(value: &ToonValue) -> Result<serde_json::Value, ToonError> {
+    Ok(match value {
+        ToonValue::Null => serde_json::Value::Null,
+        ToonValue::Bool(b) => serde_json::Value::Bool(*b),
+        ToonValue::Integer(i) => serde_json::Value::from(*i),
+        ToonValue::Number(n) => serde_json::Number::from_f64(*n)
+            .map(serde_json::Value::Number)
+            .ok_or_else(|| {
+                ToonError::Serialization(format!("{} cannot be represented in JSON", format_number(*n)))
+            })?,
+        ToonValue::String(s) => serde_json::Value::String(s.clone()),
+        ToonValue::Array(arr) => {
+            serde_json::Value::Array(arr.iter().map(to_json_value).collect::<Result<_, _>>()?)
+        }
+        ToonValue::Object(obj) => serde_json::Value::Object(
+            obj.iter()
+                .map(|(k, v)| Ok((k.clone(), to_json_value(v)?)))
+                .collect::<Result<_, ToonError>>()?,
+        ),
+    })
+}
+
+/// Export a flat object as a URL query string (`k=v&k2=v2`)
+///
+/// Keys are written in sorted order and arrays of primitives become repeated
+/// keys (`tag=a&tag=b`). Keys and values are percent-encoded, leaving only
+/// RFC 3986 unreserved characters as is; null becomes an empty value. Nested
+/// objects and arrays, or a value that isn't an object, are rejected.
+pub fn to_query_string(value: &ToonValue) -> Result<String, ToonError> {
+    if value.as_object().is_none() {
+        return Err(ToonError::TypeError(
+            "query strings can only be built from an object".to_string(),
+        ));
+    }
+    
+    let mut pairs = Vec::new();
+    for (key, item) in value.iter_sorted() {
+        let items = match item {
+            ToonValue::Array(arr) => arr.as_slice(),
+            _ => std::slice::from_ref(item),
+        };
+        
+        for item in items {
+            let text = match item {
+                ToonValue::Null => String::new(),
+                ToonValue::Bool(b) => b.to_string(),
+                ToonValue::Integer(i) => i.to_string(),
+                ToonValue::Number(n) => format_number(*n),
+                ToonValue::String(s) => s.clone(),
+                ToonValue::Array(_) | ToonValue::Object(_) => {
+                    return Err(ToonError::TypeError(format!(
+                        "query strings can't hold the nested value at key '{}'",
+                        key
+                    )));
+                }
+            };
+            pairs.push(format!("{}={}", percent_encode(key), percent_encode(&text)));
+        }
+    }
+    
+    Ok(pairs.join("&"))
+}
+
+/// Percent-encode every byte outside the RFC 3986 unreserved set
+fn percent_encode(s: &str) -> String {
+    let mut output = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            output.push(byte as char);
+        } else {
+            write!(output, "%{:02X}", byte).unwrap();
+        }
+    }
+    output
+}
+
+/// Export a value as block-style YAML
+///
+/// Objects become mappings with keys in sorted order, arrays become `- `
+/// sequences, and empty containers are written in flow style (`{}`, `[]`).
+/// Strings YAML would read as something else (numbers, booleans such as
+/// `yes`, `~`, or text with indicator characters) are double-quoted.
+pub fn to_yaml_string(value: &ToonValue) -> String {
+    let mut output = String::new();
+    write_yaml_block(value, 0, &mut output);
+    output.truncate(output.trim_end_matches('\n').len());
+    output
+}
+
+fn write_yaml_block(value: &ToonValue, level: usize, output: &mut String) {
+    let indent = "  ".repeat(level);
+    match value {
+        ToonValue::Array(arr) if !arr.is_empty() => {
+            for item in arr {
+                output.push_str(&indent);
+                output.push('-');
+                write_yaml_item(item, level + 1, true, output);
+            }
+        }
+        ToonValue::Object(obj) if !obj.is_empty() => {
+            for (key, item) in value.iter_sorted() {
+                output.push_str(&indent);
+                output.push_str(&yaml_scalar_str(key));
+                output.push(':');
+                write_yaml_item(item, level + 1, false, output);
+            }
+        }
+        _ => {
+            output.push_str(&indent);
+            output.push_str(&yaml_scalar(value));
+            output.push('\n');
+        }
+    }
+}
+
+/// Write a value following a `-` or `key:` marker
+fn write_yaml_item(value: &ToonValue, level: usize, in_sequence: bool, output: &mut String) {
+    let is_block = match value {
+        ToonValue::Array(arr) => !arr.is_empty(),
+        ToonValue::Object(obj) => !obj.is_empty(),
+        _ => false,
+    };
+    
+    if !is_block {
+        output.push(' ');
+        output.push_str(&yaml_scalar(value));
+        output.push('\n');
+    } else if in_sequence {
+        // Compact form: the first line of the nested block shares the `- ` line
+        let mut nested = String::new();
+        write_yaml_block(value, level, &mut nested);
+        output.push(' ');
+        output.push_str(&nested[level * 2..]);
+    } else {
+        output.push('\n');
+        write_yaml_block(value, level, output);
+    }
+}
+
+fn yaml_scalar(value: &ToonValue) -> String {
+    match value {
+        ToonValue::Null => "null".to_string(),
+        ToonValue::Bool(b) => b.to_string(),
+        ToonValue::Integer(i) => i.to_string(),
+        ToonValue::Number(n) if n.is_nan() => ".nan".to_string(),
+        ToonValue::Number(n) if n.is_infinite() => if *n > 0.0 { ".inf" } else { "-.inf" }.to_string(),
+        ToonValue::Number(n) => format_number(*n),
+        ToonValue::String(s) => yaml_scalar_str(s),
+        ToonValue::Array(_) => "[]".to_string(),
+        ToonValue::Object(_) => "{}".to_string(),
+    }
+}
+
+/// Write a string plainly if YAML would read it back as the same string,
+/// double-quoting it otherwise
+fn yaml_scalar_str(s: &str) -> String {
+    let plain = s.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_alphanumeric() || " _-./".contains(c))
+        && !s.ends_with(' ')
+        && !matches!(
+            s.to_ascii_lowercase().as_str(),
+            "true" | "false" | "yes" | "no" | "on" | "off" | "y" | "n" | "null"
+                | ".nan" | ".inf"
+        );
+    
+    if plain {
+        s.to_string()
+    } else {
+        format!("\"{}\"", escape_str(s, &EscapeTable::default()))
+    }
+}
+
+/// Export an object as a TOML document
+///
+/// Keys are written in sorted order. Nested objects become `[table]` sections
+/// and non-empty arrays of objects become `[[array-of-tables]]` sections;
+/// objects inside other arrays are written as inline tables. Keys that
+/// aren't bare TOML keys are quoted, and whole-valued floats keep a `.0` so
+/// they stay floats. A value that isn't an object, or a null anywhere, is
+/// rejected, since TOML can't express them.
+pub fn to_toml_string(value: &ToonValue) -> Result<String, ToonError> {
+    if value.as_object().is_none() {
+        return Err(ToonError::TypeError(
+            "TOML documents can only be built from an object".to_string(),
+        ));
+    }
+    
     let mut output = String::new();
-    let options = EncodeOptions::default();
+    write_toml_table(value, &mut Vec::new(), &mut output)?;
+    output.truncate(output.trim_end_matches('\n').len());
+    Ok(output)
+}
+
+/// Whether a value is written as a `[table]` or `[[array-of-tables]]`
+/// section rather than inline after `key =`
+fn is_toml_section(value: &ToonValue) -> bool {
+    match value {
+        ToonValue::Object(_) => true,
+        ToonValue::Array(arr) => !arr.is_empty() && arr.iter().all(|item| item.as_object().is_some()),
+        _ => false,
+    }
+}
+
+/// Write the entries of the table at `path`: plain `key = value` lines first,
+/// then the sections for nested tables
+fn write_toml_table(value: &ToonValue, path: &mut Vec<String>, output: &mut String) -> Result<(), ToonError> {
+    for (key, item) in value.iter_sorted() {
+        if !is_toml_section(item) {
+            output.push_str(&toml_key(key));
+            output.push_str(" = ");
+            write_toml_inline(item, key, output)?;
+            output.push('\n');
+        }
+    }
+    
+    for (key, item) in value.iter_sorted() {
+        if !is_toml_section(item) {
+            continue;
+        }
+        
+        path.push(toml_key(key));
+        let items = match item {
+            ToonValue::Array(arr) => arr.as_slice(),
+            _ => std::slice::from_ref(item),
+        };
+        let (open, close) = if item.as_array().is_some() { ("[[", "]]") } else { ("[", "]") };
+        for table in items {
+            if !output.is_empty() {
+                output.push('\n');
+            }
+            writeln!(output, "{}{}{}", open, path.join("."), close)?;
+            write_toml_table(table, path, output)?;
+        }
+        path.pop();
+    }
     
-    encode_value(value, 0, &options, &mut output, false)?;
+    Ok(())
+}
+
+/// Write a value after `key =`, or as an element of an inline array
+fn write_toml_inline(value: &ToonValue, key: &str, output: &mut String) -> Result<(), ToonError> {
+    match value {
+        ToonValue::Null => {
+            return Err(ToonError::TypeError(format!(
+                "TOML has no null, found one at key '{}'",
+                key
+            )));
+        }
+        ToonValue::Bool(b) => write!(output, "{}", b)?,
+        ToonValue::Integer(i) => write!(output, "{}", i)?,
+        ToonValue::Number(n) if n.is_finite() && n.fract() == 0.0 => write!(output, "{}.0", format_number(*n))?,
+        ToonValue::Number(n) => output.push_str(&format_number(*n)),
+        ToonValue::String(s) => output.push_str(&toml_string(s)),
+        ToonValue::Array(arr) => {
+            output.push('[');
+            for (i, item) in arr.iter().enumerate() {
+                if i > 0 {
+                    output.push_str(", ");
+                }
+                write_toml_inline(item, key, output)?;
+            }
+            output.push(']');
+        }
+        ToonValue::Object(obj) if obj.is_empty() => output.push_str("{}"),
+        ToonValue::Object(_) => {
+            output.push('{');
+            for (i, (field, item)) in value.iter_sorted().enumerate() {
+                output.push_str(if i > 0 { ", " } else { " " });
+                write!(output, "{} = ", toml_key(field))?;
+                write_toml_inline(item, field, output)?;
+            }
+            output.push_str(" }");
+        }
+    }
     
+    Ok(())
+}
+
+/// Write a key bare if TOML allows it, as a quoted string otherwise
+fn toml_key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare {
+        key.to_string()
+    } else {
+        toml_string(key)
+    }
+}
+
+/// Write a TOML basic string, escaping quotes, backslashes and control
+/// characters
+fn toml_string(s: &str) -> String {
+    let mut output = String::with_capacity(s.len() + 2);
+    output.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            '\x08' => output.push_str("\\b"),
+            '\x0c' => output.push_str("\\f"),
+            c if c.is_control() => write!(output, "\\u{:04X}", c as u32).unwrap(),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+/// Export a value as an XML document with a `root` element
+///
+/// Objects become nested elements named after their keys, in sorted order,
+/// and an array under a key becomes one element of that name per item.
+/// Arrays anywhere else (at the top level or inside another array) wrap
+/// their items in `<item>` elements. Scalars become text, with `&`, `<`,
+/// `>`, `"` and `'` escaped, and nulls and empty containers become empty
+/// elements like `<key/>`.
+///
+/// The mapping is lossy: there are no attributes or type information, so
+/// `1` and `"1"` look the same, a one-item array looks like a single value
+/// and an empty array under a key disappears. Keys that aren't valid XML
+/// element names, and control characters XML can't represent, are rejected.
+pub fn to_xml_string(value: &ToonValue, root: &str) -> Result<String, ToonError> {
+    let mut output = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    write_xml_element(root, value, 0, &mut output)?;
+    output.truncate(output.trim_end_matches('\n').len());
     Ok(output)
 }
 
-/// Encode a value with the given options
-pub fn encode_with_options(
+/// Write the element(s) for an object entry: one per item for arrays
+fn write_xml_field(name: &str, value: &ToonValue, level: usize, output: &mut String) -> Result<(), ToonError> {
+    match value {
+        ToonValue::Array(arr) => arr.iter().try_for_each(|item| write_xml_element(name, item, level, output)),
+        _ => write_xml_element(name, value, level, output),
+    }
+}
+
+/// Write a single `<name>` element holding `value`
+fn write_xml_element(name: &str, value: &ToonValue, level: usize, output: &mut String) -> Result<(), ToonError> {
+    if !is_xml_name(name) {
+        return Err(ToonError::TypeError(format!("'{}' is not a valid XML element name", name)));
+    }
+    
+    let indent = "  ".repeat(level);
+    match value {
+        ToonValue::Null => writeln!(output, "{}<{}/>", indent, name)?,
+        ToonValue::Array(arr) if arr.is_empty() => writeln!(output, "{}<{}/>", indent, name)?,
+        ToonValue::Object(obj) if obj.is_empty() => writeln!(output, "{}<{}/>", indent, name)?,
+        ToonValue::Array(arr) => {
+            writeln!(output, "{}<{}>", indent, name)?;
+            for item in arr {
+                write_xml_element("item", item, level + 1, output)?;
+            }
+            writeln!(output, "{}</{}>", indent, name)?;
+        }
+        ToonValue::Object(_) => {
+            writeln!(output, "{}<{}>", indent, name)?;
+            for (key, item) in value.iter_sorted() {
+                write_xml_field(key, item, level + 1, output)?;
+            }
+            writeln!(output, "{}</{}>", indent, name)?;
+        }
+        ToonValue::Bool(b) => writeln!(output, "{}<{}>{}</{}>", indent, name, b, name)?,
+        ToonValue::Integer(i) => writeln!(output, "{}<{}>{}</{}>", indent, name, i, name)?,
+        ToonValue::Number(n) => writeln!(output, "{}<{}>{}</{}>", indent, name, format_number(*n), name)?,
+        ToonValue::String(s) => writeln!(output, "{}<{}>{}</{}>", indent, name, xml_text(s)?, name)?,
+    }
+    
+    Ok(())
+}
+
+/// Whether `name` can be used as an XML element name without namespaces
+fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Escape XML special characters in text content
+fn xml_text(s: &str) -> Result<String, ToonError> {
+    let mut output = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&apos;"),
+            '\t' | '\n' | '\r' => output.push(c),
+            c if (c as u32) < 0x20 => {
+                return Err(ToonError::TypeError(format!(
+                    "character U+{:04X} cannot appear in XML",
+                    c as u32
+                )));
+            }
+            c => output.push(c),
+        }
+    }
+    Ok(output)
+}
+
+/// Encode a value directly to an `io::Write`, such as a file
+///
+/// The output is written incrementally rather than built up in memory
+/// first; wrap unbuffered writers in a `BufWriter`.
+pub fn encode_to_writer<W: std::io::Write>(
+    value: &ToonValue,
+    options: &EncodeOptions,
+    writer: W,
+) -> Result<(), ToonError> {
+    let mut writer = IoWriter { inner: writer, error: None };
+    encode_limited(value, options, &KeyOrder::None, None, &mut writer)
+        .map_err(|e| writer.error.take().map_or(e, ToonError::Io))
+}
+
+fn encode_to_string(
     value: &ToonValue,
     options: &EncodeOptions,
+    order: &KeyOrder,
 ) -> Result<String, ToonError> {
     let mut output = String::new();
-    encode_value(value, 0, options, &mut output, false)?;
+    encode_limited(value, options, order, None, &mut output)?;
     Ok(output)
 }
 
+/// Encode into `output`, enforcing `max_output_len`
+fn encode_limited<W: Write>(
+    value: &ToonValue,
+    options: &EncodeOptions,
+    order: &KeyOrder,
+    comments: Option<&HashMap<String, String>>,
+    output: &mut W,
+) -> Result<(), ToonError> {
+    let mut output = LimitedWriter {
+        inner: output,
+        written: 0,
+        limit: options.max_output_len,
+        exceeded: false,
+    };
+    
+    let result = match options.line_ending {
+        LineEnding::Lf => encode_root(value, options, order, comments, &mut output),
+        LineEnding::CrLf => {
+            encode_root(value, options, order, comments, &mut CrLfWriter { inner: &mut output })
+        }
+    };
+    match result {
+        Err(_) if output.exceeded => {
+            Err(ToonError::Serialization("output too large".to_string()))
+        }
+        result => result,
+    }
+}
+
+/// Encode the top-level value, with `comments` before the keys they name
+fn encode_root<W: Write>(
+    value: &ToonValue,
+    options: &EncodeOptions,
+    order: &KeyOrder,
+    comments: Option<&HashMap<String, String>>,
+    output: &mut W,
+) -> Result<(), ToonError> {
+    let Some(comments) = comments else {
+        return encode_value(value, 0, options, output, false, order);
+    };
+    
+    let entries: Vec<(Cow<str>, &ToonValue, &KeyOrder)> = match value {
+        ToonValue::Object(obj) if obj.is_empty() => Vec::new(),
+        ToonValue::Object(obj) if options.flatten_keys => flattened_entries(obj, order)?
+            .into_iter()
+            .map(|(key, value, child_order)| (Cow::Owned(key), value, child_order))
+            .collect(),
+        ToonValue::Object(obj) => ordered_entries(obj, order)
+            .into_iter()
+            .map(|(key, value, child_order)| (Cow::Borrowed(key.as_str()), value, child_order))
+            .collect(),
+        _ => Vec::new(),
+    };
+    if let Some(path) = comments.keys().find(|path| !entries.iter().any(|(key, _, _)| key == *path)) {
+        return Err(ToonError::TypeError(format!(
+            "no top-level key '{}' to write the comment before",
+            path
+        )));
+    }
+    if entries.is_empty() {
+        return encode_value(value, 0, options, output, false, order);
+    }
+    
+    for (i, (key, value, child_order)) in entries.iter().enumerate() {
+        let comment = comments.get(key.as_ref()).map(String::as_str);
+        encode_top_level_entry(key, value, i, comment, "", 0, options, output, child_order)?;
+    }
+    Ok(())
+}
+
+/// Encodes a value lazily as a sequence of string chunks, for streaming
+///
+/// Concatenating the chunks gives exactly the output of
+/// `encode_with_key_order`. A top-level object yields one chunk per entry,
+/// and a top-level array written one item per line yields one chunk per
+/// item plus one for each bracket; any other value is a single chunk. Each
+/// chunk is encoded only when requested. `max_output_len` applies to the
+/// total length; once it is exceeded, or a chunk fails to encode, an error
+/// is yielded and iteration ends.
+pub struct EncodeChunks {
+    value: ToonValue,
+    order: KeyOrder,
+    options: EncodeOptions,
+    limit: Option<usize>,
+    layout: ChunkLayout,
+    next: usize,
+    written: usize,
+    done: bool,
+}
+
+/// How an `EncodeChunks` value is split up
+enum ChunkLayout {
+    /// Entries of a top-level object, as keys in output order with the
+    /// ordering of their values
+    Entries(Vec<(String, KeyOrder)>),
+    /// Opening bracket, items and closing bracket of a multi-line array
+    Lines,
+    /// The whole value at once
+    Whole,
+}
+
+impl EncodeChunks {
+    /// Split the encoding of `value` into chunks
+    pub fn new(value: ToonValue, options: EncodeOptions) -> Self {
+        Self::with_key_order(value, options, KeyOrder::None)
+    }
+    
+    /// Like `new`, emitting object keys in the order captured by `order`
+    pub fn with_key_order(value: ToonValue, options: EncodeOptions, order: KeyOrder) -> Self {
+        // Flattening up front lets the dotted entries be chunked like any
+        // others; on a key collision the value is encoded whole, which fails
+        if let (ToonValue::Object(obj), true) = (&value, options.flatten_keys) {
+            if let Ok(entries) = flattened_entries(obj, &order) {
+                let order = KeyOrder::Object(
+                    entries.iter().map(|(key, _, child_order)| (key.clone(), (*child_order).clone())).collect(),
+                );
+                let flat = ToonValue::Object(
+                    entries.into_iter().map(|(key, value, _)| (key, value.clone())).collect(),
+                );
+                return Self::with_key_order(flat, EncodeOptions { flatten_keys: false, ..options }, order);
+            }
+        }
+        
+        let layout = match &value {
+            ToonValue::Object(obj) if !obj.is_empty() && !options.flatten_keys => ChunkLayout::Entries(
+                ordered_entries(obj, &order)
+                    .into_iter()
+                    .map(|(key, _, child_order)| (key.clone(), child_order.clone()))
+                    .collect(),
+            ),
+            ToonValue::Array(arr) if is_multiline_array(arr, &options) => ChunkLayout::Lines,
+            _ => ChunkLayout::Whole,
+        };
+        
+        Self {
+            value,
+            order,
+            limit: options.max_output_len,
+            options: EncodeOptions { max_output_len: None, ..options },
+            layout,
+            next: 0,
+            written: 0,
+            done: false,
+        }
+    }
+    
+    /// Encode chunk number `self.next` into `output`, returning `None` past
+    /// the last chunk
+    fn encode_next(&self, output: &mut String) -> Option<Result<(), ToonError>> {
+        let options = &self.options;
+        let result = match (&self.layout, &self.value) {
+            (ChunkLayout::Entries(keys), ToonValue::Object(obj)) => {
+                let (key, order) = keys.get(self.next)?;
+                encode_top_level_entry(key, &obj[key], self.next, None, "", 0, options, output, order)
+            }
+            (ChunkLayout::Lines, ToonValue::Array(arr)) => match self.next {
+                0 => writeln!(output, "[").map_err(ToonError::from),
+                n if n <= arr.len() => {
+                    let i = n - 1;
+                    encode_array_line(&arr[i], i, "", 0, options, output, self.order.element(i))
+                }
+                n if n == arr.len() + 1 => {
+                    let comma = if options.trailing_comma { "," } else { "" };
+                    write!(output, "{}\n]", comma).map_err(ToonError::from)
+                }
+                _ => return None,
+            },
+            _ if self.next == 0 => encode_value(&self.value, 0, options, output, false, &self.order),
+            _ => return None,
+        };
+        Some(result)
+    }
+}
+
+impl Iterator for EncodeChunks {
+    type Item = Result<String, ToonError>;
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        
+        let mut chunk = String::new();
+        let result = match self.encode_next(&mut chunk) {
+            Some(result) => result,
+            None => {
+                self.done = true;
+                return None;
+            }
+        };
+        if self.options.line_ending == LineEnding::CrLf {
+            chunk = chunk.replace('\n', "\r\n");
+        }
+        self.next += 1;
+        self.written += chunk.len();
+        
+        let result = match result {
+            Ok(()) if self.limit.is_some_and(|limit| self.written > limit) => {
+                Err(ToonError::Serialization("output too large".to_string()))
+            }
+            result => result,
+        };
+        self.done = result.is_err();
+        Some(result.map(|()| chunk))
+    }
+}
+
+/// A writer that fails once its output would exceed `limit` bytes
+struct LimitedWriter<'a, W> {
+    inner: &'a mut W,
+    written: usize,
+    limit: Option<usize>,
+    exceeded: bool,
+}
+
+impl<W: Write> Write for LimitedWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        if let Some(limit) = self.limit {
+            if self.written + s.len() > limit {
+                self.exceeded = true;
+                return Err(std::fmt::Error);
+            }
+        }
+        
+        self.written += s.len();
+        self.inner.write_str(s)
+    }
+}
+
+/// A writer that turns each `\n` into `\r\n`
+///
+/// Newlines inside strings are always escaped (heredoc block strings are
+/// not written under this line ending), so every raw `\n` the encoder
+/// writes is a line break.
+struct CrLfWriter<'a, W> {
+    inner: &'a mut W,
+}
+
+impl<W: Write> Write for CrLfWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        let mut lines = s.split('\n');
+        if let Some(first) = lines.next() {
+            self.inner.write_str(first)?;
+        }
+        for line in lines {
+            self.inner.write_str("\r\n")?;
+            self.inner.write_str(line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Adapts an `io::Write` to `fmt::Write`, keeping the underlying I/O error
+struct IoWriter<W> {
+    inner: W,
+    error: Option<std::io::Error>,
+}
+
+impl<W: std::io::Write> Write for IoWriter<W> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            std::fmt::Error
+        })
+    }
+}
+
 fn encode_value<W: Write>(
     value: &ToonValue,
     level: usize,
     options: &EncodeOptions,
     output: &mut W,
     in_array: bool,
+    order: &KeyOrder,
 ) -> Result<(), ToonError> {
     match value {
         ToonValue::Null => write!(output, "null")?,
         ToonValue::Bool(b) => write!(output, "{}", b)?,
-        ToonValue::Number(n) => write!(output, "{}", format_number(*n))?,
+        ToonValue::Integer(i) => write!(output, "{}", i)?,
+        ToonValue::Number(n) => write!(output, "{}", format_float(*n, options))?,
+        ToonValue::String(s) if is_block_string(s, options) => {
+            let delimiter = heredoc_delimiter(s);
+            write!(output, "<<{}\n{}\n{}", delimiter, s, delimiter)?
+        }
         ToonValue::String(s) => {
-            if utils::needs_quotes(s) {
-                write!(output, "\"{}\"", escape_str(s))?
+            if string_needs_quotes(s, false, options) {
+                write!(output, "\"{}\"", escape_str(s, &options.effective_escape_table()))?
             } else {
                 write!(output, "{}", s)?
             }
         }
-        ToonValue::Array(arr) => encode_array(arr, level, options, output, in_array)?,
-        ToonValue::Object(obj) => encode_object(obj, level, options, output, in_array)?,
+        ToonValue::Array(arr) => encode_array(arr, level, options, output, in_array, order)?,
+        ToonValue::Object(obj) => encode_object(obj, level, options, output, in_array, order)?,
     }
 
     Ok(())
 }
 
+/// Whether `s` is written as a heredoc block under `options`
+///
+/// Only with `\n` line endings: under [`LineEnding::CrLf`] the block's line
+/// breaks would be rewritten to `\r\n` and decode back as part of the string.
+fn is_block_string(s: &str, options: &EncodeOptions) -> bool {
+    options.block_strings
+        && options.line_ending == LineEnding::Lf
+        && s.contains('\n')
+        && !s.contains('\r')
+}
+
+/// Pick a heredoc delimiter that appears nowhere in `s`, so none of its
+/// lines can end the block early
+fn heredoc_delimiter(s: &str) -> String {
+    (0..)
+        .map(|i| if i == 0 { "END".to_string() } else { format!("END{}", i) })
+        .find(|delimiter| !s.contains(delimiter.as_str()))
+        .unwrap()
+}
+
+/// Whether a key or string value has to be quoted under `options`
+fn string_needs_quotes(s: &str, is_key: bool, options: &EncodeOptions) -> bool {
+    if options.minimize_quotes {
+        utils::needs_quotes_minimal(s, is_key)
+    } else {
+        utils::needs_quotes(s)
+    }
+}
+
+/// Format a float, switching to scientific notation outside the configured
+/// `scientific_threshold` range
+fn format_float(n: f64, options: &EncodeOptions) -> String {
+    match options.scientific_threshold {
+        Some((low, high)) if n.is_finite() && n != 0.0 && (n.abs() < low || n.abs() >= high) => {
+            format!("{:e}", n)
+        }
+        _ if !options.coerce_whole_floats_to_int && n.is_finite() && n.fract() == 0.0 => {
+            format!("{}.0", format_number(n))
+        }
+        _ => format_number(n),
+    }
+}
+
 fn encode_array<W: Write>(
     arr: &[ToonValue],
     level: usize,
     options: &EncodeOptions,
     output: &mut W,
     in_array: bool,
+    order: &KeyOrder,
 ) -> Result<(), ToonError> {
     if arr.is_empty() {
-        return write!(output, "[]").map_err(|e| ToonError::Serialization(e.to_string()));
+        return encode_empty('[', ']', level, options, output);
     }
     
-    // Check if this is an array of objects that can be represented in tabular format
-    if let Some(fields) = is_uniform_array_of_objects(arr) {
-        return encode_tabular_array(arr, &fields, level, options, output);
+    // Check if this is an array of objects that can be represented in tabular
+    // format. Its rows take whole lines, so only where a document has them:
+    // at the top level and as a top-level entry's value.
+    if options.tabular && !in_array && level <= 1 {
+        if let Some(fields) = is_uniform_array_of_objects(arr, options, order) {
+            return encode_tabular_array(arr, &fields, level, options, output);
+        }
     }
     
     // Check if this is a simple array that can be written on one line
@@ -76,7 +891,7 @@ fn encode_array<W: Write>(
             if i > 0 {
                 write!(output, ", ")?;
             }
-            encode_value(item, 0, options, output, true)?;
+            encode_value(item, 0, options, output, true, &KeyOrder::None)?;
         }
         
         write!(output, "]")?;
@@ -84,7 +899,7 @@ fn encode_array<W: Write>(
     }
     
     // Complex array with nested structures
-    if in_array || level > 0 {
+    if (in_array || level > 0) && !options.pretty {
         // If we're already in an array or at a nested level, don't add extra newlines
         write!(output, "[")?;
         
@@ -92,94 +907,230 @@ fn encode_array<W: Write>(
             if i > 0 {
                 write!(output, ", ")?;
             }
-            encode_value(item, level + 1, options, output, true)?;
+            encode_value(item, level + 1, options, output, true, order.element(i))?;
         }
         
         write!(output, "]")?;
     } else {
-        // Top-level array gets special formatting
+        // Top-level (or, when pretty-printing, any) array gets one item per
+        // line. Array elements start at their own nesting level, while object
+        // values share the line of their key one level up.
         writeln!(output, "[")?;
         
-        let indent = " ".repeat(level * options.indent);
+        let base = if in_array { level } else { level.saturating_sub(1) };
+        let indent = " ".repeat(base * options.indent);
         
         for (i, item) in arr.iter().enumerate() {
-            if i > 0 {
-                writeln!(output, ",")?;
-            }
-            
-            write!(output, "{}{}", indent, " ".repeat(options.indent))?;
-            encode_value(item, level + 1, options, output, true)?;
+            encode_array_line(item, i, &indent, level, options, output, order.element(i))?;
         }
         
-        if !arr.is_empty() {
-            writeln!(output)?;
+        if options.trailing_comma {
+            write!(output, ",")?;
         }
-        
+        writeln!(output)?;
         write!(output, "{}]", indent)?;
     }
     
     Ok(())
 }
 
-fn encode_object<W: Write>(
+/// Write item `i` of a multi-line array on its own line, after the comma
+/// ending the previous item
+fn encode_array_line<W: Write>(
+    item: &ToonValue,
+    i: usize,
+    indent: &str,
+    level: usize,
+    options: &EncodeOptions,
+    output: &mut W,
+    order: &KeyOrder,
+) -> Result<(), ToonError> {
+    if i > 0 {
+        writeln!(output, ",")?;
+    }
+    
+    write!(output, "{}{}", indent, " ".repeat(options.indent))?;
+    encode_value(item, level + 1, options, output, true, order)
+}
+
+/// Whether a top-level array is written one item per line
+fn is_multiline_array(arr: &[ToonValue], options: &EncodeOptions) -> bool {
+    let tabular = options.tabular && is_uniform_array_of_objects(arr, options, &KeyOrder::None).is_some();
+    !arr.is_empty() && !tabular && arr.iter().any(|v| !v.is_primitive())
+}
+
+fn encode_empty<W: Write>(
+    open: char,
+    close: char,
+    level: usize,
+    options: &EncodeOptions,
+    output: &mut W,
+) -> Result<(), ToonError> {
+    if options.expand_empty {
+        // Align the closer with the line holding the opener
+        let indent = " ".repeat(level.saturating_sub(1) * options.indent);
+        write!(output, "{}\n{}{}", open, indent, close)?;
+    } else {
+        write!(output, "{}{}", open, close)?;
+    }
+    
+    Ok(())
+}
+
+fn encode_object<W: Write>(
     obj: &HashMap<String, ToonValue>,
     level: usize,
     options: &EncodeOptions,
     output: &mut W,
     in_array: bool,
+    order: &KeyOrder,
 ) -> Result<(), ToonError> {
     if obj.is_empty() {
-        return write!(output, "{{}}").map_err(|e| ToonError::Serialization(e.to_string()));
+        return encode_empty('{', '}', level, options, output);
     }
     
     let indent = " ".repeat(level * options.indent);
+    let colon = options.colon_spacing.separator();
     if in_array || level > 0 {
         // Inline object
         write!(output, "{{")?;
         
-        for (i, (key, value)) in obj.iter().enumerate() {
+        for (i, (key, value, child_order)) in ordered_entries(obj, order).into_iter().enumerate() {
             if i > 0 {
                 write!(output, ", ")?;
             }
             
-            if utils::needs_quotes(key) {
-                write!(output, "\"{}\": ", escape_str(key))?;
+            if string_needs_quotes(key, true, options) {
+                write!(output, "\"{}\"{}", escape_str(key, &options.effective_escape_table()), colon)?;
             } else {
-                write!(output, "{}: ", key)?;
+                write!(output, "{}{}", key, colon)?;
             }
             
-            encode_value(value, level + 1, options, output, false)?;
+            encode_value(value, level + 1, options, output, false, child_order)?;
         }
         
         write!(output, "}}")?;
+    } else if options.flatten_keys {
+        // Top-level object with its nested objects folded into dotted keys
+        for (i, (key, value, child_order)) in flattened_entries(obj, order)?.into_iter().enumerate() {
+            encode_top_level_entry(&key, value, i, None, &indent, level, options, output, child_order)?;
+        }
     } else {
         // Top-level object
-        for (i, (key, value)) in obj.iter().enumerate() {
-            if i > 0 {
-                writeln!(output, "")?;
-            }
-            
-            if utils::needs_quotes(key) {
-                write!(output, "{}{}\"{}: ", indent, "\"", escape_str(key))?;
-            } else {
-                write!(output, "{}{}: ", indent, key)?;
+        for (i, (key, value, child_order)) in ordered_entries(obj, order).into_iter().enumerate() {
+            encode_top_level_entry(key, value, i, None, &indent, level, options, output, child_order)?;
+        }
+    }
+    
+    Ok(())
+}
+
+/// The entries of `obj` in order, with each non-empty nested object replaced
+/// by its own entries under dotted keys, as written under `flatten_keys`
+fn flattened_entries<'a>(
+    obj: &'a HashMap<String, ToonValue>,
+    order: &'a KeyOrder,
+) -> Result<Vec<(String, &'a ToonValue, &'a KeyOrder)>, ToonError> {
+    let mut entries = Vec::new();
+    flatten_entries(obj, order, "", &mut entries);
+    let mut seen = std::collections::HashSet::with_capacity(entries.len());
+    match entries.iter().find(|(key, _, _)| !seen.insert(key.as_str())) {
+        Some((key, _, _)) => Err(ToonError::TypeError(format!(
+            "flattened key '{}' appears more than once",
+            key
+        ))),
+        None => Ok(entries),
+    }
+}
+
+fn flatten_entries<'a>(
+    obj: &'a HashMap<String, ToonValue>,
+    order: &'a KeyOrder,
+    prefix: &str,
+    entries: &mut Vec<(String, &'a ToonValue, &'a KeyOrder)>,
+) {
+    for (key, value, child_order) in ordered_entries(obj, order) {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match value {
+            ToonValue::Object(nested) if !nested.is_empty() => {
+                flatten_entries(nested, child_order, &path, entries)
             }
-            
-            match value {
-                ToonValue::Array(arr) if !arr.is_empty() => {
-                    encode_array(arr, level + 1, options, output, false)?;
-                }
-                ToonValue::Object(nested_obj) if !nested_obj.is_empty() => {
-                    encode_object(nested_obj, level + 1, options, output, false)?;
-                }
-                _ => {
-                    encode_value(value, level + 1, options, output, false)?;
-                }
+            _ => entries.push((path, value, child_order)),
+        }
+    }
+}
+
+/// Write entry `i` of a top-level object on its own line, after the newline
+/// ending the previous entry and the lines of its `comment`, if any
+#[allow(clippy::too_many_arguments)]
+fn encode_top_level_entry<W: Write>(
+    key: &str,
+    value: &ToonValue,
+    i: usize,
+    comment: Option<&str>,
+    indent: &str,
+    level: usize,
+    options: &EncodeOptions,
+    output: &mut W,
+    order: &KeyOrder,
+) -> Result<(), ToonError> {
+    if i > 0 {
+        writeln!(output)?;
+    }
+    for line in comment.into_iter().flat_map(str::lines) {
+        if line.is_empty() {
+            writeln!(output, "{}#", indent)?;
+        } else {
+            writeln!(output, "{}# {}", indent, line)?;
+        }
+    }
+    
+    let colon = options.colon_spacing.separator();
+    if string_needs_quotes(key, true, options) {
+        write!(output, "{}\"{}\"{}", indent, escape_str(key, &options.effective_escape_table()), colon)?;
+    } else {
+        write!(output, "{}{}{}", indent, key, colon)?;
+    }
+    
+    match value {
+        ToonValue::Array(arr) if !arr.is_empty() => {
+            encode_array(arr, level + 1, options, output, false, order)
+        }
+        ToonValue::Object(nested_obj) if !nested_obj.is_empty() => {
+            encode_object(nested_obj, level + 1, options, output, false, order)
+        }
+        _ => encode_value(value, level + 1, options, output, false, order),
+    }
+}
+
+/// List an object's entries in captured key order, with any unordered keys last
+fn ordered_entries<'a>(
+    obj: &'a HashMap<String, ToonValue>,
+    order: &'a KeyOrder,
+) -> Vec<(&'a String, &'a ToonValue, &'a KeyOrder)> {
+    let keys = match order {
+        KeyOrder::Object(keys) => keys.as_slice(),
+        _ => &[],
+    };
+    
+    let mut entries: Vec<_> = keys
+        .iter()
+        .filter_map(|(key, child)| obj.get_key_value(key).map(|(k, v)| (k, v, child)))
+        .collect();
+    
+    if entries.len() < obj.len() {
+        for (key, value) in obj {
+            if !keys.iter().any(|(k, _)| k == key) {
+                entries.push((key, value, &KeyOrder::None));
             }
         }
     }
     
-    Ok(())
+    entries
 }
 
 fn encode_tabular_array<W: Write>(
@@ -189,22 +1140,27 @@ fn encode_tabular_array<W: Write>(
     options: &EncodeOptions,
     output: &mut W,
 ) -> Result<(), ToonError> {
-    // Write the header
-    write!(output, "[")?;
+    // Write the header, bracketed unless a bare CSV-style line was requested
+    if options.tabular_bracket_header {
+        write!(output, "[")?;
+    }
     
     for (i, field) in fields.iter().enumerate() {
         if i > 0 {
             write!(output, ", ")?;
         }
         
-        if utils::needs_quotes(field) {
-            write!(output, "\"{}\"", escape_str(field))?;
+        if string_needs_quotes(field, true, options) {
+            write!(output, "\"{}\"", escape_str(field, &options.effective_escape_table()))?;
         } else {
             write!(output, "{}", field)?;
         }
     }
     
-    write!(output, "]\n")?;
+    if options.tabular_bracket_header {
+        write!(output, "]")?;
+    }
+    writeln!(output)?;
     
     // Write each row
     for (row_idx, item) in arr.iter().enumerate() {
@@ -215,7 +1171,7 @@ fn encode_tabular_array<W: Write>(
                 }
                 
                 if let Some(value) = obj.get(field) {
-                    encode_value(value, level + 1, options, output, true)?;
+                    encode_value(value, level + 1, options, output, true, &KeyOrder::None)?;
                 } else {
                     write!(output, "null")?;
                 }
@@ -230,54 +1186,65 @@ fn encode_tabular_array<W: Write>(
     Ok(())
 }
 
-fn is_uniform_array_of_objects(arr: &[ToonValue]) -> Option<Vec<String>> {
-    if arr.is_empty() {
-        return None;
+/// Decide whether an array can be written in tabular form, returning the
+/// sorted column names if so
+///
+/// Every element must be a non-empty object whose values are all primitives,
+/// so no field is ever dropped. By default all objects must also have exactly
+/// the same set of keys. With `tabular_fill_missing`, objects may instead
+/// hold any subset of the columns (the union of all keys) and missing fields
+/// are written as `null` cells; an empty object then becomes a row of nulls.
+/// Fields are ordered according to `tabular_field_order`, using `order` for
+/// the key order of each object.
+fn is_uniform_array_of_objects(
+    arr: &[ToonValue],
+    options: &EncodeOptions,
+    order: &KeyOrder,
+) -> Option<Vec<String>> {
+    let mut objects = Vec::with_capacity(arr.len());
+    for item in arr {
+        match item {
+            // Heredoc blocks span lines, so they cannot sit in a tabular row
+            ToonValue::Object(obj) if obj.values().all(|v| {
+                v.is_primitive() && !matches!(v, ToonValue::String(s) if is_block_string(s, options))
+            }) => objects.push(obj),
+            _ => return None,
+        }
     }
     
-    // Get fields from first object
-    let first_obj = match &arr[0] {
-        ToonValue::Object(obj) => obj,
-        _ => return None,
+    let keys_in_order = |i: usize| {
+        ordered_entries(objects[i], order.element(i))
+            .into_iter()
+            .map(|(key, _, _)| key)
     };
     
-    // Collect all field names that have primitive values
-    let mut fields: Vec<String> = first_obj
-        .iter()
-        .filter_map(|(k, v)| {
-            if v.is_primitive() {
-                Some(k.clone())
-            } else {
-                None
+    let mut fields: Vec<String> = if options.tabular_fill_missing {
+        let mut union: Vec<String> = Vec::new();
+        for i in 0..objects.len() {
+            for key in keys_in_order(i) {
+                if !union.contains(key) {
+                    union.push(key.clone());
+                }
             }
-        })
-        .collect();
+        }
+        union
+    } else {
+        let first_obj = objects.first()?;
+        let same_keys = |obj: &&HashMap<String, ToonValue>| {
+            obj.len() == first_obj.len() && obj.keys().all(|k| first_obj.contains_key(k))
+        };
+        if !objects.iter().all(same_keys) {
+            return None;
+        }
+        keys_in_order(0).cloned().collect()
+    };
     
-    if fields.is_empty() {
+    if fields.is_empty() || options.tabular_max_cols.is_some_and(|max| fields.len() > max) {
         return None;
     }
     
-    // Sort fields for consistent output
-    fields.sort();
-    
-    // Check all objects have the same structure
-    for item in arr.iter().skip(1) {
-        let obj = match item {
-            ToonValue::Object(obj) => obj,
-            _ => return None,
-        };
-        
-        // Check if all fields exist and are primitive
-        for field in &fields {
-            if !obj.contains_key(field) || !obj[field].is_primitive() {
-                return None;
-            }
-        }
-        
-        // Check for any extra fields
-        if obj.len() != fields.len() {
-            return None;
-        }
+    if options.tabular_field_order == TabularFieldOrder::Sorted {
+        fields.sort();
     }
     
     Some(fields)
@@ -291,7 +1258,11 @@ impl ToonValueExt for ToonValue {
     fn is_primitive(&self) -> bool {
         matches!(
             self,
-            ToonValue::Null | ToonValue::Bool(_) | ToonValue::Number(_) | ToonValue::String(_)
+            ToonValue::Null
+                | ToonValue::Bool(_)
+                | ToonValue::Integer(_)
+                | ToonValue::Number(_)
+                | ToonValue::String(_)
         )
     }
 }
@@ -306,9 +1277,11 @@ mod tests {
         assert_eq!(encode(&ToonValue::Null).unwrap(), "null");
         assert_eq!(encode(&ToonValue::Bool(true)).unwrap(), "true");
         assert_eq!(encode(&ToonValue::Bool(false)).unwrap(), "false");
+        assert_eq!(encode(&ToonValue::Integer(42)).unwrap(), "42");
         assert_eq!(encode(&ToonValue::Number(42.0)).unwrap(), "42");
-        assert_eq!(encode(&ToonValue::Number(3.14)).unwrap(), "3.14");
-        assert_eq!(encode(&ToonValue::String("hello".to_string())).unwrap(), "\"hello\"");
+        assert_eq!(encode(&ToonValue::Number(2.5)).unwrap(), "2.5");
+        assert_eq!(encode(&ToonValue::String("hello".to_string())).unwrap(), "hello");
+        assert_eq!(encode(&ToonValue::String("hello world".to_string())).unwrap(), "\"hello world\"");
     }
     
     #[test]
@@ -332,7 +1305,96 @@ mod tests {
         let result = encode(&obj).unwrap();
         
         // The order of keys is not guaranteed, so we need to check both possibilities
-        assert!(result == "a: 1\nb: \"test\"" || result == "b: \"test\"\na: 1");
+        assert!(result == "a: 1\nb: test" || result == "b: test\na: 1");
+    }
+    
+    #[test]
+    fn test_encode_flatten_keys() {
+        let value = crate::decoder::decode(
+            "{name: Ann, address: {city: Anytown, geo: {lat: 1.5, lng: -2}}, empty: {}, tags: [{a: {b: 1}}, 2]}",
+        )
+        .unwrap();
+        let options = EncodeOptions::new().flatten_keys(true);
+        let encoded = encode_with_key_order(&value, &options, &KeyOrder::sorted(&value)).unwrap();
+        assert_eq!(
+            encoded,
+            "address.city: Anytown\naddress.geo.lat: 1.5\naddress.geo.lng: -2\nempty: {}\nname: Ann\ntags: [{a: {b: 1}}, 2]"
+        );
+        
+        let chunks: Vec<String> = EncodeChunks::with_key_order(value.clone(), options, KeyOrder::sorted(&value))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(chunks.len(), 6);
+        assert_eq!(chunks.concat(), encoded);
+        
+        // The same lines as an object that really has dotted keys
+        let flat = crate::decoder::decode("{address.city: Anytown, address.geo.lat: 1.5}").unwrap();
+        let encoded = encode_with_key_order(&flat, &EncodeOptions::new(), &KeyOrder::sorted(&flat)).unwrap();
+        assert_eq!(encoded, "address.city: Anytown\naddress.geo.lat: 1.5");
+        
+        // Only the top-level object is flattened
+        let nested = crate::decoder::decode("[{a: {b: 1}}]").unwrap();
+        assert_eq!(encode_with_options(&nested, &options.tabular(false)).unwrap(), "[\n  {a: {b: 1}}\n]");
+        
+        let clash = crate::decoder::decode("{\"a.b\": 1, a: {b: 2}}").unwrap();
+        let err = encode_with_options(&clash, &options).unwrap_err().to_string();
+        assert!(err.contains("flattened key 'a.b' appears more than once"), "{}", err);
+        assert!(EncodeChunks::new(clash, options).any(|chunk| chunk.is_err()));
+    }
+    
+    #[test]
+    fn test_encode_with_comments() {
+        let value = crate::decoder::decode("{name: Ann, port: 8080, address: {city: Oslo}}").unwrap();
+        let order = KeyOrder::sorted(&value);
+        let mut comments = HashMap::new();
+        comments.insert("port".to_string(), "Port to listen on\n\nDefaults to 80".to_string());
+        
+        let encoded = encode_with_comments_ordered(&value, &comments, &EncodeOptions::new(), &order).unwrap();
+        assert_eq!(
+            encoded,
+            "address: {city: Oslo}\nname: Ann\n# Port to listen on\n#\n# Defaults to 80\nport: 8080"
+        );
+        
+        // Nested keys can carry comments once they are flattened
+        comments.insert("address.city".to_string(), "Where Ann lives".to_string());
+        let err = encode_with_comments(&value, &comments, &EncodeOptions::new()).unwrap_err();
+        assert!(err.to_string().contains("no top-level key 'address.city'"), "{}", err);
+        let options = EncodeOptions::new().flatten_keys(true).line_ending(LineEnding::CrLf);
+        let encoded = encode_with_comments_ordered(&value, &comments, &options, &order).unwrap();
+        assert!(encoded.starts_with("# Where Ann lives\r\naddress.city: Oslo\r\nname: Ann\r\n# Port"), "{}", encoded);
+        
+        // Comments are skipped when decoding
+        let single = crate::decoder::decode("[{id: 1}]").unwrap();
+        let encoded = encode_with_comments(&single, &HashMap::new(), &EncodeOptions::new()).unwrap();
+        assert_eq!(encoded, encode(&single).unwrap());
+        let commented = "{\n# The id\nid: 1}";
+        assert_eq!(crate::decoder::decode(commented).unwrap(), crate::decoder::decode("{id: 1}").unwrap());
+        
+        assert!(encode_with_comments(&single, &comments, &EncodeOptions::new()).is_err());
+    }
+    
+    #[test]
+    fn test_encode_trailing_comma() {
+        use crate::decoder::decode_with_options;
+        use crate::types::DecodeOptions;
+        
+        let value = crate::decoder::decode("[{a: [1, {b: 2}]}, [3, 4], x]").unwrap();
+        let options = EncodeOptions::new().trailing_comma(true);
+        assert_eq!(encode_with_options(&value, &options).unwrap(), "[\n  {a: [1, {b: 2}]},\n  [3, 4],\n  x,\n]");
+        let pretty = encode_with_options(&value, &options.pretty(true)).unwrap();
+        assert_eq!(pretty, "[\n  {a: [\n    1,\n    {b: 2},\n  ]},\n  [3, 4],\n  x,\n]");
+        
+        let decode_options = DecodeOptions::new().allow_trailing_comma(true);
+        for encoded in [encode_with_options(&value, &options).unwrap(), pretty] {
+            assert_eq!(decode_with_options(&encoded, &decode_options).unwrap(), value);
+        }
+        
+        let chunks: Vec<String> = EncodeChunks::new(value.clone(), options).collect::<Result<_, _>>().unwrap();
+        assert_eq!(chunks.concat(), encode_with_options(&value, &options).unwrap());
+        
+        // Single-line arrays are unaffected
+        let flat = crate::decoder::decode("[1, 2]").unwrap();
+        assert_eq!(encode_with_options(&flat, &options.pretty(true)).unwrap(), "[1, 2]");
     }
     
     #[test]
@@ -355,12 +1417,812 @@ mod tests {
         let expected2 = "[\"id\", \"name\"]\n1, Alice\n2, Bob";
         let expected3 = "[\"id\", \"name\"]\n1,Alice\n2,Bob";
         let expected4 = "[\"name\", \"id\"]\n\"Alice\", 1\n\"Bob\", 2";
+        let expected5 = "[id, name]\n1, Alice\n2, Bob";
         
         assert!(
             result == expected1 || 
             result == expected2 || 
             result == expected3 ||
-            result == expected4
+            result == expected4 ||
+            result == expected5
+        );
+    }
+    
+    #[test]
+    fn test_encode_tabular_only_where_rows_fit() {
+        use crate::decoder::{decode, decode_document};
+        
+        // Tabular rows need whole lines, so nested arrays of records stay inline
+        for (input, expected) in [
+            ("{a: [{x: 1}, {x: 2}]}", "a: [x]\n1\n2"),
+            ("{a: {b: [{x: 1}, {x: 2}]}}", "a: {b: [{x: 1}, {x: 2}]}"),
+            ("[[{x: 1}, {x: 2}], 3]", "[\n  [{x: 1}, {x: 2}],\n  3\n]"),
+        ] {
+            let value = decode(input).unwrap();
+            let encoded = encode(&value).unwrap();
+            assert_eq!(encoded, expected);
+            assert_eq!(decode_document(&encoded).unwrap(), value);
+        }
+    }
+    
+    #[test]
+    fn test_encode_expand_empty() {
+        let empty_arr = ToonValue::Array(vec![]);
+        let empty_obj = ToonValue::Object(HashMap::new());
+        
+        assert_eq!(encode(&empty_arr).unwrap(), "[]");
+        assert_eq!(encode(&empty_obj).unwrap(), "{}");
+        
+        let options = EncodeOptions::new().pretty(true).expand_empty(true);
+        assert_eq!(encode_with_options(&empty_arr, &options).unwrap(), "[\n]");
+        assert_eq!(encode_with_options(&empty_obj, &options).unwrap(), "{\n}");
+        
+        let mut map = HashMap::new();
+        map.insert("items".to_string(), empty_arr);
+        assert_eq!(
+            encode_with_options(&ToonValue::Object(map), &options).unwrap(),
+            "items: [\n]"
+        );
+    }
+    
+    #[test]
+    fn test_encode_with_key_order() {
+        let keys = ["zeta", "alpha", "mid", "beta"];
+        let mut inner = HashMap::new();
+        inner.insert("y".to_string(), ToonValue::Integer(2));
+        inner.insert("x".to_string(), ToonValue::Integer(1));
+        let mut outer = HashMap::new();
+        for key in keys {
+            outer.insert(key.to_string(), ToonValue::Integer(0));
+        }
+        outer.insert("alpha".to_string(), ToonValue::Object(inner));
+        
+        let order = KeyOrder::Object(vec![
+            ("zeta".to_string(), KeyOrder::None),
+            (
+                "alpha".to_string(),
+                KeyOrder::Object(vec![
+                    ("y".to_string(), KeyOrder::None),
+                    ("x".to_string(), KeyOrder::None),
+                ]),
+            ),
+            ("mid".to_string(), KeyOrder::None),
+            ("beta".to_string(), KeyOrder::None),
+        ]);
+        
+        let result = encode_with_key_order(
+            &ToonValue::Object(outer),
+            &EncodeOptions::default(),
+            &order,
+        )
+        .unwrap();
+        assert_eq!(result, "zeta: 0\nalpha: {y: 2, x: 1}\nmid: 0\nbeta: 0");
+    }
+    
+    #[test]
+    fn test_encode_escape_forward_slash() {
+        let value = ToonValue::String("</script> a\\b".to_string());
+        assert_eq!(encode(&value).unwrap(), "\"</script> a\\\\b\"");
+        
+        let options = EncodeOptions::new().escape_forward_slash(true);
+        let encoded = encode_with_options(&value, &options).unwrap();
+        assert_eq!(encoded, "\"<\\/script> a\\\\b\"");
+        assert!(!encoded.contains("</"));
+        assert_eq!(crate::decoder::decode(&encoded).unwrap(), value);
+    }
+    
+    #[test]
+    fn test_encode_custom_escape_table() {
+        use crate::types::EscapeTable;
+        
+        let value = ToonValue::String("<b>bold</b>".to_string());
+        let table = EscapeTable::new().with('<').with('>');
+        let options = EncodeOptions::new().escape_table(table).escape_forward_slash(true);
+        
+        let encoded = encode_with_options(&value, &options).unwrap();
+        assert_eq!(encoded, "\"\\u003cb\\u003ebold\\u003c\\/b\\u003e\"");
+        assert_eq!(crate::decoder::decode(&encoded).unwrap(), value);
+    }
+    
+    #[test]
+    fn test_encode_max_output_len() {
+        let arr = ToonValue::Array((0..10_000).map(ToonValue::Integer).collect());
+        
+        let options = EncodeOptions::new().max_output_len(Some(100));
+        match encode_with_options(&arr, &options) {
+            Err(ToonError::Serialization(msg)) => assert_eq!(msg, "output too large"),
+            other => panic!("expected a size error, got {:?}", other),
+        }
+        
+        // The cap is inclusive, and no cap means unlimited output
+        let small = ToonValue::Array(vec![ToonValue::Integer(1), ToonValue::Integer(2)]);
+        let options = EncodeOptions::new().max_output_len(Some(6));
+        assert_eq!(encode_with_options(&small, &options).unwrap(), "[1, 2]");
+        assert!(encode(&arr).unwrap().len() > 100);
+    }
+    
+    #[test]
+    fn test_encode_tabular_keeps_nested_fields() {
+        // A single object with a nested field must not go tabular and lose it
+        let value = crate::decoder::decode("[{id: 1, tags: [a, b]}]").unwrap();
+        let encoded = encode(&value).unwrap();
+        assert!(encoded.contains("tags"));
+        assert_eq!(crate::decoder::decode(&encoded).unwrap(), value);
+        
+        // Likewise when only the first object has the nested field
+        let value = crate::decoder::decode("[{id: 1, meta: {x: 1}}, {id: 2}]").unwrap();
+        assert!(encode(&value).unwrap().contains("meta"));
+    }
+    
+    #[test]
+    fn test_encode_tabular_fill_missing() {
+        let value = crate::decoder::decode("[{a: 1, b: x}, {a: 2}, {}]").unwrap();
+        
+        // Exact field sets are required by default
+        assert!(!encode(&value).unwrap().starts_with("[a, b]"));
+        
+        let options = EncodeOptions::new().tabular_fill_missing(true);
+        assert_eq!(
+            encode_with_options(&value, &options).unwrap(),
+            "[a, b]\n1, x\n2, null\nnull, null"
+        );
+        
+        // Nested values still disqualify the array, as do non-objects
+        let nested = crate::decoder::decode("[{a: 1}, {b: [1]}]").unwrap();
+        assert!(!encode_with_options(&nested, &options).unwrap().starts_with("[a, b]"));
+        let mixed = crate::decoder::decode("[{a: 1}, 2]").unwrap();
+        assert_eq!(encode_with_options(&mixed, &options).unwrap(), "[\n  {a: 1},\n  2\n]");
+        
+        // All-empty objects have no columns to write
+        let empty = crate::decoder::decode("[{}, {}]").unwrap();
+        assert_eq!(encode_with_options(&empty, &options).unwrap(), "[\n  {},\n  {}\n]");
+    }
+    
+    #[test]
+    fn test_encode_tabular_max_cols() {
+        let options = EncodeOptions::new().tabular_max_cols(Some(4));
+        
+        // Narrow tables with many rows stay tabular
+        let narrow = ToonValue::Array(
+            (0..50)
+                .map(|i| crate::decoder::decode(&format!("{{id: {}, ok: true}}", i)).unwrap())
+                .collect(),
+        );
+        let encoded = encode_with_options(&narrow, &options).unwrap();
+        assert!(encoded.starts_with("[id, ok]\n0, true\n1, true\n"), "{}", encoded);
+        assert_eq!(encoded.lines().count(), 51);
+        
+        // Wide ones are written one object per line
+        let row = |n: i64| {
+            ToonValue::Object((0..20).map(|i| (format!("f{:02}", i), ToonValue::Integer(n))).collect())
+        };
+        let wide = ToonValue::Array(vec![row(1), row(2)]);
+        let encoded = encode_with_options(&wide, &options).unwrap();
+        assert!(encoded.starts_with("[\n  {"), "{}", encoded);
+        assert_eq!(crate::decoder::decode(&encoded).unwrap(), wide);
+        assert!(encode(&wide).unwrap().starts_with("[f00, f01,"));
+        
+        // The limit is inclusive
+        let options = EncodeOptions::new().tabular_max_cols(Some(20));
+        assert!(encode_with_options(&wide, &options).unwrap().starts_with("[f00, f01,"));
+    }
+    
+    #[test]
+    fn test_encode_tabular_field_order() {
+        let value = crate::decoder::decode("[{name: Ann, id: 1, age: 30}, {age: 41, id: 2, name: Bob}]").unwrap();
+        let row_order = |keys: &[&str]| {
+            KeyOrder::Object(keys.iter().map(|k| (k.to_string(), KeyOrder::None)).collect())
+        };
+        let order = KeyOrder::Array(vec![row_order(&["name", "id", "age"]), row_order(&["age", "id", "name"])]);
+        
+        // Sorted by default, even with a captured order
+        let sorted = encode_with_key_order(&value, &EncodeOptions::new(), &order).unwrap();
+        assert_eq!(sorted, "[age, id, name]\n30, 1, Ann\n41, 2, Bob");
+        
+        // FirstSeen follows the first object's key order
+        let options = EncodeOptions::new().tabular_field_order(TabularFieldOrder::FirstSeen);
+        let first_seen = encode_with_key_order(&value, &options, &order).unwrap();
+        assert_eq!(first_seen, "[name, id, age]\nAnn, 1, 30\nBob, 2, 41");
+        
+        // With missing fields, later objects append their new keys in order
+        let value = crate::decoder::decode("[{id: 1}, {note: x, id: 2, extra: y}]").unwrap();
+        let order = KeyOrder::Array(vec![row_order(&["id"]), row_order(&["note", "id", "extra"])]);
+        let options = options.tabular_fill_missing(true);
+        let first_seen = encode_with_key_order(&value, &options, &order).unwrap();
+        assert_eq!(first_seen, "[id, note, extra]\n1, null, null\n2, x, y");
+    }
+    
+    #[test]
+    fn test_encode_line_ending() {
+        use crate::types::LineEnding;
+        
+        let options = EncodeOptions::new().line_ending(LineEnding::CrLf);
+        let rows = crate::decoder::decode("[{id: 1, note: \"a\\nb\"}, {id: 2, note: c}]").unwrap();
+        let encoded = encode_with_options(&rows, &options).unwrap();
+        assert_eq!(encoded, "[id, note]\r\n1, \"a\\nb\"\r\n2, c");
+        assert_eq!(crate::decoder::decode_tabular(&encoded).unwrap(), rows);
+        
+        let nested = crate::decoder::decode("[[1], {a: 2}]").unwrap();
+        let pretty = encode_with_options(&nested, &options.pretty(true)).unwrap();
+        assert_eq!(pretty, "[\r\n  [1],\r\n  {a: 2}\r\n]");
+        
+        // Chunks and the output limit see the same CRLF output
+        let chunks: String = EncodeChunks::new(nested.clone(), options).map(Result::unwrap).collect();
+        assert_eq!(chunks, encode_with_options(&nested, &options).unwrap());
+        let limited = options.max_output_len(Some(pretty.len() - 1));
+        assert!(encode_with_options(&nested, &limited.pretty(true)).is_err());
+        assert!(encode_with_options(&nested, &limited.max_output_len(Some(pretty.len())).pretty(true)).is_ok());
+    }
+    
+    #[test]
+    fn test_to_json_string() {
+        let value = crate::decoder::decode("{b: [1, 2.5, null], a: {t: true, s: \"x y\"}}").unwrap();
+        assert_eq!(
+            to_json_string(&value, None).unwrap(),
+            "{\"a\":{\"s\":\"x y\",\"t\":true},\"b\":[1,2.5,null]}"
+        );
+        assert_eq!(
+            to_json_string(&ToonValue::Array(vec![ToonValue::Integer(1)]), Some(4)).unwrap(),
+            "[\n    1\n]"
+        );
+        assert!(to_json_string(&ToonValue::Number(f64::NAN), None).is_err());
+    }
+    
+    #[test]
+    fn test_to_yaml_string() {
+        let value = crate::decoder::decode(r#"{
+            "name": "John",
+            "age": 30,
+            "address": {
+                "street": "123 Main St",
+                "city": "Anytown"
+            },
+            "hobbies": ["reading", "swimming", "coding"]
+        }"#)
+        .unwrap();
+        
+        assert_eq!(
+            to_yaml_string(&value),
+            "address:\n  city: Anytown\n  street: \"123 Main St\"\nage: 30\n\
+             hobbies:\n  - reading\n  - swimming\n  - coding\nname: John"
+        );
+        
+        // Sequences of containers use the compact `- ` form
+        let value = crate::decoder::decode("[{id: 1, tags: [a]}, [1, [2]], [], {}]").unwrap();
+        assert_eq!(
+            to_yaml_string(&value),
+            "- id: 1\n  tags:\n    - a\n- - 1\n  - - 2\n- []\n- {}"
+        );
+    }
+    
+    #[test]
+    fn test_to_yaml_string_quoting() {
+        for (s, yaml) in [
+            ("plain text", "plain text"),
+            ("yes", "\"yes\""),
+            ("No", "\"No\""),
+            ("null", "\"null\""),
+            ("42", "\"42\""),
+            ("-1", "\"-1\""),
+            ("", "\"\""),
+            ("a: b", "\"a: b\""),
+            ("# not a comment", "\"# not a comment\""),
+            ("line\nbreak", "\"line\\nbreak\""),
+            ("trailing ", "\"trailing \""),
+        ] {
+            assert_eq!(to_yaml_string(&ToonValue::String(s.to_string())), yaml, "{:?}", s);
+        }
+        
+        assert_eq!(to_yaml_string(&ToonValue::Number(f64::NEG_INFINITY)), "-.inf");
+        assert_eq!(to_yaml_string(&ToonValue::Null), "null");
+    }
+    
+    #[test]
+    fn test_encode_colon_spacing() {
+        use crate::types::ColonSpacing;
+        
+        let value = crate::decoder::decode("{a: {b: 1}}").unwrap();
+        for (spacing, expected) in [
+            (ColonSpacing::None, "a:{b:1}"),
+            (ColonSpacing::After, "a: {b: 1}"),
+            (ColonSpacing::Around, "a : {b : 1}"),
+        ] {
+            let options = EncodeOptions::new().colon_spacing(spacing);
+            let encoded = encode_with_options(&value, &options).unwrap();
+            assert_eq!(encoded, expected);
+            
+            // Nested objects still decode whatever the spacing
+            let nested = crate::decoder::decode(&encoded["a".len() + spacing.separator().len()..]).unwrap();
+            assert_eq!(nested, value["a"]);
+        }
+        
+        let value = crate::decoder::decode("{\"a b\": 1}").unwrap();
+        let options = EncodeOptions::new().colon_spacing(ColonSpacing::None);
+        assert_eq!(encode_with_options(&value, &options).unwrap(), "\"a b\":1");
+    }
+    
+    #[test]
+    fn test_encode_scientific_threshold() {
+        let options = EncodeOptions::new().scientific_threshold(Some((1e-3, 1e6)));
+        for (n, expected) in [
+            (999999.0, "999999"),
+            (1e6, "1e6"),
+            (-2.5e20, "-2.5e20"),
+            (0.001, "0.001"),
+            (0.000999, "9.99e-4"),
+            (-1e-10, "-1e-10"),
+            (0.0, "0"),
+        ] {
+            let encoded = encode_with_options(&ToonValue::Number(n), &options).unwrap();
+            assert_eq!(encoded, expected);
+            assert_eq!(crate::decoder::decode(&encoded).unwrap().as_number(), Some(n));
+        }
+        
+        // Integers and non-finite values are unaffected; the default is off
+        let big = ToonValue::Integer(10_000_000);
+        assert_eq!(encode_with_options(&big, &options).unwrap(), "10000000");
+        assert_eq!(encode_with_options(&ToonValue::Number(f64::INFINITY), &options).unwrap(), "inf");
+        assert_eq!(encode(&ToonValue::Number(1e20)).unwrap(), "100000000000000000000");
+    }
+    
+    #[test]
+    fn test_encode_to_writer() {
+        let value = crate::decoder::decode("[{id: 1, name: Ann}, {id: 2, name: Bob}]").unwrap();
+        let mut buffer = Vec::new();
+        encode_to_writer(&value, &EncodeOptions::default(), &mut buffer).unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(), encode(&value).unwrap());
+        
+        // The size cap applies to writers too
+        let options = EncodeOptions::new().max_output_len(Some(4));
+        let err = encode_to_writer(&value, &options, Vec::new()).unwrap_err();
+        assert!(matches!(err, ToonError::Serialization(_)));
+        
+        // I/O errors are passed through as such
+        let mut full = [0u8; 4];
+        let err = encode_to_writer(&value, &EncodeOptions::default(), &mut full[..]).unwrap_err();
+        assert!(matches!(err, ToonError::Io(_)));
+    }
+    
+    #[test]
+    fn test_encode_tabular_disabled() {
+        let value = crate::decoder::decode("{rows: [{id: 1}, {id: 2}]}").unwrap();
+        assert_eq!(encode(&value).unwrap(), "rows: [id]\n1\n2");
+        
+        let options = EncodeOptions::new().tabular(false);
+        let encoded = encode_with_options(&value, &options).unwrap();
+        assert_eq!(encoded, "rows: [{id: 1}, {id: 2}]");
+        assert_eq!(crate::decoder::decode(&format!("{{{}}}", encoded)).unwrap(), value);
+    }
+    
+    #[test]
+    fn test_encode_tabular_header_styles() {
+        let value = crate::decoder::decode("[{id: 1, name: Alice}, {id: 2, name: \"Bob B\"}]").unwrap();
+        assert_eq!(encode(&value).unwrap(), "[id, name]\n1, Alice\n2, \"Bob B\"");
+        
+        let options = EncodeOptions::new().tabular_bracket_header(false);
+        assert_eq!(
+            encode_with_options(&value, &options).unwrap(),
+            "id, name\n1, Alice\n2, \"Bob B\""
+        );
+    }
+    
+    #[test]
+    fn test_encode_pretty_object_arrays() {
+        let value = crate::decoder::decode("{rows: [{a: 1}, {a: 3}]}").unwrap();
+        let options = EncodeOptions::new().pretty(true).tabular(false);
+        assert_eq!(
+            encode_with_options(&value, &options).unwrap(),
+            "rows: [\n  {a: 1},\n  {a: 3}\n]"
+        );
+        
+        // Without pretty, nested arrays of objects stay on one line
+        let compact = EncodeOptions::new().tabular(false);
+        assert_eq!(
+            encode_with_options(&value, &compact).unwrap(),
+            "rows: [{a: 1}, {a: 3}]"
+        );
+        
+        // Nested arrays indent one level further than their parent item
+        let value = crate::decoder::decode("[[{a: 1}, {a: 2}], 3]").unwrap();
+        assert_eq!(
+            encode_with_options(&value, &options).unwrap(),
+            "[\n  [\n    {a: 1},\n    {a: 2}\n  ],\n  3\n]"
+        );
+        let options = options.indent(4);
+        assert_eq!(
+            encode_with_options(&value, &options).unwrap(),
+            "[\n    [\n        {a: 1},\n        {a: 2}\n    ],\n    3\n]"
+        );
+    }
+    
+    #[test]
+    fn test_to_query_string() {
+        let value = crate::decoder::decode(
+            "{q: \"rust & toon\", page: 2, tag: [a, \"b/c\"], debug: true, empty: null, \"ünï\": \"100%\"}",
+        )
+        .unwrap();
+        assert_eq!(
+            to_query_string(&value).unwrap(),
+            "debug=true&empty=&page=2&q=rust%20%26%20toon&tag=a&tag=b%2Fc&%C3%BCn%C3%AF=100%25"
+        );
+        
+        assert_eq!(to_query_string(&crate::decoder::decode("{}").unwrap()).unwrap(), "");
+        assert!(to_query_string(&crate::decoder::decode("{a: {b: 1}}").unwrap()).is_err());
+        assert!(to_query_string(&crate::decoder::decode("{a: [[1]]}").unwrap()).is_err());
+        assert!(to_query_string(&ToonValue::Integer(1)).is_err());
+    }
+    
+    #[test]
+    fn test_minimize_quotes() {
+        let mut labels = HashMap::new();
+        labels.insert("true".to_string(), ToonValue::String("yes".to_string()));
+        labels.insert("false".to_string(), ToonValue::String("no".to_string()));
+        labels.insert("null".to_string(), ToonValue::String("unknown".to_string()));
+        let mut config = HashMap::new();
+        config.insert("labels".to_string(), ToonValue::Object(labels));
+        config.insert("timeout".to_string(), ToonValue::String("infinity".to_string()));
+        config.insert("retries".to_string(), ToonValue::String("inf".to_string()));
+        config.insert("mode".to_string(), ToonValue::String("strict".to_string()));
+        let value = ToonValue::Array(vec![ToonValue::Object(config)]);
+        
+        let quoted = encode(&value).unwrap();
+        let minimal = encode_with_options(&value, &EncodeOptions::new().minimize_quotes(true)).unwrap();
+        assert_eq!(quoted.matches('"').count(), 10);
+        // Only the value `no` still needs quotes, as the decoder reads a bare
+        // word starting with `n` as `null`
+        assert_eq!(minimal.matches('"').count(), 2);
+        assert!(minimal.contains("\"no\""));
+        assert!(minimal.len() < quoted.len());
+        
+        assert_eq!(crate::decoder::decode(&minimal).unwrap(), value);
+    }
+    
+    #[test]
+    fn test_to_toml_string() {
+        let config = crate::decoder::decode(
+            "{title: \"My App\", ratio: 0.5, timeout: 30.0, tags: [web, api], \
+             server: {host: localhost, port: 8080, tls: {enabled: true}}, \
+             users: [{name: ann, admin: true}, {name: bob, admin: false}], \
+             \"log level\": {default: warn, overrides: [{module: db, level: debug}, 3]}}",
+        )
+        .unwrap();
+        let expected = "ratio = 0.5\n\
+                        tags = [\"web\", \"api\"]\n\
+                        timeout = 30.0\n\
+                        title = \"My App\"\n\
+                        \n\
+                        [\"log level\"]\n\
+                        default = \"warn\"\n\
+                        overrides = [{ level = \"debug\", module = \"db\" }, 3]\n\
+                        \n\
+                        [server]\n\
+                        host = \"localhost\"\n\
+                        port = 8080\n\
+                        \n\
+                        [server.tls]\n\
+                        enabled = true\n\
+                        \n\
+                        [[users]]\n\
+                        admin = true\n\
+                        name = \"ann\"\n\
+                        \n\
+                        [[users]]\n\
+                        admin = false\n\
+                        name = \"bob\"";
+        assert_eq!(to_toml_string(&config).unwrap(), expected);
+        
+        let value = crate::decoder::decode("{\"a\\\"b\": \"line\\nbreak\"}").unwrap();
+        assert_eq!(to_toml_string(&value).unwrap(), "\"a\\\"b\" = \"line\\nbreak\"");
+        
+        assert!(to_toml_string(&crate::decoder::decode("[1, 2]").unwrap()).is_err());
+        assert!(to_toml_string(&crate::decoder::decode("{a: {b: [1, null]}}").unwrap()).is_err());
+    }
+    
+    #[test]
+    fn test_encode_chunks() {
+        let inputs = [
+            ("{name: Ann, tags: [a, b], address: {city: Oslo}, rows: [{id: 1}, {id: 2}]}", 4),
+            ("[{a: [1, {b: 2}]}, [3, 4], x]", 5),
+            ("[{id: 1}, {id: 2}]", 1),
+            ("[1, 2]", 1),
+            ("{}", 1),
+            ("42", 1),
+        ];
+        for (input, count) in inputs {
+            let value = crate::decoder::decode(input).unwrap();
+            for options in [EncodeOptions::new(), EncodeOptions::new().pretty(true).tabular(false)] {
+                let chunks: Vec<String> = EncodeChunks::new(value.clone(), options)
+                    .collect::<Result<_, _>>()
+                    .unwrap();
+                assert_eq!(chunks.concat(), encode_with_options(&value, &options).unwrap(), "{}", input);
+                if !options.pretty {
+                    assert_eq!(chunks.len(), count, "{}", input);
+                }
+            }
+        }
+        
+        // The output limit applies to all chunks together
+        let value = crate::decoder::decode("{a: 1, b: 2, c: 3}").unwrap();
+        let options = EncodeOptions::new().max_output_len(Some(10));
+        let results: Vec<_> = EncodeChunks::new(value, options).collect();
+        assert_eq!(results.len(), 3);
+        assert!(results[..2].iter().all(Result::is_ok));
+        assert!(results[2].is_err());
+    }
+    
+    #[test]
+    #[ignore]
+    fn bench_encode_strings() {
+        use std::time::Instant;
+        
+        let row = ToonValue::Array(
+            (0..8)
+                .map(|i| ToonValue::String(format!("entry {} of a string-heavy payload, no escapes", i)))
+                .collect(),
+        );
+        let value = ToonValue::Array(vec![row; 2000]);
+        let iterations = 100;
+        
+        let start = Instant::now();
+        for _ in 0..iterations {
+            std::hint::black_box(encode(&value).unwrap());
+        }
+        println!("encode: {:?}", start.elapsed() / iterations);
+    }
+    
+    #[test]
+    fn test_to_xml_string() {
+        let doc = crate::decoder::decode(
+            "{user: {id: 7, name: \"Ann & Bob <3\", tags: [admin, dev], \
+             address: {city: Paris, zip: null}, scores: [[1, 2], []], roles: [], prefs: {}}}",
+        )
+        .unwrap();
+        let expected = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                        <doc>\n\
+                        \x20 <user>\n\
+                        \x20   <address>\n\
+                        \x20     <city>Paris</city>\n\
+                        \x20     <zip/>\n\
+                        \x20   </address>\n\
+                        \x20   <id>7</id>\n\
+                        \x20   <name>Ann &amp; Bob &lt;3</name>\n\
+                        \x20   <prefs/>\n\
+                        \x20   <scores>\n\
+                        \x20     <item>1</item>\n\
+                        \x20     <item>2</item>\n\
+                        \x20   </scores>\n\
+                        \x20   <scores/>\n\
+                        \x20   <tags>admin</tags>\n\
+                        \x20   <tags>dev</tags>\n\
+                        \x20 </user>\n\
+                        </doc>";
+        assert_eq!(to_xml_string(&doc, "doc").unwrap(), expected);
+        
+        let list = crate::decoder::decode("[1, 2.5, true]").unwrap();
+        assert_eq!(
+            to_xml_string(&list, "values").unwrap(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <values>\n  <item>1</item>\n  <item>2.5</item>\n  <item>true</item>\n</values>"
         );
+        
+        let bad_key = crate::decoder::decode("{\"first name\": x}").unwrap();
+        let err = to_xml_string(&bad_key, "doc").unwrap_err().to_string();
+        assert!(err.contains("'first name' is not a valid XML element name"), "{}", err);
+        assert!(to_xml_string(&ToonValue::Null, "1doc").is_err());
+        assert!(to_xml_string(&ToonValue::String("bell\x07".to_string()), "doc").is_err());
+    }
+    
+    #[test]
+    fn test_encode_block_strings() {
+        use crate::decoder::decode_with_options;
+        use crate::types::DecodeOptions;
+        
+        let text = "Dear END user,\n\n  \"quoted\", # kept\nEND1 is taken too\n";
+        let mut map = HashMap::new();
+        map.insert("body".to_string(), ToonValue::String(text.to_string()));
+        let value = ToonValue::Array(vec![
+            ToonValue::Object(map),
+            ToonValue::String("one line".to_string()),
+            ToonValue::String("a\nb".to_string()),
+        ]);
+        
+        let options = EncodeOptions::new().block_strings(true);
+        let encoded = encode_with_options(&value, &options).unwrap();
+        assert!(encoded.contains("body: <<END2\nDear END user,\n"), "{}", encoded);
+        assert!(encoded.contains("\"one line\""), "{}", encoded);
+        assert!(encoded.ends_with("<<END\na\nb\nEND\n]"), "{}", encoded);
+        
+        let decode_options = DecodeOptions::new().allow_heredoc(true);
+        assert_eq!(decode_with_options(&encoded, &decode_options).unwrap(), value);
+        let pretty = encode_with_options(&value, &options.pretty(true)).unwrap();
+        assert_eq!(decode_with_options(&pretty, &decode_options).unwrap(), value);
+        
+        // Carriage returns wouldn't survive a block, so those stay quoted
+        let crlf = ToonValue::String("a\r\nb".to_string());
+        assert_eq!(encode_with_options(&crlf, &options).unwrap(), "\"a\\r\\nb\"");
+        
+        // Under CRLF line endings the block would come back with `\r\n`
+        // breaks, so the strings stay quoted and round-trip unchanged
+        let crlf_options = options.pretty(true).line_ending(LineEnding::CrLf);
+        let encoded = encode_with_options(&value, &crlf_options).unwrap();
+        assert!(!encoded.contains("<<END"), "{}", encoded);
+        assert!(encoded.contains("\"a\\nb\""), "{}", encoded);
+        assert_eq!(decode_with_options(&encoded, &decode_options).unwrap(), value);
+        
+        // Arrays of objects holding a block string are not written as tables
+        let rows: Vec<ToonValue> = ["a\nb", "c"]
+            .iter()
+            .map(|body| {
+                let mut map = HashMap::new();
+                map.insert("body".to_string(), ToonValue::String(body.to_string()));
+                ToonValue::Object(map)
+            })
+            .collect();
+        let rows = ToonValue::Array(rows);
+        let encoded = encode_with_options(&rows, &options).unwrap();
+        assert_eq!(encoded, "[\n  {body: <<END\na\nb\nEND},\n  {body: c}\n]");
+        assert_eq!(decode_with_options(&encoded, &decode_options).unwrap(), rows);
+        let single_line = ToonValue::Array(vec![rows[1].clone(), rows[1].clone()]);
+        assert_eq!(encode_with_options(&single_line, &options).unwrap(), "[body]\nc\nc");
+    }
+    
+    #[test]
+    fn test_encode_coerce_whole_floats_to_int() {
+        let value = ToonValue::Array(vec![
+            ToonValue::Number(5.0),
+            ToonValue::Number(5.5),
+            ToonValue::Integer(5),
+            ToonValue::Number(-0.0),
+            ToonValue::Number(f64::INFINITY),
+        ]);
+        assert_eq!(encode(&value).unwrap(), "[5, 5.5, 5, -0, inf]");
+        
+        let options = EncodeOptions::new().coerce_whole_floats_to_int(false);
+        let encoded = encode_with_options(&value, &options).unwrap();
+        assert_eq!(encoded, "[5.0, 5.5, 5, -0.0, inf]");
+        
+        // Keeping the `.0` preserves the float type through a roundtrip
+        let mixed = ToonValue::Array(vec![ToonValue::Number(5.0), ToonValue::Integer(5)]);
+        let encoded = encode_with_options(&mixed, &options).unwrap();
+        assert_eq!(crate::decoder::decode(&encoded).unwrap(), mixed);
+        
+        // Scientific notation takes precedence for out-of-range magnitudes
+        let options = options.scientific_threshold(Some((1e-3, 1e6)));
+        assert_eq!(encode_with_options(&ToonValue::Number(2e7), &options).unwrap(), "2e7");
+    }
+}
+
+#[cfg(test)]
+mod numeric_string_roundtrip {
+    use super::*;
+    use crate::decoder::decode;
+    
+    /// Strings a reader (or the decoder) could mistake for numbers or keywords
+    const EDGE_STRINGS: &[&str] = &[
+        "0", "-0", "+1", "42", "007", "1_000", "0x10", "0b1", "0o7", ".5", "1.", "-.5",
+        "1.5e3", "1e5", "1E-5", "-1e+5", "NaN", "nan", "inf", "-inf", "+inf", "Infinity",
+        "-Infinity", "infinity", "true", "false", "null", "True", "NULL", "1,000", "1 000",
+        " 1", "1 ", "-", "+", ".", "e5", "E", "¹", "١٢٣",
+    ];
+    
+    #[test]
+    fn test_edge_strings_roundtrip() {
+        for s in EDGE_STRINGS {
+            let value = ToonValue::String(s.to_string());
+            let encoded = encode(&value).unwrap();
+            assert_eq!(decode(&encoded).unwrap(), value, "{:?} encoded as {}", s, encoded);
+        }
+    }
+    
+    #[test]
+    fn test_numeric_looking_strings_are_quoted() {
+        for s in EDGE_STRINGS.iter().filter(|s| s.starts_with(|c: char| !c.is_alphabetic())) {
+            let encoded = encode(&ToonValue::String(s.to_string())).unwrap();
+            assert!(encoded.starts_with('"'), "{:?} encoded as {}", s, encoded);
+        }
+        
+        // Reserved words are quoted too, in any position
+        for s in ["nan", "inf", "true", "false", "null"] {
+            let value = ToonValue::Array(vec![ToonValue::String(s.to_string())]);
+            assert_eq!(encode(&value).unwrap(), format!("[\"{}\"]", s));
+        }
+    }
+    
+    #[test]
+    fn test_edge_strings_roundtrip_as_keys() {
+        let map = EDGE_STRINGS
+            .iter()
+            .map(|s| (s.to_string(), ToonValue::String(s.to_string())))
+            .collect();
+        let value = ToonValue::Array(vec![ToonValue::Object(map)]);
+        let options = EncodeOptions::new().tabular(false);
+        let encoded = encode_with_options(&value, &options).unwrap();
+        assert_eq!(decode(&encoded).unwrap(), value);
+    }
+}
+
+#[cfg(test)]
+mod zero_padded_ids {
+    use super::*;
+    use crate::decoder::{decode, decode_tabular};
+    
+    /// Numeric-looking IDs whose leading zeros or signs would be lost if
+    /// they were ever written bare and read back as numbers
+    const IDS: &[&str] = &[
+        "00042", "0", "00", "007", "-007", "+0042", "-0", "0.50", "00042.0", "0e5",
+        "0001234567890123456789012345678901234567890", "0042-17", "000_1",
+    ];
+    
+    fn ids() -> impl Iterator<Item = ToonValue> {
+        IDS.iter().map(|id| ToonValue::String(id.to_string()))
+    }
+    
+    #[test]
+    fn test_ids_are_quoted() {
+        for options in [EncodeOptions::new(), EncodeOptions::new().minimize_quotes(true)] {
+            for id in ids() {
+                let encoded = encode_with_options(&id, &options).unwrap();
+                assert!(encoded.starts_with('"'), "{} encoded as {}", id, encoded);
+            }
+        }
+    }
+    
+    fn records(with_arrays: bool) -> ToonValue {
+        ToonValue::Array(
+            ids()
+                .enumerate()
+                .map(|(i, id)| {
+                    let mut map = HashMap::new();
+                    if with_arrays {
+                        map.insert("ids".to_string(), ToonValue::Array(vec![id.clone(), id.clone()]));
+                    }
+                    map.insert("id".to_string(), id);
+                    map.insert("n".to_string(), ToonValue::Integer(i as i64));
+                    ToonValue::Object(map)
+                })
+                .collect(),
+        )
+    }
+    
+    #[test]
+    fn test_ids_roundtrip() {
+        let keyed = ToonValue::Array(vec![ToonValue::Object(
+            ids().map(|id| (id.as_str().unwrap().to_string(), id)).collect(),
+        )]);
+        let option_sets = [
+            EncodeOptions::new().tabular(false),
+            EncodeOptions::new().tabular(false).pretty(true),
+            EncodeOptions::new().tabular(false).minimize_quotes(true),
+            EncodeOptions::new().tabular(false).line_ending(LineEnding::CrLf),
+        ];
+        for options in &option_sets {
+            for value in [&records(true), &keyed, &ToonValue::Array(ids().collect())] {
+                let encoded = encode_with_options(value, options).unwrap();
+                assert_eq!(&decode(&encoded).unwrap(), value, "{:?}: {}", options, encoded);
+            }
+        }
+    }
+    
+    #[test]
+    fn test_ids_roundtrip_tabular() {
+        let rows = records(false);
+        for options in [
+            EncodeOptions::new(),
+            EncodeOptions::new().tabular_bracket_header(false),
+            EncodeOptions::new().minimize_quotes(true),
+        ] {
+            let encoded = encode_with_options(&rows, &options).unwrap();
+            assert!(encoded.contains("\"00042\""), "{}", encoded);
+            assert_eq!(decode_tabular(&encoded).unwrap(), rows, "{}", encoded);
+        }
+    }
+    
+    #[test]
+    fn test_ids_survive_numbers_as_strings_decoding() {
+        // Quoted IDs aren't numbers, so this option leaves them alone
+        let options = crate::types::DecodeOptions::new().numbers_as_strings(true);
+        let value = ToonValue::Array(ids().collect());
+        let encoded = encode(&value).unwrap();
+        assert_eq!(crate::decoder::decode_with_options(&encoded, &options).unwrap(), value);
     }
 }
@@ -2,9 +2,10 @@
 //! 
 //! A high-performance implementation of the TOON format in Rust with Python bindings.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
 use pyo3::prelude::*;
-use pyo3::types::{PyDict, PyList};
+use pyo3::types::{PyBool, PyDict, PyFloat, PyList, PyLong, PyString, PyType};
 use thiserror::Error;
 
 // Re-export public API
@@ -13,7 +14,7 @@ pub mod decoder;
 pub mod utils;
 pub mod types;
 
-use types::ToonValue;
+use types::{DecodeOptions, EncodeOptions, KeyOrder, ToonValue};
 
 /// Error type for TOON encoding/decoding operations
 #[derive(Error, Debug)]
@@ -43,32 +44,108 @@ impl From<std::fmt::Error> for ToonError {
 /// PyO3 Result type
 type PyResult<T> = Result<T, PyErr>;
 
-/// Convert a Python object to a Rust ToonValue
-fn py_to_toon_value(obj: &PyAny) -> PyResult<ToonValue> {
+/// Convert a Python object to a Rust ToonValue, capturing dict key order
+fn py_to_toon_value_ordered(obj: &PyAny) -> PyResult<(ToonValue, KeyOrder)> {
+    py_to_toon_value_visiting(obj, &mut HashSet::new(), false, false)
+}
+
+/// Like `py_to_toon_value_ordered`, but raising `ValueError` for ints
+/// outside the i64 range instead of widening them to floats, for helpers
+/// that hand back a modified copy of their argument
+fn py_to_toon_value_exact(obj: &PyAny) -> PyResult<(ToonValue, KeyOrder)> {
+    py_to_toon_value_visiting(obj, &mut HashSet::new(), false, true)
+}
+
+/// Conversion worker; `visiting` holds the addresses of the lists, dicts and
+/// dataclass instances on the current path, so a container that contains
+/// itself is rejected instead of recursing forever. With `skip_unsupported`,
+/// objects of unsupported types become `Null` with a `UserWarning` instead
+/// of raising `TypeError`. With `exact_ints`, ints outside the i64 range
+/// raise `ValueError` rather than becoming a `Number`.
+///
+/// Scalars are checked first, so only containers pay for the cycle check.
+fn py_to_toon_value_visiting(
+    obj: &PyAny,
+    visiting: &mut HashSet<usize>,
+    skip_unsupported: bool,
+    exact_ints: bool,
+) -> PyResult<(ToonValue, KeyOrder)> {
     if obj.is_none() {
-        Ok(ToonValue::Null)
+        Ok((ToonValue::Null, KeyOrder::None))
     } else if let Ok(b) = obj.extract::<bool>() {
-        Ok(ToonValue::Bool(b))
+        Ok((ToonValue::Bool(b), KeyOrder::None))
     } else if let Ok(i) = obj.extract::<i64>() {
-        Ok(ToonValue::Number(i as f64))
+        Ok((ToonValue::Integer(i), KeyOrder::None))
+    } else if exact_ints && obj.is_instance_of::<PyLong>() {
+        Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            format!("int {} is out of the 64-bit range", obj)
+        ))
     } else if let Ok(f) = obj.extract::<f64>() {
-        Ok(ToonValue::Number(f))
+        // Python ints beyond the i64 range also land here, losing precision
+        Ok((ToonValue::Number(f), KeyOrder::None))
     } else if let Ok(s) = obj.extract::<String>() {
-        Ok(ToonValue::String(s))
+        Ok((ToonValue::String(s), KeyOrder::None))
     } else if let Ok(list) = obj.downcast::<PyList>() {
-        let mut vec = Vec::with_capacity(list.len());
-        for item in list.iter() {
-            vec.push(py_to_toon_value(item)?);
-        }
-        Ok(ToonValue::Array(vec))
+        visit(obj, visiting, |visiting| {
+            let mut vec = Vec::with_capacity(list.len());
+            let mut orders = Vec::with_capacity(list.len());
+            for item in list.iter() {
+                let (value, order) = py_to_toon_value_visiting(item, visiting, skip_unsupported, exact_ints)?;
+                vec.push(value);
+                orders.push(order);
+            }
+            let order = if orders.iter().all(|o| *o == KeyOrder::None) {
+                KeyOrder::None
+            } else {
+                KeyOrder::Array(orders)
+            };
+            Ok((ToonValue::Array(vec), order))
+        })
     } else if let Ok(dict) = obj.downcast::<PyDict>() {
-        let mut map = HashMap::with_capacity(dict.len());
-        for (key, value) in dict.iter() {
-            let key_str = key.extract::<String>()?;
-            let value_toon = py_to_toon_value(value)?;
-            map.insert(key_str, value_toon);
-        }
-        Ok(ToonValue::Object(map))
+        visit(obj, visiting, |visiting| {
+            let mut map = HashMap::with_capacity(dict.len());
+            let mut keys = Vec::with_capacity(dict.len());
+            // Iterating the raw dict storage ignores a subclass's own ordering
+            // (e.g. `OrderedDict.move_to_end`), so subclasses go through `items()`
+            let items: Vec<(&PyAny, &PyAny)> = if dict.is_exact_instance_of::<PyDict>() {
+                dict.iter().collect()
+            } else {
+                dict.call_method0("items")?
+                    .iter()?
+                    .map(|item| item.and_then(|item| item.extract()))
+                    .collect::<PyResult<_>>()?
+            };
+            for (key, value) in items {
+                let key_str = key.extract::<String>()?;
+                let (value_toon, value_order) = py_to_toon_value_visiting(value, visiting, skip_unsupported, exact_ints)?;
+                keys.push((key_str.clone(), value_order));
+                map.insert(key_str, value_toon);
+            }
+            Ok((ToonValue::Object(map), KeyOrder::Object(keys)))
+        })
+    } else if is_dataclass_instance(obj) {
+        visit(obj, visiting, |visiting| {
+            // Fields become keys in declaration order, like `dataclasses.asdict`
+            let fields = obj.py().import("dataclasses")?.call_method1("fields", (obj,))?;
+            let mut map = HashMap::new();
+            let mut keys = Vec::new();
+            for field in fields.iter()? {
+                let name = field?.getattr("name")?.extract::<String>()?;
+                let (value_toon, value_order) =
+                    py_to_toon_value_visiting(obj.getattr(name.as_str())?, visiting, skip_unsupported, exact_ints)?;
+                keys.push((name.clone(), value_order));
+                map.insert(name, value_toon);
+            }
+            Ok((ToonValue::Object(map), KeyOrder::Object(keys)))
+        })
+    } else if obj.is_instance(obj.py().import("enum")?.getattr("Enum")?)? {
+        // Enum members are written as their value, which may itself be
+        // any supported type
+        py_to_toon_value_visiting(obj.getattr("value")?, visiting, skip_unsupported, exact_ints)
+    } else if skip_unsupported {
+        let message = format!("replaced unsupported {} object with None", obj.get_type().name()?);
+        PyErr::warn(obj.py(), obj.py().get_type::<pyo3::exceptions::PyUserWarning>(), &message, 1)?;
+        Ok((ToonValue::Null, KeyOrder::None))
     } else {
         Err(PyErr::new::<pyo3::exceptions::PyTypeError, _>(
             "Unsupported Python type"
@@ -76,28 +153,65 @@ fn py_to_toon_value(obj: &PyAny) -> PyResult<ToonValue> {
     }
 }
 
+/// Run `convert` with the container `obj` marked as on the current path,
+/// rejecting it if it already is
+fn visit<T>(
+    obj: &PyAny,
+    visiting: &mut HashSet<usize>,
+    convert: impl FnOnce(&mut HashSet<usize>) -> PyResult<T>,
+) -> PyResult<T> {
+    let id = obj.as_ptr() as usize;
+    if !visiting.insert(id) {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            "circular reference detected"
+        ));
+    }
+    let result = convert(visiting);
+    visiting.remove(&id);
+    result
+}
+
+/// Whether `obj` is a dataclass instance (as opposed to a dataclass itself)
+fn is_dataclass_instance(obj: &PyAny) -> bool {
+    !obj.is_instance_of::<PyType>() && obj.hasattr("__dataclass_fields__").unwrap_or(false)
+}
+
 /// Convert a Rust ToonValue to a Python object
+///
+/// `Integer` always becomes a Python `int` and `Number` always becomes a
+/// Python `float`, so `3` and `3.0` keep their original types. Only integer
+/// literals within the i64 range decode to `Integer`; anything larger (and
+/// any literal with a fraction or exponent, such as `9.2e18`) is a `Number`
+/// and therefore a `float`, even if its value happens to be whole.
 fn toon_value_to_py(py: Python<'_>, value: ToonValue) -> PyResult<PyObject> {
+    toon_value_to_py_ordered(py, value, &KeyOrder::None)
+}
+
+/// Convert a Rust ToonValue to a Python object, building dicts with their
+/// keys in `order` and any keys it doesn't list after them
+fn toon_value_to_py_ordered(py: Python<'_>, value: ToonValue, order: &KeyOrder) -> PyResult<PyObject> {
     match value {
-        ToonValue::Null => Ok(py.None().into()),
+        ToonValue::Null => Ok(py.None()),
         ToonValue::Bool(b) => Ok(b.into_py(py)),
-        ToonValue::Number(n) => {
-            if n.fract() == 0.0 && n >= (i64::MIN as f64) && n <= (i64::MAX as f64) {
-                Ok((n as i64).into_py(py))
-            } else {
-                Ok(n.into_py(py))
-            }
-        }
+        ToonValue::Integer(i) => Ok(i.into_py(py)),
+        ToonValue::Number(n) => Ok(n.into_py(py)),
         ToonValue::String(s) => Ok(s.into_py(py)),
         ToonValue::Array(arr) => {
             let list = PyList::empty(py);
-            for item in arr {
-                list.append(toon_value_to_py(py, item)?)?;
+            for (i, item) in arr.into_iter().enumerate() {
+                list.append(toon_value_to_py_ordered(py, item, order.element(i))?)?;
             }
             Ok(list.into())
         }
-        ToonValue::Object(map) => {
+        ToonValue::Object(mut map) => {
             let dict = PyDict::new(py);
+            if let KeyOrder::Object(keys) = order {
+                for (k, child) in keys {
+                    if let Some(v) = map.remove(k) {
+                        dict.set_item(k, toon_value_to_py_ordered(py, v, child)?)?;
+                    }
+                }
+            }
             for (k, v) in map {
                 dict.set_item(k, toon_value_to_py(py, v)?)?;
             }
@@ -106,92 +220,1867 @@ fn toon_value_to_py(py: Python<'_>, value: ToonValue) -> PyResult<PyObject> {
     }
 }
 
+/// Check whether a value contains a NaN or infinite number anywhere
+fn contains_non_finite(value: &ToonValue) -> bool {
+    match value {
+        ToonValue::Number(n) => !n.is_finite(),
+        ToonValue::Array(arr) => arr.iter().any(contains_non_finite),
+        ToonValue::Object(map) => map.values().any(contains_non_finite),
+        _ => false,
+    }
+}
+
 /// Encode a Python object to TOON format
+///
+/// Like `json.dumps`, `allow_nan` defaults to true, in which case NaN and
+/// infinities encode as `nan`, `inf` and `-inf`. When false they raise
+/// `ValueError` instead. Object keys are emitted in dict insertion order.
+/// With `tabular=False`, lists of uniform dicts are written as inline
+/// objects rather than as a header row plus value rows. With
+/// `skip_unsupported=True`, objects that can't be encoded (such as open
+/// files) are written as `null`, each with a `UserWarning`, instead of
+/// raising `TypeError`.
 #[pyfunction]
-fn encode(_py: Python, obj: &PyAny) -> PyResult<String> {
-    let toon_value = py_to_toon_value(obj)?;
-    encoder::encode(&toon_value).map_err(|e| {
+#[pyo3(signature = (obj, allow_nan = true, tabular = true, skip_unsupported = false))]
+fn encode(
+    _py: Python,
+    obj: &PyAny,
+    allow_nan: bool,
+    tabular: bool,
+    skip_unsupported: bool,
+) -> PyResult<String> {
+    let options = EncodeOptions::new().tabular(tabular);
+    encode_obj(obj, &options, allow_nan, skip_unsupported)
+}
+
+/// Encode a Python object with `options`, handling `allow_nan` and
+/// `skip_unsupported` as `encode` does
+fn encode_obj(
+    obj: &PyAny,
+    options: &EncodeOptions,
+    allow_nan: bool,
+    skip_unsupported: bool,
+) -> PyResult<String> {
+    let (toon_value, key_order) = py_to_toon_value_visiting(obj, &mut HashSet::new(), skip_unsupported, false)?;
+    if !allow_nan && contains_non_finite(&toon_value) {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            "Out of range float values are not allowed when allow_nan is false"
+        ));
+    }
+    encoder::encode_with_key_order(&toon_value, options, &key_order).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            format!("Failed to encode: {}", e)
+        )
+    })
+}
+
+/// Encode a Python object to TOON with `# comment` lines before some keys
+///
+/// `comments` maps the paths of top-level keys to their comment text; with
+/// `flatten_keys=True`, nested dicts are written as dotted top-level keys
+/// (`address.city: Oslo`) so their keys can be commented too. Raises
+/// `ValueError` if a path names no such key.
+#[pyfunction]
+#[pyo3(signature = (obj, comments, flatten_keys = false))]
+fn encode_with_comments(
+    obj: &PyAny,
+    comments: HashMap<String, String>,
+    flatten_keys: bool,
+) -> PyResult<String> {
+    let (toon_value, key_order) = py_to_toon_value_ordered(obj)?;
+    let options = EncodeOptions::new().flatten_keys(flatten_keys);
+    encoder::encode_with_comments_ordered(&toon_value, &comments, &options, &key_order).map_err(|e| {
         PyErr::new::<pyo3::exceptions::PyValueError, _>(
             format!("Failed to encode: {}", e)
         )
     })
 }
 
+/// Check whether a Python object survives encoding to TOON and decoding back
+///
+/// `obj` is encoded as `encode` does by default, decoded again as a whole
+/// document (so top-level dicts and tabular lists read back) and compared
+/// with the original using `==`, so `1` and `1.0` are equal. Dataclasses and
+/// enums are compared as the dicts and values they encode to. With
+/// `with_path=True`, returns `(ok, path)` where `path` is the first value that
+/// differs, in `get`'s path syntax, or `None`. Output that fails to decode
+/// differs at the top level, `""`. Raises like `encode` if `obj` can't be
+/// encoded at all.
+#[pyfunction]
+#[pyo3(signature = (obj, with_path = false))]
+fn check_roundtrip(py: Python, obj: &PyAny, with_path: bool) -> PyResult<PyObject> {
+    let encoded = encode(py, obj, true, true, false)?;
+    let difference = match decoder::decode_document(&encoded) {
+        Ok(value) => first_difference(obj, toon_value_to_py(py, value)?.as_ref(py), "")?,
+        Err(_) => Some(String::new()),
+    };
+    if with_path {
+        Ok((difference.is_none(), difference).into_py(py))
+    } else {
+        Ok(difference.is_none().into_py(py))
+    }
+}
+
+/// Path of the first value in `decoded` that differs from `original`
+fn first_difference(original: &PyAny, decoded: &PyAny, path: &str) -> PyResult<Option<String>> {
+    if let Ok(list) = original.downcast::<PyList>() {
+        let Ok(other) = decoded.downcast::<PyList>() else {
+            return Ok(Some(path.to_string()));
+        };
+        for (i, (item, other_item)) in list.iter().zip(other.iter()).enumerate() {
+            let difference = first_difference(item, other_item, &format!("{}[{}]", path, i))?;
+            if difference.is_some() {
+                return Ok(difference);
+            }
+        }
+        return Ok((list.len() != other.len()).then(|| path.to_string()));
+    }
+    
+    let fields: Option<Vec<(String, &PyAny)>> = if let Ok(dict) = original.downcast::<PyDict>() {
+        Some(dict.iter().map(|(k, v)| Ok((k.extract()?, v))).collect::<PyResult<_>>()?)
+    } else if is_dataclass_instance(original) {
+        let fields = original.py().import("dataclasses")?.call_method1("fields", (original,))?;
+        let mut values = Vec::new();
+        for field in fields.iter()? {
+            let name: String = field?.getattr("name")?.extract()?;
+            let value = original.getattr(name.as_str())?;
+            values.push((name, value));
+        }
+        Some(values)
+    } else {
+        None
+    };
+    if let Some(fields) = fields {
+        let Ok(other) = decoded.downcast::<PyDict>() else {
+            return Ok(Some(path.to_string()));
+        };
+        let key_path = |key: &str| {
+            if path.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", path, key)
+            }
+        };
+        for (key, value) in &fields {
+            let difference = match other.get_item(key)? {
+                Some(other_value) => first_difference(value, other_value, &key_path(key))?,
+                None => Some(key_path(key)),
+            };
+            if difference.is_some() {
+                return Ok(difference);
+            }
+        }
+        for key in other.keys() {
+            let key: String = key.extract()?;
+            if !fields.iter().any(|(k, _)| *k == key) {
+                return Ok(Some(key_path(&key)));
+            }
+        }
+        return Ok(None);
+    }
+    
+    if original.is_instance(original.py().import("enum")?.getattr("Enum")?)? {
+        return first_difference(original.getattr("value")?, decoded, path);
+    }
+    Ok((!original.eq(decoded)?).then(|| path.to_string()))
+}
+
+/// Lazily encode a Python object to TOON, one string chunk at a time
+///
+/// Takes the same arguments as `encode` and returns an iterator whose chunks
+/// join up to `encode`'s output, e.g. for streaming a response body.
+#[pyfunction]
+#[pyo3(signature = (obj, allow_nan = true, tabular = true))]
+fn iter_encode(obj: &PyAny, allow_nan: bool, tabular: bool) -> PyResult<EncodeIterator> {
+    let (toon_value, key_order) = py_to_toon_value_ordered(obj)?;
+    if !allow_nan && contains_non_finite(&toon_value) {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            "Out of range float values are not allowed when allow_nan is false"
+        ));
+    }
+    let options = EncodeOptions::new().tabular(tabular);
+    Ok(EncodeIterator {
+        chunks: encoder::EncodeChunks::with_key_order(toon_value, options, key_order),
+    })
+}
+
+/// Python iterator over the chunks of an `iter_encode` call
+#[pyclass]
+struct EncodeIterator {
+    chunks: encoder::EncodeChunks,
+}
+
+#[pymethods]
+impl EncodeIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+    
+    fn __next__(mut slf: PyRefMut<'_, Self>) -> PyResult<Option<String>> {
+        slf.chunks.next().transpose().map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                format!("Failed to encode: {}", e)
+            )
+        })
+    }
+}
+
+/// Option-holding `Encoder` and `Decoder` classes
+///
+/// pyo3 0.20 emits the `#[new]` glue as impls nested in a generated function,
+/// which newer compilers flag as non-local definitions. The glue does not
+/// carry attributes from the `#[pymethods]` block, so the lint is allowed for
+/// this module only.
+mod py_classes {
+    #![allow(non_local_definitions)]
+    
+    use super::*;
+    
+    /// Reusable TOON encoder holding a set of encoding options
+    ///
+    /// `Encoder()` encodes like `encode` with its defaults. Each option method
+    /// returns a new `Encoder` with that option changed, so they chain, as in
+    /// `Encoder().pretty(True).tabular(False)`, and a configured encoder can be
+    /// shared and reused freely.
+    #[pyclass]
+    #[derive(Clone)]
+    pub(crate) struct Encoder {
+        options: EncodeOptions,
+        allow_nan: bool,
+        skip_unsupported: bool,
+    }
+
+    #[pymethods]
+    impl Encoder {
+        #[new]
+        fn new() -> Self {
+            Encoder { options: EncodeOptions::new(), allow_nan: true, skip_unsupported: false }
+        }
+    
+        /// Encode a Python object to a TOON string with this encoder's options
+        fn encode(&self, obj: &PyAny) -> PyResult<String> {
+            encode_obj(obj, &self.options, self.allow_nan, self.skip_unsupported)
+        }
+    
+        /// Whether arrays of containers are written one item per line at every level
+        fn pretty(&self, pretty: bool) -> Self {
+            Encoder { options: self.options.pretty(pretty), ..self.clone() }
+        }
+    
+        /// Number of spaces per indentation level
+        fn indent(&self, indent: usize) -> Self {
+            Encoder { options: self.options.indent(indent), ..self.clone() }
+        }
+    
+        /// Whether lists of uniform dicts are written in tabular form
+        fn tabular(&self, tabular: bool) -> Self {
+            Encoder { options: self.options.tabular(tabular), ..self.clone() }
+        }
+    
+        /// Whether strings are only quoted where the decoder needs it
+        fn minimize_quotes(&self, minimize_quotes: bool) -> Self {
+            Encoder { options: self.options.minimize_quotes(minimize_quotes), ..self.clone() }
+        }
+    
+        /// Whether nested dicts are written as dotted top-level keys
+        fn flatten_keys(&self, flatten_keys: bool) -> Self {
+            Encoder { options: self.options.flatten_keys(flatten_keys), ..self.clone() }
+        }
+    
+        /// Whether multi-line lists end with a trailing comma
+        fn trailing_comma(&self, trailing_comma: bool) -> Self {
+            Encoder { options: self.options.trailing_comma(trailing_comma), ..self.clone() }
+        }
+    
+        /// Whether NaN and infinities are written rather than raising `ValueError`
+        fn allow_nan(&self, allow_nan: bool) -> Self {
+            Encoder { allow_nan, ..self.clone() }
+        }
+    
+        /// Whether unsupported objects become `null`, with a `UserWarning`,
+        /// rather than raising `TypeError`
+        fn skip_unsupported(&self, skip_unsupported: bool) -> Self {
+            Encoder { skip_unsupported, ..self.clone() }
+        }
+    }
+
+    /// Reusable TOON decoder holding a set of decoding options
+    ///
+    /// `Decoder()` decodes like `decode`. As with `Encoder`, each option method
+    /// returns a new `Decoder` with that option changed.
+    #[pyclass]
+    #[derive(Clone)]
+    pub(crate) struct Decoder {
+        options: DecodeOptions,
+    }
+
+    #[pymethods]
+    impl Decoder {
+        #[new]
+        fn new() -> Self {
+            Decoder { options: DecodeOptions::new() }
+        }
+    
+        /// Decode a TOON string to a Python object with this decoder's options
+        fn decode(&self, py: Python, s: &str) -> PyResult<PyObject> {
+            let toon_value = decoder::decode_document_with_options(s, &self.options).map_err(|e| {
+                PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                    format!("Failed to decode: {}", e)
+                )
+            })?;
+            toon_value_to_py(py, toon_value)
+        }
+    
+        /// Whether numbers may have a leading `+`
+        fn allow_plus_sign(&self, allow: bool) -> Self {
+            Decoder { options: self.options.allow_plus_sign(allow) }
+        }
+    
+        /// Whether a newline between list items may stand in for a comma
+        fn newline_as_separator(&self, enable: bool) -> Self {
+            Decoder { options: self.options.newline_as_separator(enable) }
+        }
+    
+        /// Whether `.5` and `5.` are read as numbers
+        fn allow_bare_decimals(&self, allow: bool) -> Self {
+            Decoder { options: self.options.allow_bare_decimals(allow) }
+        }
+    
+        /// Whether `key = value` entries are accepted alongside `key: value`
+        fn allow_equals_separator(&self, allow: bool) -> Self {
+            Decoder { options: self.options.allow_equals_separator(allow) }
+        }
+    
+        /// Whether `<<END` heredoc block strings are accepted
+        fn allow_heredoc(&self, allow: bool) -> Self {
+            Decoder { options: self.options.allow_heredoc(allow) }
+        }
+    
+        /// Whether a comma may follow the last item of a list or dict
+        fn allow_trailing_comma(&self, allow: bool) -> Self {
+            Decoder { options: self.options.allow_trailing_comma(allow) }
+        }
+    }
+}
+
+use py_classes::{Decoder, Encoder};
+
+/// Decode a TOON document, mapping failures to `ValueError`
+///
+/// The whole string is read as `decoder::decode_document` does, so the
+/// top-level dicts and tabular lists `encode` writes read back, and input
+/// left after the document is an error.
+fn decode_to_value(s: &str) -> PyResult<ToonValue> {
+    decoder::decode_document(s).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            format!("Failed to decode: {}", e)
+        )
+    })
+}
+
 /// Decode a TOON string to a Python object
+///
+/// Reads everything `encode` writes, including top-level dicts and tabular
+/// lists. Raises `ValueError` if the string is not one valid document.
 #[pyfunction]
 fn decode(py: Python, s: &str) -> PyResult<PyObject> {
-    let toon_value = decoder::decode(s).map_err(|e| {
+    let toon_value = decode_to_value(s)?;
+    toon_value_to_py(py, toon_value)
+}
+
+/// Decode UTF-8 encoded TOON bytes to a Python object
+///
+/// On invalid UTF-8 the raised `ValueError` carries a `byte_offset` attribute
+/// pointing at the first invalid byte.
+#[pyfunction]
+fn decode_bytes(py: Python, data: &[u8]) -> PyResult<PyObject> {
+    let s = std::str::from_utf8(data).map_err(|e| {
+        let err = PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            format!("Failed to decode: {}", decoder::utf8_error(e))
+        );
+        if let Err(attr_err) = err.value(py).setattr("byte_offset", e.valid_up_to()) {
+            return attr_err;
+        }
+        err
+    })?;
+    decode(py, s)
+}
+
+/// Decode a string holding several `---`-separated TOON documents to a list
+#[pyfunction]
+fn decode_all(py: Python, s: &str) -> PyResult<PyObject> {
+    let docs = decoder::decode_multi(s).map_err(|e| {
         PyErr::new::<pyo3::exceptions::PyValueError, _>(
             format!("Failed to decode: {}", e)
         )
     })?;
+    toon_value_to_py(py, ToonValue::Array(docs))
+}
+
+/// Decode a TOON string and list the paths of all values of one type
+///
+/// `type_name` is one of `"null"`, `"bool"`, `"number"`, `"string"`,
+/// `"array"` or `"object"`, as used by `infer_schema`. Paths use `get`'s
+/// syntax and come in sorted key order; the whole document's path is `""`.
+/// Raises `ValueError` for any other type name.
+#[pyfunction]
+fn find(s: &str, type_name: &str) -> PyResult<Vec<String>> {
+    const TYPE_NAMES: [&str; 6] = ["null", "bool", "number", "string", "array", "object"];
+    if !TYPE_NAMES.contains(&type_name) {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "unknown type name '{}', expected one of {}",
+            type_name,
+            TYPE_NAMES.join(", ")
+        )));
+    }
+    let toon_value = decode_to_value(s)?;
+    let found = toon_value.find_all(|value| value.schema_type_name() == type_name);
+    Ok(found.into_iter().map(|(path, _)| path).collect())
+}
+
+/// Decode a TOON string and return the list at `path` without repeats
+///
+/// The first occurrence of each element is kept, comparing nested lists and
+/// dicts by content; see `ToonValue::dedup_array`. The empty path is the
+/// whole document. Raises `TypeError` if there is no list at `path`.
+#[pyfunction]
+fn distinct(py: Python, s: &str, path: &str) -> PyResult<PyObject> {
+    let toon_value = decode_to_value(s)?;
+    let mut array = toon_value
+        .pointer(path)
+        .filter(|value| value.as_array().is_some())
+        .cloned()
+        .ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!("no list at '{}'", path))
+        })?;
+    array.dedup_array();
+    toon_value_to_py(py, array)
+}
+
+/// Decode a TOON string and return the value at `path`, or `default` if the
+/// path does not exist
+///
+/// Paths use `/` or `.` separators with optional `[N]` indices, e.g.
+/// `"users[0].name"`.
+#[pyfunction]
+#[pyo3(signature = (s, path, default = None))]
+fn get(py: Python, s: &str, path: &str, default: Option<PyObject>) -> PyResult<PyObject> {
+    let toon_value = decode_to_value(s)?;
+    match toon_value.pointer(path) {
+        Some(leaf) => toon_value_to_py(py, leaf.clone()),
+        None => Ok(default.unwrap_or_else(|| py.None())),
+    }
+}
+
+/// Decode a TOON string and read the value at `path` as a float, leniently
+///
+/// Numeric strings such as `"42"` are parsed and bools become `1.0` or
+/// `0.0`; see `ToonValue::as_f64_lossy`. Returns `None` if the path is
+/// missing or the value can't be read as a number.
+#[pyfunction]
+fn as_number(s: &str, path: &str) -> PyResult<Option<f64>> {
+    Ok(decode_to_value(s)?.pointer(path).and_then(ToonValue::as_f64_lossy))
+}
+
+/// Decode a TOON string and sum all of its numbers
+///
+/// Ints and floats nested anywhere count; bools, numeric strings and other
+/// values are ignored. See `ToonValue::aggregate_numbers`.
+#[pyfunction]
+fn sum_numbers(s: &str) -> PyResult<f64> {
+    Ok(decode_to_value(s)?.aggregate_numbers().0)
+}
+
+/// Decode a TOON string and average all of its numbers, as counted by
+/// `sum_numbers`
+///
+/// Returns `None` if it holds no numbers.
+#[pyfunction]
+fn mean_numbers(s: &str) -> PyResult<Option<f64>> {
+    let (sum, count) = decode_to_value(s)?.aggregate_numbers();
+    Ok((count > 0).then(|| sum / count as f64))
+}
+
+/// Decode a TOON string and report whether a value exists at `path`
+///
+/// Uses the same path syntax as `get`; a path holding `null` exists.
+#[pyfunction]
+fn has(s: &str, path: &str) -> PyResult<bool> {
+    Ok(decode_to_value(s)?.contains_path(path))
+}
+
+/// Look up a value in a TOON string by path, falling back to `default` when
+/// the path is missing or holds a value of a different type
+///
+/// The type is that of `default`: a `bool`, a number (`int` and `float`
+/// accept either), a `str`, a `list` or a `dict`. A default of `None`
+/// accepts any value, including an explicit null.
+#[pyfunction]
+fn get_or(py: Python, s: &str, path: &str, default: &PyAny) -> PyResult<PyObject> {
+    let toon_value = decode_to_value(s)?;
+    match toon_value.pointer(path) {
+        Some(leaf) if matches_type_of(leaf, default) => toon_value_to_py(py, leaf.clone()),
+        _ => Ok(default.into_py(py)),
+    }
+}
+
+/// Whether a value has the same kind of type as the Python object `like`
+fn matches_type_of(value: &ToonValue, like: &PyAny) -> bool {
+    if like.is_none() {
+        true
+    } else if like.downcast::<PyBool>().is_ok() {
+        matches!(value, ToonValue::Bool(_))
+    } else if like.downcast::<PyLong>().is_ok() || like.downcast::<PyFloat>().is_ok() {
+        matches!(value, ToonValue::Integer(_) | ToonValue::Number(_))
+    } else if like.downcast::<PyString>().is_ok() {
+        matches!(value, ToonValue::String(_))
+    } else if like.downcast::<PyList>().is_ok() {
+        matches!(value, ToonValue::Array(_))
+    } else if like.downcast::<PyDict>().is_ok() {
+        matches!(value, ToonValue::Object(_))
+    } else {
+        false
+    }
+}
+
+/// Decode a TOON string with every number converted to a string
+#[pyfunction]
+fn stringify_numbers(py: Python, s: &str) -> PyResult<PyObject> {
+    let mut toon_value = decode_to_value(s)?;
+    toon_value.stringify_numbers();
     toon_value_to_py(py, toon_value)
 }
 
-/// Python module for TOON format encoding/decoding
-#[pymodule]
-fn toonify_rs(_py: Python, m: &PyModule) -> PyResult<()> {
-    m.add_function(wrap_pyfunction!(encode, m)?)?;
-    m.add_function(wrap_pyfunction!(decode, m)?)?;
-    
-    // Add constants
-    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
-    
-    Ok(())
+/// Decode a TOON string, unwrapping a single-key wrapper object
+///
+/// If the value is an object whose only key is `key`, its inner value is
+/// returned; otherwise the whole value is returned unchanged.
+#[pyfunction]
+fn unwrap(py: Python, s: &str, key: &str) -> PyResult<PyObject> {
+    let toon_value = decode_to_value(s)?;
+    match toon_value.unwrap_key(key) {
+        Some(inner) => toon_value_to_py(py, inner.clone()),
+        None => toon_value_to_py(py, toon_value),
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use pyo3::types::IntoPyDict;
-    use pyo3::IntoPy;
-    
-    #[test]
-    fn test_py_to_toon_value() -> PyResult<()> {
-        Python::with_gil(|py| {
-            // Test None
-            let none = py.None();
-            assert_eq!(py_to_toon_value(none.as_ref(py))?, ToonValue::Null);
-            
-            // Test bool
-            let py_true = true.to_object(py);
-            let py_false = false.to_object(py);
-            assert_eq!(py_to_toon_value(py_true.as_ref(py))?, ToonValue::Bool(true));
-            assert_eq!(py_to_toon_value(py_false.as_ref(py))?, ToonValue::Bool(false));
-            
-            // Test number
-            let py_int = 42.to_object(py);
-            let py_float = 3.14.to_object(py);
-            assert_eq!(py_to_toon_value(py_int.as_ref(py))?, ToonValue::Number(42.0));
-            assert_eq!(py_to_toon_value(py_float.as_ref(py))?, ToonValue::Number(3.14));
-            
-            // Test string
-            let py_str = "hello".to_object(py);
-            assert_eq!(
-                py_to_toon_value(py_str.as_ref(py))?,
-                ToonValue::String("hello".to_string())
-            );
-            
-            // Test list
-            let py_list = vec![1, 2, 3].to_object(py);
-            let expected = ToonValue::Array(vec![
-                ToonValue::Number(1.0),
-                ToonValue::Number(2.0),
-                ToonValue::Number(3.0),
-            ]);
-            assert_eq!(py_to_toon_value(py_list.as_ref(py))?, expected);
-            
-            // Test dict
-            let py_dict = [("a", 1), ("b", 2)].into_py_dict(py);
-            let py_dict_obj: Py<PyDict> = py_dict.into_py(py);
-            let expected = {
-                let mut map = std::collections::HashMap::new();
-                map.insert("a".to_string(), ToonValue::Number(1.0));
-                map.insert("b".to_string(), ToonValue::Number(2.0));
-                ToonValue::Object(map)
-            };
-            assert_eq!(py_to_toon_value(py_dict_obj.as_ref(py))?, expected);
+/// Decode a TOON string into a cut-down preview for logging
+///
+/// See `ToonValue::truncate` for how arrays and strings are shortened.
+#[pyfunction]
+fn preview(py: Python, s: &str, max_array: usize, max_string: usize) -> PyResult<PyObject> {
+    let toon_value = decode_to_value(s)?;
+    toon_value_to_py(py, toon_value.truncate(max_array, max_string))
+}
+
+/// Convert a TOON string directly to a JSON string
+///
+/// With `indent`, the JSON is pretty-printed using that many spaces per level.
+#[pyfunction]
+#[pyo3(signature = (s, indent = None))]
+fn toon_to_json(s: &str, indent: Option<usize>) -> PyResult<String> {
+    let toon_value = decode_to_value(s)?;
+    encoder::to_json_string(&toon_value, indent).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            format!("Failed to encode: {}", e)
+        )
+    })
+}
+
+/// Canonicalize a TOON string whose arrays are unordered sets
+///
+/// Every array is sorted and every object's keys are written in sorted
+/// order, so two documents that differ only in array or key order produce
+/// the same string.
+#[pyfunction]
+fn canonicalize_unordered(s: &str) -> PyResult<String> {
+    let mut toon_value = decode_to_value(s)?;
+    toon_value.sort_all_arrays();
+    let order = KeyOrder::sorted(&toon_value);
+    encoder::encode_with_key_order(&toon_value, &EncodeOptions::default(), &order).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            format!("Failed to encode: {}", e)
+        )
+    })
+}
+
+/// Convert a JSON string directly to a TOON string
+#[pyfunction]
+fn json_to_toon(s: &str) -> PyResult<String> {
+    let toon_value = decoder::decode_json(s).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            format!("Failed to decode: {}", e)
+        )
+    })?;
+    encoder::encode(&toon_value).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            format!("Failed to encode: {}", e)
+        )
+    })
+}
+
+/// Convert a TOON string to block-style YAML
+#[pyfunction]
+fn to_yaml(s: &str) -> PyResult<String> {
+    let toon_value = decode_to_value(s)?;
+    Ok(encoder::to_yaml_string(&toon_value))
+}
+
+/// Convert a TOON object to a TOML document
+#[pyfunction]
+fn to_toml(s: &str) -> PyResult<String> {
+    let toon_value = decode_to_value(s)?;
+    encoder::to_toml_string(&toon_value).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            format!("Failed to encode: {}", e)
+        )
+    })
+}
+
+/// Convert a TOON value to an XML document under a `root` element
+///
+/// See `encoder::to_xml_string` for the mapping; it has no attributes and
+/// drops type information. Raises `ValueError` for keys that aren't valid
+/// XML element names.
+#[pyfunction]
+#[pyo3(signature = (s, root = "root"))]
+fn to_xml(s: &str, root: &str) -> PyResult<String> {
+    let toon_value = decode_to_value(s)?;
+    encoder::to_xml_string(&toon_value, root).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            format!("Failed to encode: {}", e)
+        )
+    })
+}
+
+/// Parse CSV text into a list of dicts (or of lists, without a header row)
+#[pyfunction]
+#[pyo3(signature = (s, has_header = true))]
+fn from_csv(py: Python, s: &str, has_header: bool) -> PyResult<PyObject> {
+    let toon_value = decoder::from_csv(s, has_header).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            format!("Failed to decode: {}", e)
+        )
+    })?;
+    toon_value_to_py(py, toon_value)
+}
+
+/// Decode a TOON string, keeping only `keys` of an object or of each object
+/// in an array
+#[pyfunction]
+fn select(py: Python, s: &str, keys: Vec<String>) -> PyResult<PyObject> {
+    let toon_value = decode_to_value(s)?;
+    let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+    toon_value_to_py(py, toon_value.select(&keys))
+}
+
+/// Recursively rename the keys of dicts in a Python object per `mapping`
+///
+/// Renamed keys keep their place in the dict. Raises `ValueError` if a
+/// rename collides with an existing key.
+#[pyfunction]
+fn rename_keys(py: Python, obj: &PyAny, mapping: HashMap<String, String>) -> PyResult<PyObject> {
+    let (mut toon_value, mut key_order) = py_to_toon_value_ordered(obj)?;
+    toon_value.rename_keys_ordered(&mut key_order, &mapping).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string())
+    })?;
+    toon_value_to_py_ordered(py, toon_value, &key_order)
+}
+
+/// Set the value at `path` in a Python object, creating missing dicts along
+/// the way, and return the modified copy
+///
+/// Uses the same path syntax as `get`; see `ToonValue::set`. Dicts, dict
+/// subclasses and dataclasses come back as plain dicts with their keys in
+/// the original order, and a new key goes last. Raises `TypeError` if the
+/// path runs into a value that isn't a dict or list, or past the end of a
+/// list, and `ValueError` for ints outside the 64-bit range, which can't be
+/// copied exactly.
+#[pyfunction]
+fn set(py: Python, obj: &PyAny, path: &str, value: &PyAny) -> PyResult<PyObject> {
+    let (mut toon_value, mut key_order) = py_to_toon_value_exact(obj)?;
+    let (value, value_order) = py_to_toon_value_exact(value)?;
+    toon_value.set_ordered(&mut key_order, path, value, value_order).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyTypeError, _>(e.to_string())
+    })?;
+    toon_value_to_py_ordered(py, toon_value, &key_order)
+}
+
+/// Remove the value at `path` from a Python object
+///
+/// Returns the modified copy together with the removed value, which is
+/// `None` (with the copy unchanged) if the path doesn't exist. Uses the same
+/// path syntax as `get`; removing a list element shifts the ones after it.
+/// Dicts keep their key order, as with `set`, and ints outside the 64-bit
+/// range raise `ValueError`.
+#[pyfunction]
+fn remove(py: Python, obj: &PyAny, path: &str) -> PyResult<(PyObject, PyObject)> {
+    let (mut toon_value, mut key_order) = py_to_toon_value_exact(obj)?;
+    let removed = match toon_value.remove_ordered(&mut key_order, path) {
+        Some((removed, removed_order)) => toon_value_to_py_ordered(py, removed, &removed_order)?,
+        None => py.None(),
+    };
+    Ok((toon_value_to_py_ordered(py, toon_value, &key_order)?, removed))
+}
+
+/// Round every float in a Python object to `ndigits` decimal places
+///
+/// Floats nested anywhere in lists, dicts and dataclasses are rounded, half
+/// away from zero; ints and other values are left as they are, and dicts
+/// keep their key order. Ints outside the 64-bit range raise `ValueError`
+/// rather than being rounded as floats.
+#[pyfunction]
+fn round_all(py: Python, obj: &PyAny, ndigits: i32) -> PyResult<PyObject> {
+    let (mut toon_value, key_order) = py_to_toon_value_exact(obj)?;
+    let scale = 10f64.powi(ndigits);
+    toon_value.for_each_leaf_mut(|leaf| {
+        if let ToonValue::Number(n) = leaf {
+            let rounded = (*n * scale).round() / scale;
+            // Scaling can overflow for huge values, which are already whole
+            if rounded.is_finite() {
+                *n = rounded;
+            }
+        }
+    });
+    toon_value_to_py_ordered(py, toon_value, &key_order)
+}
+
+/// Upsert the records of the list `patch` into the list `base`, matching on
+/// the `key` field
+///
+/// Matched records are replaced and unmatched ones appended; see
+/// `ToonValue::merge_arrays_by_key`. Every record keeps its key order.
+/// Raises `TypeError` if either argument isn't a list or a record of
+/// `patch` lacks the key, and `ValueError` for ints outside the 64-bit
+/// range.
+#[pyfunction]
+fn upsert(py: Python, base: &PyAny, patch: &PyAny, key: &str) -> PyResult<PyObject> {
+    let (mut toon_value, mut key_order) = py_to_toon_value_exact(base)?;
+    let (patch, patch_order) = py_to_toon_value_exact(patch)?;
+    toon_value.merge_arrays_by_key_ordered(&mut key_order, &patch, &patch_order, key).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyTypeError, _>(e.to_string())
+    })?;
+    toon_value_to_py_ordered(py, toon_value, &key_order)
+}
+
+/// Decode a TOON array of records and group them by the value of `key`
+///
+/// Returns a dict mapping each stringified value to a list of the records
+/// holding it; see `ToonValue::group_by`. Raises `TypeError` if the input
+/// isn't an array of objects.
+#[pyfunction]
+fn group_by(py: Python, s: &str, key: &str) -> PyResult<PyObject> {
+    let toon_value = decode_to_value(s)?;
+    let groups = toon_value.group_by(key).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyTypeError, _>(e.to_string())
+    })?;
+    toon_value_to_py(py, groups)
+}
+
+/// Convert a file between JSON and TOON
+///
+/// `from_fmt` and `to_fmt` are each `"json"` or `"toon"`. The input is read
+/// in full, but the output is streamed straight to `out_path`. TOON input is
+/// read as a whole document in the form written here, so a converted file
+/// converts back; anything left after the document raises `ValueError`.
+#[pyfunction]
+fn convert_file(in_path: &str, out_path: &str, from_fmt: &str, to_fmt: &str) -> PyResult<()> {
+    let value_error = |e: ToonError| PyErr::new::<pyo3::exceptions::PyValueError, _>(e.to_string());
+    let format_error = |fmt: &str| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "Unsupported format '{}', expected 'json' or 'toon'",
+            fmt
+        ))
+    };
+    if !matches!(to_fmt, "json" | "toon") {
+        return Err(format_error(to_fmt));
+    }
+    
+    let input = std::fs::read_to_string(in_path)?;
+    let toon_value = match from_fmt {
+        "json" => decoder::decode_json(&input),
+        "toon" => decoder::decode_document(&input),
+        other => return Err(format_error(other)),
+    }
+    .map_err(value_error)?;
+    
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(out_path)?);
+    let result = if to_fmt == "json" {
+        encoder::to_json_writer(&toon_value, None, &mut writer)
+    } else {
+        encoder::encode_to_writer(&toon_value, &EncodeOptions::default(), &mut writer)
+    };
+    result.map_err(|e| match e {
+        ToonError::Io(err) => err.into(),
+        other => value_error(other),
+    })?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Decode a TOON list of dicts into `(headers, rows)`
+///
+/// `headers` is the sorted union of all keys and each row lists one dict's
+/// values in that order, with `None` for missing keys. Raises `ValueError`
+/// if the input is not a list of dicts.
+#[pyfunction]
+fn to_records(py: Python, s: &str) -> PyResult<(PyObject, PyObject)> {
+    let toon_value = decode_to_value(s)?;
+    let (headers, rows) = toon_value.to_records().ok_or_else(|| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>("Expected an array of objects")
+    })?;
+    let rows = rows.into_iter().map(ToonValue::Array).collect();
+    Ok((headers.into_py(py), toon_value_to_py(py, ToonValue::Array(rows))?))
+}
+
+/// Convert a flat TOON object to a percent-encoded URL query string
+#[pyfunction]
+fn to_query_string(s: &str) -> PyResult<String> {
+    let toon_value = decode_to_value(s)?;
+    encoder::to_query_string(&toon_value).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            format!("Failed to encode: {}", e)
+        )
+    })
+}
+
+/// Infer a rough type schema from a TOON string
+#[pyfunction]
+fn infer_schema(py: Python, s: &str) -> PyResult<PyObject> {
+    let toon_value = decode_to_value(s)?;
+    toon_value_to_py(py, toon_value.infer_schema())
+}
+
+/// Decode a TOON string and coerce it to fit `schema`
+///
+/// `schema` uses the format returned by `infer_schema`; see
+/// `ToonValue::coerce_to` for the conversions applied. Values that can't be
+/// made to fit raise `TypeError`.
+#[pyfunction]
+fn coerce(py: Python, s: &str, schema: &PyAny) -> PyResult<PyObject> {
+    let toon_value = decode_to_value(s)?;
+    let (schema, _) = py_to_toon_value_ordered(schema)?;
+    let coerced = toon_value.coerce_to(&schema).map_err(|e| {
+        PyErr::new::<pyo3::exceptions::PyTypeError, _>(e.to_string())
+    })?;
+    toon_value_to_py(py, coerced)
+}
+
+/// Compute structural statistics for a TOON string
+///
+/// Returns a dict with `node_count` (every value, containers included) and
+/// `depth` (maximum container nesting, 0 for a bare scalar).
+#[pyfunction]
+fn stats(py: Python, s: &str) -> PyResult<PyObject> {
+    let toon_value = decode_to_value(s)?;
+    let dict = PyDict::new(py);
+    dict.set_item("node_count", toon_value.node_count())?;
+    dict.set_item("depth", toon_value.depth())?;
+    Ok(dict.into())
+}
+
+/// Python module for TOON format encoding/decoding
+#[pymodule]
+fn toonify_rs(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(encode, m)?)?;
+    m.add_function(wrap_pyfunction!(iter_encode, m)?)?;
+    m.add_class::<EncodeIterator>()?;
+    m.add_class::<Encoder>()?;
+    m.add_class::<Decoder>()?;
+    m.add_function(wrap_pyfunction!(decode, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_bytes, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_all, m)?)?;
+    m.add_function(wrap_pyfunction!(get, m)?)?;
+    m.add_function(wrap_pyfunction!(has, m)?)?;
+    m.add_function(wrap_pyfunction!(check_roundtrip, m)?)?;
+    m.add_function(wrap_pyfunction!(distinct, m)?)?;
+    m.add_function(wrap_pyfunction!(find, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_with_comments, m)?)?;
+    m.add_function(wrap_pyfunction!(sum_numbers, m)?)?;
+    m.add_function(wrap_pyfunction!(mean_numbers, m)?)?;
+    m.add_function(wrap_pyfunction!(as_number, m)?)?;
+    m.add_function(wrap_pyfunction!(get_or, m)?)?;
+    m.add_function(wrap_pyfunction!(stringify_numbers, m)?)?;
+    m.add_function(wrap_pyfunction!(preview, m)?)?;
+    m.add_function(wrap_pyfunction!(unwrap, m)?)?;
+    m.add_function(wrap_pyfunction!(toon_to_json, m)?)?;
+    m.add_function(wrap_pyfunction!(json_to_toon, m)?)?;
+    m.add_function(wrap_pyfunction!(canonicalize_unordered, m)?)?;
+    m.add_function(wrap_pyfunction!(to_yaml, m)?)?;
+    m.add_function(wrap_pyfunction!(to_toml, m)?)?;
+    m.add_function(wrap_pyfunction!(to_xml, m)?)?;
+    m.add_function(wrap_pyfunction!(from_csv, m)?)?;
+    m.add_function(wrap_pyfunction!(select, m)?)?;
+    m.add_function(wrap_pyfunction!(rename_keys, m)?)?;
+    m.add_function(wrap_pyfunction!(upsert, m)?)?;
+    m.add_function(wrap_pyfunction!(round_all, m)?)?;
+    m.add_function(wrap_pyfunction!(set, m)?)?;
+    m.add_function(wrap_pyfunction!(remove, m)?)?;
+    m.add_function(wrap_pyfunction!(group_by, m)?)?;
+    m.add_function(wrap_pyfunction!(convert_file, m)?)?;
+    m.add_function(wrap_pyfunction!(to_records, m)?)?;
+    m.add_function(wrap_pyfunction!(to_query_string, m)?)?;
+    m.add_function(wrap_pyfunction!(infer_schema, m)?)?;
+    m.add_function(wrap_pyfunction!(coerce, m)?)?;
+    m.add_function(wrap_pyfunction!(stats, m)?)?;
+    
+    // Add constants
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::IntoPyDict;
+    use pyo3::IntoPy;
+    
+    fn py_to_toon_value(obj: &PyAny) -> PyResult<ToonValue> {
+        py_to_toon_value_ordered(obj).map(|(value, _)| value)
+    }
+    
+    #[test]
+    fn test_py_to_toon_value() -> PyResult<()> {
+        Python::with_gil(|py| {
+            // Test None
+            let none = py.None();
+            assert_eq!(py_to_toon_value(none.as_ref(py))?, ToonValue::Null);
+            
+            // Test bool
+            let py_true = true.to_object(py);
+            let py_false = false.to_object(py);
+            assert_eq!(py_to_toon_value(py_true.as_ref(py))?, ToonValue::Bool(true));
+            assert_eq!(py_to_toon_value(py_false.as_ref(py))?, ToonValue::Bool(false));
+            
+            // Test number
+            let py_int = 42.to_object(py);
+            let py_float = 2.5.to_object(py);
+            assert_eq!(py_to_toon_value(py_int.as_ref(py))?, ToonValue::Integer(42));
+            assert_eq!(py_to_toon_value(py_float.as_ref(py))?, ToonValue::Number(2.5));
+            
+            // Test string
+            let py_str = "hello".to_object(py);
+            assert_eq!(
+                py_to_toon_value(py_str.as_ref(py))?,
+                ToonValue::String("hello".to_string())
+            );
+            
+            // Test list
+            let py_list = vec![1, 2, 3].to_object(py);
+            let expected = ToonValue::Array(vec![
+                ToonValue::Integer(1),
+                ToonValue::Integer(2),
+                ToonValue::Integer(3),
+            ]);
+            assert_eq!(py_to_toon_value(py_list.as_ref(py))?, expected);
+            
+            // Test dict
+            let py_dict = [("a", 1), ("b", 2)].into_py_dict(py);
+            let py_dict_obj: Py<PyDict> = py_dict.into_py(py);
+            let expected = {
+                let mut map = std::collections::HashMap::new();
+                map.insert("a".to_string(), ToonValue::Integer(1));
+                map.insert("b".to_string(), ToonValue::Integer(2));
+                ToonValue::Object(map)
+            };
+            assert_eq!(py_to_toon_value(py_dict_obj.as_ref(py))?, expected);
+            
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_toon_value_to_py_types() -> PyResult<()> {
+        use pyo3::types::{PyBool, PyFloat, PyLong};
+        
+        Python::with_gil(|py| {
+            // Integer literals stay ints, float literals stay floats
+            let int_obj = decode(py, "3")?;
+            assert!(int_obj.as_ref(py).is_exact_instance_of::<PyLong>());
+            assert_eq!(int_obj.extract::<i64>(py)?, 3);
+            
+            let float_obj = decode(py, "3.0")?;
+            assert!(float_obj.as_ref(py).is_instance_of::<PyFloat>());
+            assert_eq!(float_obj.extract::<f64>(py)?, 3.0);
+            
+            // Booleans never collapse into ints and vice versa
+            let bool_obj = toon_value_to_py(py, ToonValue::Bool(true))?;
+            assert!(bool_obj.as_ref(py).is_instance_of::<PyBool>());
+            let one_obj = toon_value_to_py(py, ToonValue::Integer(1))?;
+            assert!(!one_obj.as_ref(py).is_instance_of::<PyBool>());
+            assert_eq!(py_to_toon_value(1.to_object(py).as_ref(py))?, ToonValue::Integer(1));
+            assert_eq!(py_to_toon_value(true.to_object(py).as_ref(py))?, ToonValue::Bool(true));
+            
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_decode_bytes_invalid_utf8_offset() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let value = decode_bytes(py, b"[1, 2]")?;
+            assert_eq!(value.extract::<Vec<i64>>(py)?, vec![1, 2]);
+            
+            let err = decode_bytes(py, b"[\"ab\xc3\x28\"]").unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+            assert!(err.to_string().contains("invalid UTF-8 at byte 4"));
+            let offset: usize = err.value(py).getattr("byte_offset")?.extract()?;
+            assert_eq!(offset, 4);
+            
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_encode_allow_nan() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let inf = f64::INFINITY.to_object(py);
+            let nan = f64::NAN.to_object(py);
+            let nested = vec![1.0, f64::NEG_INFINITY].to_object(py);
+            
+            assert_eq!(encode(py, inf.as_ref(py), true, true, false)?, "inf");
+            assert_eq!(encode(py, nan.as_ref(py), true, true, false)?, "nan");
+            assert_eq!(encode(py, nested.as_ref(py), true, true, false)?, "[1, -inf]");
+            
+            for obj in [&inf, &nan, &nested] {
+                let err = encode(py, obj.as_ref(py), false, true, false).unwrap_err();
+                assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+            }
+            
+            // Finite floats are unaffected by the flag
+            let finite = 2.5.to_object(py);
+            assert_eq!(encode(py, finite.as_ref(py), false, true, false)?, "2.5");
+            
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_integer_boundaries() -> PyResult<()> {
+        use pyo3::types::{PyFloat, PyLong};
+        
+        Python::with_gil(|py| {
+            let max = decode(py, "9223372036854775807")?;
+            assert!(max.as_ref(py).is_exact_instance_of::<PyLong>());
+            assert_eq!(max.extract::<i64>(py)?, i64::MAX);
+            
+            let min = decode(py, "-9223372036854775808")?;
+            assert!(min.as_ref(py).is_exact_instance_of::<PyLong>());
+            assert_eq!(min.extract::<i64>(py)?, i64::MIN);
+            
+            // Just past the i64 range, and whole-valued floats, stay floats
+            for input in ["9223372036854775808", "-9223372036854775809", "9.2e18", "1e19"] {
+                let obj = decode(py, input)?;
+                assert!(obj.as_ref(py).is_instance_of::<PyFloat>(), "{} should be a float", input);
+            }
+            
+            let py_max = i64::MAX.to_object(py);
+            assert_eq!(py_to_toon_value(py_max.as_ref(py))?, ToonValue::Integer(i64::MAX));
+            
+            let py_big = py.eval("2 ** 64", None, None)?;
+            assert_eq!(py_to_toon_value(py_big)?, ToonValue::Number(18446744073709551616.0));
+            
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_stats() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let result = stats(py, "{a: [1, 2], b: {c: null}}")?;
+            let dict: &PyDict = result.downcast(py)?;
+            assert_eq!(dict.get_item("node_count")?.unwrap().extract::<usize>()?, 6);
+            assert_eq!(dict.get_item("depth")?.unwrap().extract::<usize>()?, 2);
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_encode_preserves_dict_order() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let obj = py.eval(
+                "{'zeta': 1, 'alpha': {'second': 2, 'first': 1}, 'mid': [{'b': 1, 'a': 0}, 2], 'beta': 3}",
+                None,
+                None,
+            )?;
+            let result = encode(py, obj, true, true, false)?;
+            assert_eq!(
+                result,
+                "zeta: 1\nalpha: {second: 2, first: 1}\nmid: [{b: 1, a: 0}, 2]\nbeta: 3"
+            );
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_decode_all() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let docs = decode_all(py, "1\n---\n\"two\"\n---\n[3]")?;
+            let list: &PyList = docs.downcast(py)?;
+            assert_eq!(list.len(), 3);
+            assert_eq!(list.get_item(0)?.extract::<i64>()?, 1);
+            assert_eq!(list.get_item(1)?.extract::<String>()?, "two");
+            assert_eq!(list.get_item(2)?.extract::<Vec<i64>>()?, vec![3]);
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_encode_circular_reference() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            py.run("a = []\na.append(a)\nd = {}\nd['self'] = [d]", None, Some(locals))?;
+            
+            for name in ["a", "d"] {
+                let obj = locals.get_item(name)?.unwrap();
+                let err = encode(py, obj, true, true, false).unwrap_err();
+                assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+                assert_eq!(err.value(py).to_string(), "circular reference detected");
+            }
+            
+            // Shared, non-circular references are fine
+            let shared = py.eval("(lambda x: [x, x])([1])", None, None)?;
+            assert_eq!(encode(py, shared, true, true, false)?, "[\n  [1],\n  [1]\n]");
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_get() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let s = "{users: [{name: Ann, age: 30}], empty: null}";
+            assert_eq!(get(py, s, "users[0].name", None)?.extract::<String>(py)?, "Ann");
+            assert_eq!(get(py, s, "users/0/age", None)?.extract::<i64>(py)?, 30);
+            
+            // A missing path yields the default, but an explicit null does not
+            assert!(get(py, s, "users[1]", None)?.is_none(py));
+            let fallback = get(py, s, "users[1]", Some(5.into_py(py)))?;
+            assert_eq!(fallback.extract::<i64>(py)?, 5);
+            assert!(get(py, s, "empty", Some(5.into_py(py)))?.is_none(py));
+            
+            assert!(get(py, "{bad", "a", None).is_err());
+            
+            // `encode` output for a dict, tabular rows included, reads in full
+            let obj = py.eval("{'a': 1, 'b': 2, 'rows': [{'id': 1}, {'id': 2}]}", None, None)?;
+            let s = encode(py, obj, true, true, false)?;
+            assert_eq!(get(py, &s, "b", None)?.extract::<i64>(py)?, 2);
+            assert_eq!(get(py, &s, "rows[1].id", None)?.extract::<i64>(py)?, 2);
+            assert!(has(&s, "b")? && has(&s, "rows[0].id")? && !has(&s, "c")?);
+            
+            // Trailing input is an error rather than silently ignored
+            assert!(get(py, "[1] [2]", "[0]", None).is_err());
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_canonicalize_unordered() -> PyResult<()> {
+        let a = canonicalize_unordered("{ids: [3, 1, 2], rows: [{k: b, v: [2, 1]}, {k: a, v: []}]}")?;
+        let b = canonicalize_unordered("{rows: [{v: [], k: a}, {v: [1, 2], k: b}], ids: [1, 2, 3]}")?;
+        assert_eq!(a, b);
+        assert_ne!(a, canonicalize_unordered("{ids: [1, 2], rows: []}")?);
+        Ok(())
+    }
+    
+    #[test]
+    fn test_as_number() -> PyResult<()> {
+        let s = "{price: \"42\", paid: true, qty: 3, note: abc, items: [{w: \"0.5\"}]}";
+        assert_eq!(as_number(s, "price")?, Some(42.0));
+        assert_eq!(as_number(s, "paid")?, Some(1.0));
+        assert_eq!(as_number(s, "qty")?, Some(3.0));
+        assert_eq!(as_number(s, "items[0].w")?, Some(0.5));
+        assert_eq!(as_number(s, "note")?, None);
+        assert_eq!(as_number(s, "missing")?, None);
+        Ok(())
+    }
+    
+    #[test]
+    fn test_encode_with_comments() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let obj = py.eval("{'name': 'Ann', 'address': {'city': 'Oslo', 'zip': '0150'}}", None, None)?;
+            let comments = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+                pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+            };
+            
+            let encoded = encode_with_comments(obj, comments(&[("name", "Full name")]), false)?;
+            assert_eq!(encoded, "# Full name\nname: Ann\naddress: {city: Oslo, zip: \"0150\"}");
+            
+            let encoded = encode_with_comments(obj, comments(&[("address.zip", "Postal code")]), true)?;
+            assert_eq!(encoded, "name: Ann\naddress.city: Oslo\n# Postal code\naddress.zip: \"0150\"");
+            
+            let err = encode_with_comments(obj, comments(&[("address.zip", "Postal code")]), false).unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_find() -> PyResult<()> {
+        let s = "{user: {name: Ann, age: 30, tags: [a, 1.5]}, ok: true}";
+        assert_eq!(find(s, "string")?, vec!["user.name", "user.tags[0]"]);
+        assert_eq!(find(s, "number")?, vec!["user.age", "user.tags[1]"]);
+        assert_eq!(find(s, "object")?, vec!["", "user"]);
+        assert!(find(s, "null")?.is_empty());
+        
+        Python::with_gil(|py| {
+            let err = find(s, "str").unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+            assert!(err.to_string().contains("expected one of null, bool, number"), "{}", err);
+        });
+        Ok(())
+    }
+    
+    #[test]
+    fn test_distinct() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let s = "{items: [{sku: A, qty: 1}, {qty: 1, sku: A}, {sku: B, qty: 1}], tags: [x, y, x]}";
+            let items = distinct(py, s, "items")?;
+            assert!(items.as_ref(py).eq(py.eval("[{'sku': 'A', 'qty': 1}, {'sku': 'B', 'qty': 1}]", None, None)?)?);
+            assert_eq!(distinct(py, "[1, 1, 2]", "")?.extract::<Vec<i64>>(py)?, vec![1, 2]);
+            
+            for path in ["missing", "items[0]"] {
+                let err = distinct(py, s, path).unwrap_err();
+                assert!(err.is_instance_of::<pyo3::exceptions::PyTypeError>(py), "{}", path);
+            }
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_check_roundtrip() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let check = |code: &str, with_path: bool| -> PyResult<PyObject> {
+                check_roundtrip(py, py.eval(code, None, None)?, with_path)
+            };
+            let value = "[1, 2.5, 'a \"quoted\"\\nline', None, True, [{'a': {'b': [1, 2]}}, 3]]";
+            assert!(check(value, false)?.extract::<bool>(py)?);
+            let (ok, path): (bool, Option<String>) = check(value, true)?.extract(py)?;
+            assert_eq!((ok, path), (true, None));
+            
+            // Top-level dicts and tabular lists are read back as `encode` writes them
+            for value in [
+                "{'a': 1}",
+                "{'items': [{'id': 1, 'label': 'A'}, {'id': 2, 'label': 'B'}], 'meta': {'count': 2}}",
+                "[{'id': 1}, {'id': 2}]",
+                // Nested lists of records
+                "{'a': {'b': [{'x': 1}, {'x': 2}]}}",
+                "[[{'x': 1}, {'x': 2}], 3]",
+                "{'groups': [[{'x': 1}], [{'x': 2}, {'x': 3}]]}",
+            ] {
+                let (ok, path): (bool, Option<String>) = check(value, true)?.extract(py)?;
+                assert_eq!((ok, path), (true, None), "{}", value);
+            }
+            let (ok, path): (bool, Option<String>) = check("{'a': 1, 'b': [1, 2 ** 64 + 1]}", true)?.extract(py)?;
+            assert_eq!((ok, path.as_deref()), (false, Some("b[1]")));
+            
+            // Ints beyond the i64 range are encoded as floats, losing precision
+            assert!(!check("[1, [2 ** 64 + 1]]", false)?.extract::<bool>(py)?);
+            let (ok, path): (bool, Option<String>) = check("[1, [2 ** 64 + 1]]", true)?.extract(py)?;
+            assert_eq!((ok, path.as_deref()), (false, Some("[1][0]")));
+            let (_, path): (bool, Option<String>) =
+                check("[{'id': 1, 'n': {'big': -(2 ** 70 + 1)}}, 2]", true)?.extract(py)?;
+            assert_eq!(path.as_deref(), Some("[0].n.big"));
+            
+            // NaN is written as `nan`, which the decoder doesn't read back
+            let (ok, path): (bool, Option<String>) = check("[float('nan')]", true)?.extract(py)?;
+            assert_eq!((ok, path.as_deref()), (false, Some("")));
+            
+            let err = check("[object()]", false).unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyTypeError>(py));
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_has() -> PyResult<()> {
+        let s = "{users: [{id: 1, email: null}, {id: 2}]}";
+        assert!(has(s, "users[0].email")?);
+        assert!(has(s, "users/1/id")?);
+        assert!(!has(s, "users[1].email")?);
+        assert!(!has(s, "users[2]")?);
+        assert!(has("{a: ", "a").is_err());
+        Ok(())
+    }
+    
+    #[test]
+    fn test_sum_and_mean_numbers() -> PyResult<()> {
+        let s = "{scores: [90, 85.5, {bonus: 4.5}], count: 3, label: \"100\", ok: true}";
+        assert_eq!(sum_numbers(s)?, 183.0);
+        assert_eq!(mean_numbers(s)?, Some(45.75));
+        assert_eq!(sum_numbers("{label: x}")?, 0.0);
+        assert_eq!(mean_numbers("[]")?, None);
+        assert!(mean_numbers("[1,").is_err());
+        Ok(())
+    }
+    
+    #[test]
+    fn test_get_or() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let s = "{host: db1, port: 5432, debug: true, tags: [a]}";
+            let get_or = |path: &str, default: &str| -> PyResult<PyObject> {
+                get_or(py, s, path, py.eval(default, None, None)?)
+            };
+            assert_eq!(get_or("host", "'localhost'")?.extract::<String>(py)?, "db1");
+            assert_eq!(get_or("port", "80")?.extract::<i64>(py)?, 5432);
+            assert_eq!(get_or("port", "1.5")?.extract::<i64>(py)?, 5432);
+            assert!(get_or("debug", "False")?.extract::<bool>(py)?);
+            assert_eq!(get_or("tags", "[]")?.extract::<Vec<String>>(py)?, vec!["a"]);
+            
+            // Missing paths and mismatched types give the default
+            assert_eq!(get_or("user", "'admin'")?.extract::<String>(py)?, "admin");
+            assert_eq!(get_or("host", "0")?.extract::<i64>(py)?, 0);
+            assert!(!get_or("port", "False")?.extract::<bool>(py)?);
+            assert_eq!(get_or("debug", "7")?.extract::<i64>(py)?, 7);
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_stringify_numbers() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let obj = stringify_numbers(py, "{id: 12, rates: [0.5, 3]}")?;
+            let expected = py.eval("{'id': '12', 'rates': ['0.5', '3']}", None, None)?;
+            assert!(obj.as_ref(py).eq(expected)?);
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_unwrap() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let obj = unwrap(py, "{result: {id: 1}}", "result")?;
+            assert!(obj.as_ref(py).eq(py.eval("{'id': 1}", None, None)?)?);
+            
+            let obj = unwrap(py, "{result: 1, page: 2}", "result")?;
+            assert!(obj.as_ref(py).eq(py.eval("{'result': 1, 'page': 2}", None, None)?)?);
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_preview() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let obj = preview(py, "{tags: [a, b, c], bio: abcdefgh}", 1, 3)?;
+            let expected = py.eval("{'tags': ['a', '…+2 more'], 'bio': 'abc…'}", None, None)?;
+            assert!(obj.as_ref(py).eq(expected)?);
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_toon_to_json() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let json = py.import("json")?;
+            let toon = "{name: Ann, tags: [a, b], age: 30, score: 9.5, extra: null}";
+            let expected = py.eval(
+                "{'name': 'Ann', 'tags': ['a', 'b'], 'age': 30, 'score': 9.5, 'extra': None}",
+                None,
+                None,
+            )?;
+            
+            for indent in [None, Some(2)] {
+                let out = toon_to_json(toon, indent)?;
+                assert!(json.call_method1("loads", (out,))?.eq(expected)?);
+            }
+            assert!(toon_to_json("[1, 2]", Some(2))?.contains('\n'));
+            assert!(toon_to_json("nan", None).is_err());
+            
+            // And back again
+            assert_eq!(json_to_toon("{\"k\": [1, 2, 3]}")?, "k: [1, 2, 3]");
+            let toon = json_to_toon("[\"hello world\", 1.5, {\"k\": null}]")?;
+            let round_trip = toon_to_json(&toon, None)?;
+            let expected = py.eval("['hello world', 1.5, {'k': None}]", None, None)?;
+            assert!(json.call_method1("loads", (round_trip,))?.eq(expected)?);
+            assert!(json_to_toon("{bad json}").is_err());
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_to_yaml() -> PyResult<()> {
+        assert_eq!(to_yaml("{a: [1, 2], b: on}")?, "a:\n  - 1\n  - 2\nb: \"on\"");
+        assert!(to_yaml("[1,").is_err());
+        Ok(())
+    }
+    
+    #[test]
+    fn test_to_xml() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let xml = to_xml("{order: {id: 3, items: [{sku: a1, qty: 2}, {sku: b2, qty: 1}]}}", "doc")?;
+            let locals = PyDict::new(py);
+            locals.set_item("xml", xml)?;
+            let parsed = py.eval(
+                "[(i.findtext('sku'), i.findtext('qty')) \
+                 for i in __import__('xml.etree.ElementTree').etree.ElementTree.fromstring(xml.encode()).iter('items')]",
+                None,
+                Some(locals),
+            )?;
+            assert!(parsed.eq(py.eval("[('a1', '2'), ('b2', '1')]", None, None)?)?);
+            
+            assert!(to_xml("{\"a b\": 1}", "root").is_err());
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_to_toml() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let toml = to_toml("{name: app, db: {port: 5432}, replicas: [{zone: a}, {zone: b}]}")?;
+            assert_eq!(toml, "name = \"app\"\n\n[db]\nport = 5432\n\n[[replicas]]\nzone = \"a\"\n\n[[replicas]]\nzone = \"b\"");
+            
+            // Python 3.11+ can read the document back
+            if let Ok(tomllib) = py.import("tomllib") {
+                let parsed = tomllib.call_method1("loads", (toml,))?;
+                let expected = py.eval(
+                    "{'name': 'app', 'db': {'port': 5432}, 'replicas': [{'zone': 'a'}, {'zone': 'b'}]}",
+                    None,
+                    None,
+                )?;
+                assert!(parsed.eq(expected)?);
+            }
+            
+            assert!(to_toml("{a: null}").is_err());
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_from_csv() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let rows = from_csv(py, "sku,price\n\"A,1\",9.5\n", true)?;
+            let expected = py.eval("[{'sku': 'A,1', 'price': 9.5}]", None, None)?;
+            assert!(rows.as_ref(py).eq(expected)?);
+            
+            let rows = from_csv(py, "1,2\n3,4", false)?;
+            assert_eq!(rows.extract::<Vec<Vec<i64>>>(py)?, vec![vec![1, 2], vec![3, 4]]);
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_select() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let keys = vec!["id".to_string()];
+            let rows = select(py, "[{id: 1, name: Ann}, {id: 2, name: Bob}]", keys)?;
+            let expected = py.eval("[{'id': 1}, {'id': 2}]", None, None)?;
+            assert!(rows.as_ref(py).eq(expected)?);
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_rename_keys() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let obj = py.eval("[{'firstName': 'Ann', 'age': 3}]", None, None)?;
+            let mapping: HashMap<String, String> =
+                [("firstName".to_string(), "first_name".to_string())].into_iter().collect();
+            let renamed = rename_keys(py, obj, mapping.clone())?;
+            let expected = py.eval("[{'first_name': 'Ann', 'age': 3}]", None, None)?;
+            assert!(renamed.as_ref(py).eq(expected)?);
+            
+            // Keys keep their place, in nested dicts too
+            let obj = py.eval("{'z': 1, 'firstName': 'Ann', 'a': [{'y': 2, 'firstName': 'Bo', 'b': 3}]}", None, None)?;
+            let renamed = rename_keys(py, obj, mapping.clone())?;
+            assert_eq!(
+                renamed.as_ref(py).repr()?.to_str()?,
+                "{'z': 1, 'first_name': 'Ann', 'a': [{'y': 2, 'first_name': 'Bo', 'b': 3}]}"
+            );
+            
+            let obj = py.eval("{'firstName': 1, 'first_name': 2}", None, None)?;
+            let err = rename_keys(py, obj, mapping).unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_convert_file() -> PyResult<()> {
+        let dir = std::env::temp_dir().join(format!("toonify_convert_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        
+        let json = "{\"items\": [{\"id\": 1, \"name\": \"A\"}, {\"id\": 2, \"name\": \"B\"}]}";
+        std::fs::write(path("in.json"), json)?;
+        convert_file(&path("in.json"), &path("out.toon"), "json", "toon")?;
+        let toon = std::fs::read_to_string(path("out.toon"))?;
+        assert_eq!(toon, "items: [id, name]\n1, A\n2, B");
+        
+        // Converting the output back gives the original JSON
+        convert_file(&path("out.toon"), &path("back.json"), "toon", "json")?;
+        let back: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path("back.json"))?).unwrap();
+        assert_eq!(back, serde_json::from_str::<serde_json::Value>(json).unwrap());
+        
+        std::fs::write(path("in.toon"), "[1, {a: x}]")?;
+        convert_file(&path("in.toon"), &path("out.json"), "toon", "json")?;
+        assert_eq!(std::fs::read_to_string(path("out.json"))?, "[1,{\"a\":\"x\"}]");
+        
+        Python::with_gil(|py| {
+            let err = convert_file(&path("in.toon"), &path("x"), "toon", "yaml").unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+            let err = convert_file(&path("missing"), &path("x"), "toon", "json").unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyOSError>(py));
+            
+            // Trailing input is an error rather than silently dropped
+            std::fs::write(path("extra.toon"), "[1] [2]").unwrap();
+            let err = convert_file(&path("extra.toon"), &path("x"), "toon", "json").unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+        });
+        
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+    
+    #[test]
+    fn test_encode_tabular_flag() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let rows = py.eval("[{'id': 1, 'name': 'A'}, {'id': 2, 'name': 'B'}]", None, None)?;
+            assert_eq!(encode(py, rows, true, true, false)?, "[id, name]\n1, A\n2, B");
+            assert_eq!(
+                encode(py, rows, true, false, false)?,
+                "[\n  {id: 1, name: A},\n  {id: 2, name: B}\n]"
+            );
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_coerce() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let schema = py.eval("{'id': 'number', 'tags': ['string']}", None, None)?;
+            let obj = coerce(py, "{id: \"7\", tags: [a, b]}", schema)?;
+            assert!(obj.as_ref(py).eq(py.eval("{'id': 7, 'tags': ['a', 'b']}", None, None)?)?);
+            
+            let err = coerce(py, "{id: seven, tags: []}", schema).unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyTypeError>(py));
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_group_by() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let groups = group_by(py, "[{id: 1, ok: true}, {id: 2, ok: false}, {id: 3, ok: true}]", "ok")?;
+            let expected = py.eval(
+                "{'true': [{'id': 1, 'ok': True}, {'id': 3, 'ok': True}], 'false': [{'id': 2, 'ok': False}]}",
+                None,
+                None,
+            )?;
+            assert!(groups.as_ref(py).eq(expected)?);
+            
+            let err = group_by(py, "{id: 1}", "id").unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyTypeError>(py));
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_set() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let obj = py.eval("{'db': {'host': 'a'}, 'ports': [80, 443]}", None, None)?;
+            let value = py.eval("{'user': 'admin'}", None, None)?;
+            let updated = set(py, obj, "db.auth", value)?;
+            let updated = set(py, updated.as_ref(py), "ports[0]", py.eval("8080", None, None)?)?;
+            let expected = py.eval(
+                "{'db': {'host': 'a', 'auth': {'user': 'admin'}}, 'ports': [8080, 443]}",
+                None,
+                None,
+            )?;
+            assert!(updated.as_ref(py).eq(expected)?);
+            
+            let err = set(py, obj, "db.host.port", value).unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyTypeError>(py));
+            
+            // Key order survives, including in the new value
+            let obj = py.eval("{'z': 1, 'a': {'y': 2, 'b': 3}}", None, None)?;
+            let value = py.eval("{'q': 1, 'c': 2}", None, None)?;
+            let updated = set(py, obj, "a.new", value)?;
+            assert_eq!(
+                updated.as_ref(py).repr()?.to_str()?,
+                "{'z': 1, 'a': {'y': 2, 'b': 3, 'new': {'q': 1, 'c': 2}}}"
+            );
+            
+            // Big ints would come back as floats, so they're rejected
+            let big = py.eval("{'id': 2**70}", None, None)?;
+            let err = set(py, big, "x", value).unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+            let err = set(py, obj, "x", py.eval("-2**63 - 1", None, None)?).unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_remove() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let obj = py.eval("{'a': {'b': 1, 'c': 2}, 'items': [10, 20, 30]}", None, None)?;
+            let (updated, removed) = remove(py, obj, "a.b")?;
+            assert!(removed.as_ref(py).eq(1)?);
+            let (updated, removed) = remove(py, updated.as_ref(py), "items[1]")?;
+            assert!(removed.as_ref(py).eq(20)?);
+            let expected = py.eval("{'a': {'c': 2}, 'items': [10, 30]}", None, None)?;
+            assert!(updated.as_ref(py).eq(expected)?);
+            
+            let (unchanged, removed) = remove(py, obj, "a.zzz")?;
+            assert!(removed.is_none(py));
+            assert!(unchanged.as_ref(py).eq(obj)?);
+            
+            // Both results keep their key order
+            let obj = py.eval("{'z': {'y': 1, 'b': 2}, 'm': 3, 'a': 4}", None, None)?;
+            let (updated, removed) = remove(py, obj, "m")?;
+            assert_eq!(updated.as_ref(py).repr()?.to_str()?, "{'z': {'y': 1, 'b': 2}, 'a': 4}");
+            let (_, removed_dict) = remove(py, obj, "z")?;
+            assert_eq!(removed_dict.as_ref(py).repr()?.to_str()?, "{'y': 1, 'b': 2}");
+            assert!(removed.as_ref(py).eq(3)?);
+            
+            let err = remove(py, py.eval("[2**64, 1]", None, None)?, "[1]").unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_round_all() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let obj = py.eval(
+                "{'total': 10.4567, 'lines': [{'qty': 3, 'price': 1.005e1}, [-2.25, 'x', None]], 'big': 1e308}",
+                None,
+                None,
+            )?;
+            let rounded = round_all(py, obj, 1)?;
+            let expected = py.eval(
+                "{'total': 10.5, 'lines': [{'qty': 3, 'price': 10.1}, [-2.3, 'x', None]], 'big': 1e308}",
+                None,
+                None,
+            )?;
+            assert!(rounded.as_ref(py).eq(expected)?, "{}", rounded.as_ref(py));
+            
+            let whole = round_all(py, py.eval("[2.5, 7]", None, None)?, 0)?;
+            assert!(whole.as_ref(py).eq(py.eval("[3.0, 7]", None, None)?)?);
+            
+            let ordered = round_all(py, py.eval("{'z': 1.25, 'a': [{'y': 2, 'b': 0.5}]}", None, None)?, 1)?;
+            assert_eq!(ordered.as_ref(py).repr()?.to_str()?, "{'z': 1.3, 'a': [{'y': 2, 'b': 0.5}]}");
+            
+            let err = round_all(py, py.eval("{'id': 10**20, 'x': 1.5}", None, None)?, 0).unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_upsert() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let base = py.eval("[{'id': 1, 'qty': 5}, {'id': 2, 'qty': 3}]", None, None)?;
+            let patch = py.eval("[{'id': 2, 'qty': 0}, {'id': 7, 'qty': 1}]", None, None)?;
+            let merged = upsert(py, base, patch, "id")?;
+            let expected = py.eval(
+                "[{'id': 1, 'qty': 5}, {'id': 2, 'qty': 0}, {'id': 7, 'qty': 1}]",
+                None,
+                None,
+            )?;
+            assert!(merged.as_ref(py).eq(expected)?);
+            
+            let err = upsert(py, base, py.eval("[{'qty': 1}]", None, None)?, "id").unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyTypeError>(py));
+            
+            // Replaced and appended records bring their key order with them
+            let patch = py.eval("[{'qty': 0, 'id': 2}, {'zone': 'b', 'id': 7}]", None, None)?;
+            let merged = upsert(py, base, patch, "id")?;
+            assert_eq!(
+                merged.as_ref(py).repr()?.to_str()?,
+                "[{'id': 1, 'qty': 5}, {'qty': 0, 'id': 2}, {'zone': 'b', 'id': 7}]"
+            );
+            
+            let big = py.eval("[{'id': 2**63}]", None, None)?;
+            let err = upsert(py, base, big, "id").unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_to_records() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let (headers, rows) = to_records(py, "[{b: 1, a: x}, {c: true}]")?;
+            assert_eq!(headers.extract::<Vec<String>>(py)?, ["a", "b", "c"]);
+            let expected = py.eval("[['x', 1, None], [None, None, True]]", None, None)?;
+            assert!(rows.as_ref(py).eq(expected)?);
+            
+            assert!(to_records(py, "[1, 2]").is_err());
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_encode_ordered_dict() -> PyResult<()> {
+        // OrderedDict is a dict subclass, so its order is captured like any
+        // dict's, including after reordering and in nested values
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            py.run(
+                "from collections import OrderedDict\n\
+                 d = OrderedDict([('zeta', 1), ('alpha', 2), ('mid', OrderedDict([('y', 1), ('x', 2)]))])\n\
+                 d.move_to_end('zeta')",
+                None,
+                Some(locals),
+            )?;
+            let obj = locals.get_item("d")?.unwrap();
+            assert_eq!(encode(py, obj, true, true, false)?, "alpha: 2\nmid: {y: 1, x: 2}\nzeta: 1");
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_encoder_and_decoder_classes() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            locals.set_item("Encoder", py.get_type::<Encoder>())?;
+            locals.set_item("Decoder", py.get_type::<Decoder>())?;
+            locals.set_item("encode", wrap_pyfunction!(encode, py)?)?;
+            py.run(
+                "data = [{'a': [1, {'b': 2}]}, [3]]\n\
+                 assert Encoder().encode(data) == encode(data)\n\
+                 enc = Encoder().pretty(True).trailing_comma(True)\n\
+                 pretty = enc.encode(data)\n\
+                 assert pretty == '[\\n  {a: [\\n    1,\\n    {b: 2},\\n  ]},\\n  [3],\\n]', pretty\n\
+                 assert enc.encode([[1], [2]]) == '[\\n  [1],\\n  [2],\\n]'\n\
+                 dec = Decoder().allow_trailing_comma(True)\n\
+                 assert dec.decode(pretty) == data\n\
+                 assert dec.decode('[1, 2,]') == [1, 2]\n\
+                 record = {'a': 1, 'rows': [{'id': 1}, {'id': 2}], 'more': [1, {'b': 2}]}\n\
+                 assert Decoder().decode(Encoder().encode(record)) == record\n\
+                 assert dec.decode(enc.encode(record)) == record\n\
+                 base = Encoder()\n\
+                 base.tabular(False)\n\
+                 assert base.encode([{'id': 1}, {'id': 2}]) == '[id]\\n1\\n2'\n\
+                 assert base.tabular(False).encode([{'id': 1}]) == '[\\n  {id: 1}\\n]'\n\
+                 assert Decoder().allow_plus_sign(True).allow_heredoc(True).decode('[+1, <<END\\na\\nEND]') == [1, 'a']",
+                None,
+                Some(locals),
+            )?;
+            
+            // Errors match `encode` and `decode`
+            let err = py.eval("Decoder().decode(pretty)", None, Some(locals)).unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+            assert!(err.value(py).to_string().starts_with("Failed to decode: "));
+            let err = py.eval("Encoder().allow_nan(False).encode([float('nan')])", None, Some(locals)).unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+            let err = py.eval("Encoder().encode([object()])", None, Some(locals)).unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyTypeError>(py));
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_iter_encode() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let obj = py.eval("{'name': 'Ann', 'tags': ['a', 'b'], 'pets': [{'n': 1}, [2]]}", None, None)?;
+            let chunks = Py::new(py, iter_encode(obj, true, true)?)?;
+            let chunks: Vec<String> = py
+                .import("builtins")?
+                .call_method1("list", (chunks,))?
+                .extract()?;
+            assert_eq!(chunks.len(), 3);
+            assert_eq!(chunks.concat(), encode(py, obj, true, true, false)?);
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_encode_dataclasses() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            py.run(
+                "from dataclasses import dataclass\n\
+                 @dataclass\n\
+                 class Point:\n    y: int\n    x: int\n\
+                 @dataclass\n\
+                 class Shape:\n    name: str\n    origin: Point\n    points: list\n\
+                 shape = Shape('tri', Point(0, 1), [Point(2, 3)])",
+                Some(locals),
+                None,
+            )?;
+            let obj = locals.get_item("shape")?.unwrap();
+            assert_eq!(
+                encode(py, obj, true, false, false)?,
+                "name: tri\norigin: {y: 0, x: 1}\npoints: [{y: 2, x: 3}]"
+            );
+            
+            // The class itself is not an instance and stays unsupported
+            let class = locals.get_item("Point")?.unwrap();
+            assert!(encode(py, class, true, true, false).is_err());
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_encode_skip_unsupported() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            locals.set_item("encode", wrap_pyfunction!(encode, py)?)?;
+            py.run(
+                "import os, warnings\n\
+                 handle = open(os.devnull)\n\
+                 data = {'id': 1, 'log': handle, 'tags': ['a', handle]}\n\
+                 with warnings.catch_warnings(record=True) as caught:\n    \
+                     warnings.simplefilter('always')\n    \
+                     out = encode(data, skip_unsupported=True)\n\
+                 handle.close()\n\
+                 messages = [str(w.message) for w in caught if w.category is UserWarning]",
+                Some(locals),
+                None,
+            )?;
+            let out: String = locals.get_item("out")?.unwrap().extract()?;
+            assert_eq!(out, "id: 1\nlog: null\ntags: [a, null]");
+            let messages: Vec<String> = locals.get_item("messages")?.unwrap().extract()?;
+            assert_eq!(messages.len(), 2);
+            assert!(messages[0].contains("unsupported TextIOWrapper object"), "{:?}", messages);
+            
+            // Without the flag the handle is still an error
+            let data = locals.get_item("data")?.unwrap();
+            let err = encode(py, data, true, true, false).unwrap_err();
+            assert!(err.is_instance_of::<pyo3::exceptions::PyTypeError>(py));
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_encode_enums() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            py.run(
+                "from enum import Enum\n\
+                 class Color(Enum):\n    RED = 'red'\n    BLUE = 'blue'\n\
+                 class Level(Enum):\n    LOW = 1\n    HIGH = 2\n\
+                 class Pair(Enum):\n    ORIGIN = [0, Color.RED]\n\
+                 config = {'color': Color.BLUE, 'levels': [Level.HIGH, Level.LOW]}",
+                Some(locals),
+                None,
+            )?;
+            let config = locals.get_item("config")?.unwrap();
+            let (value, _) = py_to_toon_value_ordered(config)?;
+            assert_eq!(
+                value,
+                crate::decoder::decode("{color: blue, levels: [2, 1]}").unwrap()
+            );
+            
+            // Values are converted recursively, including nested members
+            let pair = py.eval("Pair.ORIGIN", Some(locals), None)?;
+            assert_eq!(encode(py, pair, true, true, false)?, "[0, red]");
+            Ok(())
+        })
+    }
+    
+    #[test]
+    fn test_to_query_string() -> PyResult<()> {
+        Python::with_gil(|py| {
+            let query = to_query_string("{id: [1, 2], name: \"a b\"}")?;
+            assert_eq!(query, "id=1&id=2&name=a%20b");
+            let parsed = py.import("urllib.parse")?.call_method1("parse_qs", (query,))?;
+            let expected = py.eval("{'id': ['1', '2'], 'name': ['a b']}", None, None)?;
+            assert!(parsed.eq(expected)?);
             
+            assert!(to_query_string("{a: {b: 1}}").is_err());
             Ok(())
         })
     }
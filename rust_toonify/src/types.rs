@@ -1,16 +1,27 @@
 //! Core data types for the TOON format
 
+use std::cmp::Ordering;
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Index;
+
+use crate::ToonError;
 
 /// Represents a value in the TOON format
-#[derive(Debug, Clone, PartialEq)]
+///
+/// Values are totally ordered (see the [`Ord`] impl) and hashable, so they
+/// can be sorted or stored in a `BTreeSet` or `HashSet`.
+#[derive(Debug, Clone)]
 pub enum ToonValue {
     /// Represents a null value
     Null,
     /// Represents a boolean value
     Bool(bool),
-    /// Represents a numeric value (f64 can represent all JSON numbers)
+    /// Represents an integral value parsed from (or converted to) an integer literal
+    Integer(i64),
+    /// Represents a floating-point numeric value
     Number(f64),
     /// Represents a string value
     String(String),
@@ -34,14 +45,36 @@ impl ToonValue {
         }
     }
 
-    /// Get the value as a number if it is one
+    /// Get the value as a number if it is one (integers are widened to f64)
     pub fn as_number(&self) -> Option<f64> {
         match self {
+            ToonValue::Integer(i) => Some(*i as f64),
             ToonValue::Number(n) => Some(*n),
             _ => None,
         }
     }
 
+    /// Get the value as a number, coercing where that's unambiguous
+    ///
+    /// Besides numbers, strings holding a finite number (surrounding
+    /// whitespace allowed, as in `" 42 "` or `"1e3"`) are parsed and bools
+    /// map to `1.0` and `0.0`. Everything else, including `"nan"`, is `None`.
+    pub fn as_f64_lossy(&self) -> Option<f64> {
+        match self {
+            ToonValue::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+            ToonValue::String(s) => s.trim().parse::<f64>().ok().filter(|n| n.is_finite()),
+            _ => self.as_number(),
+        }
+    }
+
+    /// Get the value as an integer if it is one
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            ToonValue::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
     /// Get the value as a string slice if it is one
     pub fn as_str(&self) -> Option<&str> {
         match self {
@@ -89,131 +122,1807 @@ impl ToonValue {
             _ => None,
         }
     }
-}
 
-impl fmt::Display for ToonValue {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    /// Iterate over an object's entries in sorted key order
+    ///
+    /// Yields nothing for values that are not objects.
+    pub fn iter_sorted(&self) -> impl Iterator<Item = (&String, &ToonValue)> {
+        let mut entries: Vec<_> = self.as_object().into_iter().flatten().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        entries.into_iter()
+    }
+
+    /// Get the map entry for `key` if the value is an object
+    pub fn entry(&mut self, key: &str) -> Option<Entry<'_, String, ToonValue>> {
+        self.as_object_mut().map(|map| map.entry(key.to_string()))
+    }
+
+    /// Get a mutable reference to the value at `key`, inserting the result of
+    /// `default` first if the key is absent
+    ///
+    /// Returns `None` if the value is not an object.
+    pub fn get_or_insert_with<F>(&mut self, key: &str, default: F) -> Option<&mut ToonValue>
+    where
+        F: FnOnce() -> ToonValue,
+    {
+        self.entry(key).map(|entry| entry.or_insert_with(default))
+    }
+
+    /// Count every node in the tree, including this one and all containers
+    pub fn node_count(&self) -> usize {
+        1 + match self {
+            ToonValue::Array(arr) => arr.iter().map(ToonValue::node_count).sum(),
+            ToonValue::Object(map) => map.values().map(ToonValue::node_count).sum(),
+            _ => 0,
+        }
+    }
+
+    /// Maximum container nesting depth
+    ///
+    /// Scalars have depth 0 and each enclosing array or object adds one, so
+    /// `[]` and `[1]` have depth 1 while `{"a": [1]}` has depth 2.
+    pub fn depth(&self) -> usize {
         match self {
-            ToonValue::Null => write!(f, "null"),
-            ToonValue::Bool(b) => write!(f, "{}", b),
-            ToonValue::Number(n) => {
-                // Format integers without decimal part for better readability
-                if n.fract() == 0.0 {
-                    write!(f, "{:.0}", n)
-                } else {
-                    write!(f, "{}", n)
+            ToonValue::Array(arr) => 1 + arr.iter().map(ToonValue::depth).max().unwrap_or(0),
+            ToonValue::Object(map) => 1 + map.values().map(ToonValue::depth).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    /// Sum every number in the tree, returning the sum and how many numbers
+    /// went into it
+    ///
+    /// Integers and floats count, wherever they are nested; bools, numeric
+    /// strings and all other values are ignored. Divide the sum by the count
+    /// for the mean. A NaN anywhere makes the sum NaN.
+    pub fn aggregate_numbers(&self) -> (f64, usize) {
+        match self {
+            ToonValue::Integer(i) => (*i as f64, 1),
+            ToonValue::Number(n) => (*n, 1),
+            ToonValue::Array(arr) => sum_aggregates(arr.iter()),
+            ToonValue::Object(map) => sum_aggregates(map.values()),
+            _ => (0.0, 0),
+        }
+    }
+
+    /// Look up a nested value by path, returning `None` if any segment misses
+    ///
+    /// Segments are separated by `/` or `.` and array indices may also be
+    /// written as `[N]`, so `"address/city"`, `"address.city"`,
+    /// `"hobbies/0"` and `"hobbies[0]"` are all valid. A numeric segment
+    /// indexes into arrays and is used as a plain key on objects. Negative
+    /// indices count from the end, so `"hobbies/-1"` is the last element.
+    /// The empty path refers to the value itself.
+    pub fn pointer(&self, path: &str) -> Option<&ToonValue> {
+        self.pointer_segments(path_segments(path))
+    }
+
+    fn pointer_segments<'s>(&self, segments: impl IntoIterator<Item = &'s str>) -> Option<&ToonValue> {
+        segments.into_iter().try_fold(self, |value, segment| match value {
+            ToonValue::Object(map) => map.get(segment),
+            ToonValue::Array(arr) => array_index(segment, arr.len()).map(|i| &arr[i]),
+            _ => None,
+        })
+    }
+
+    /// Set the value at `path`, creating missing objects along the way
+    ///
+    /// Uses the path syntax of [`ToonValue::pointer`]. A missing key creates
+    /// an object (even if the next segment looks like an index), while array
+    /// indices must already exist. Stepping into anything other than an
+    /// object or array, `null` included, is a `TypeError`, in which case the
+    /// value is left unmodified. The empty path replaces the whole value.
+    pub fn set(&mut self, path: &str, value: ToonValue) -> Result<(), ToonError> {
+        let segments: Vec<&str> = path_segments(path).collect();
+        self.set_segments(&segments, 0, value)
+    }
+
+    fn set_segments(&mut self, segments: &[&str], depth: usize, value: ToonValue) -> Result<(), ToonError> {
+        let Some(&segment) = segments.get(depth) else {
+            *self = value;
+            return Ok(());
+        };
+        let last = depth + 1 == segments.len();
+        match self {
+            ToonValue::Object(map) => map
+                .entry(segment.to_string())
+                .or_insert_with(|| if last { ToonValue::Null } else { ToonValue::Object(HashMap::new()) })
+                .set_segments(segments, depth + 1, value),
+            ToonValue::Array(arr) => match array_index(segment, arr.len()) {
+                Some(i) => arr[i].set_segments(segments, depth + 1, value),
+                None => Err(ToonError::TypeError(format!(
+                    "index {} is out of range for the array of length {} {}",
+                    segment,
+                    arr.len(),
+                    path_label(&segments[..depth].join("."))
+                ))),
+            },
+            other => Err(ToonError::TypeError(format!(
+                "cannot set '{}' inside a {} {}",
+                segment,
+                other.schema_type_name(),
+                path_label(&segments[..depth].join("."))
+            ))),
+        }
+    }
+
+    /// Like [`ToonValue::remove`], also dropping the removed key or element
+    /// from `order` and returning the removed value's own ordering with it
+    pub fn remove_ordered(&mut self, order: &mut KeyOrder, path: &str) -> Option<(ToonValue, KeyOrder)> {
+        let segments: Vec<&str> = path_segments(path).collect();
+        let (last, parents) = segments.split_last()?;
+        self.pointer_segments(segments.iter().copied())?;
+        let removed_order = match (self.pointer_segments(parents.iter().copied())?, order.slot(self, parents)) {
+            (ToonValue::Object(_), KeyOrder::Object(keys)) => {
+                keys.iter().position(|(key, _)| key == last).map(|i| keys.remove(i).1)
+            }
+            (ToonValue::Array(arr), KeyOrder::Array(items)) => {
+                array_index(last, arr.len()).filter(|&i| i < items.len()).map(|i| items.remove(i))
+            }
+            _ => None,
+        };
+        let removed = self.remove(path)?;
+        Some((removed, removed_order.unwrap_or_default()))
+    }
+
+    /// Like [`ToonValue::set`], also recording `value_order` as the ordering
+    /// of the value now at `path` in `order`; a new key goes after the keys
+    /// `order` already lists
+    pub fn set_ordered(
+        &mut self,
+        order: &mut KeyOrder,
+        path: &str,
+        value: ToonValue,
+        value_order: KeyOrder,
+    ) -> Result<(), ToonError> {
+        self.set(path, value)?;
+        let segments: Vec<&str> = path_segments(path).collect();
+        *order.slot(self, &segments) = value_order;
+        Ok(())
+    }
+
+    /// Remove and return the value at `path`
+    ///
+    /// Uses the path syntax of [`ToonValue::pointer`]. Removing an array
+    /// element shifts the ones after it down. Returns `None`, leaving the
+    /// value unmodified, if the path doesn't exist or is empty.
+    pub fn remove(&mut self, path: &str) -> Option<ToonValue> {
+        let segments: Vec<&str> = path_segments(path).collect();
+        let (last, parents) = segments.split_last()?;
+        let parent = parents.iter().try_fold(self, |value, segment| match value {
+            ToonValue::Object(map) => map.get_mut(*segment),
+            ToonValue::Array(arr) => array_index(segment, arr.len()).map(|i| &mut arr[i]),
+            _ => None,
+        })?;
+        match parent {
+            ToonValue::Object(map) => map.remove(*last),
+            ToonValue::Array(arr) => array_index(last, arr.len()).map(|i| arr.remove(i)),
+            _ => None,
+        }
+    }
+
+    /// Whether [`ToonValue::pointer`] finds a value at `path`
+    ///
+    /// A path leading to `null` exists; only a missing key or index doesn't.
+    pub fn contains_path(&self, path: &str) -> bool {
+        self.pointer(path).is_some()
+    }
+
+    /// Whether this is a string holding a strict ISO-8601 date or date-time
+    ///
+    /// TOON has no date type, so timestamps travel as strings; this picks out
+    /// `2024-06-01` and `2024-06-01T12:30:00Z` (with optional fraction and
+    /// `±HH:MM` offset) but not looser forms like `2024-06-01 12:30`.
+    pub fn looks_like_timestamp(&self) -> bool {
+        matches!(self, ToonValue::String(s) if crate::utils::is_iso8601_timestamp(s))
+    }
+
+    /// Collect every timestamp-shaped string with its path, in
+    /// [`ToonValue::walk`] order
+    pub fn timestamps(&self) -> Vec<(String, &str)> {
+        self.walk()
+            .filter(|(_, value)| value.looks_like_timestamp())
+            .filter_map(|(path, value)| value.as_str().map(|s| (path, s)))
+            .collect()
+    }
+
+    /// Collect every node matching `pred` with its path, in
+    /// [`ToonValue::walk`] order
+    ///
+    /// Containers are tested as well as leaves, and the value itself is
+    /// included, with the empty path, if it matches.
+    pub fn find_all<F: Fn(&ToonValue) -> bool>(&self, pred: F) -> Vec<(String, &ToonValue)> {
+        self.walk().filter(|(_, value)| pred(value)).collect()
+    }
+
+    /// Iterate over every node with its path, depth first
+    ///
+    /// The value itself comes first with the empty path, followed by each
+    /// container's children: object entries in sorted key order (`"a"`,
+    /// `"a.b"`) and array elements in index order (`"tags[0]"`). Paths use
+    /// the syntax accepted by [`ToonValue::pointer`] as long as keys contain
+    /// no `.`, `/` or `[`.
+    pub fn walk(&self) -> impl Iterator<Item = (String, &ToonValue)> {
+        Walk { stack: vec![(String::new(), self)] }
+    }
+
+    /// Look up a nested value by path and convert it to `T`
+    ///
+    /// Uses the same path syntax as [`ToonValue::pointer`]. Returns `None` if
+    /// the path misses or the leaf cannot be converted; see
+    /// [`FromToonValue`] for the conversions each type accepts.
+    pub fn get_path_as<T: FromToonValue>(&self, path: &str) -> Option<T> {
+        self.pointer(path).and_then(T::from_toon_value)
+    }
+
+    /// Get the string at `path`, or `default` if it is missing or not a string
+    ///
+    /// Paths use the syntax of [`ToonValue::pointer`], so a plain key works
+    /// too. The same goes for [`ToonValue::get_number_or`] and
+    /// [`ToonValue::get_bool_or`].
+    pub fn get_str_or<'a>(&'a self, path: &str, default: &'a str) -> &'a str {
+        self.pointer(path).and_then(ToonValue::as_str).unwrap_or(default)
+    }
+
+    /// Get the number at `path` (integers are widened to f64), or `default`
+    /// if it is missing or not a number
+    pub fn get_number_or(&self, path: &str, default: f64) -> f64 {
+        self.pointer(path).and_then(ToonValue::as_number).unwrap_or(default)
+    }
+
+    /// Get the boolean at `path`, or `default` if it is missing or not a
+    /// boolean
+    pub fn get_bool_or(&self, path: &str, default: bool) -> bool {
+        self.pointer(path).and_then(ToonValue::as_bool).unwrap_or(default)
+    }
+
+    /// Build a tagged value, the usual encoding of an enum variant
+    ///
+    /// The result is an object holding the variant name under `tag_key` and
+    /// its payload under [`TAGGED_DATA_KEY`], e.g. `{type: Circle, data:
+    /// {radius: 2}}`, so payloads of any type (not only objects) can be
+    /// tagged. See [`ToonValue::as_tagged`] for the reverse.
+    pub fn tagged(tag_key: &str, tag: &str, data: ToonValue) -> ToonValue {
+        let mut map = HashMap::with_capacity(2);
+        map.insert(tag_key.to_string(), ToonValue::String(tag.to_string()));
+        map.insert(TAGGED_DATA_KEY.to_string(), data);
+        ToonValue::Object(map)
+    }
+
+    /// Split a value built by [`ToonValue::tagged`] into its tag and payload
+    ///
+    /// Returns `None` unless this is an object with a string under `tag_key`.
+    /// A missing payload is treated as null, so unit variants can be written
+    /// as just `{type: Empty}`.
+    pub fn as_tagged(&self, tag_key: &str) -> Option<(&str, &ToonValue)> {
+        let map = self.as_object()?;
+        let tag = map.get(tag_key)?.as_str()?;
+        Some((tag, map.get(TAGGED_DATA_KEY).unwrap_or(&NULL)))
+    }
+
+    /// Compare two values, treating numbers within `epsilon` of each other as
+    /// equal
+    ///
+    /// Integers and floats compare by value here (so `1` matches `1.0`), and
+    /// NaN matches NaN as in `==`. Everything else must match exactly, with
+    /// arrays and objects compared structurally.
+    pub fn approx_eq(&self, other: &ToonValue, epsilon: f64) -> bool {
+        match (self, other) {
+            (ToonValue::Array(a), ToonValue::Array(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.approx_eq(y, epsilon))
+            }
+            (ToonValue::Object(a), ToonValue::Object(b)) => {
+                a.len() == b.len()
+                    && a.iter().all(|(k, x)| b.get(k).is_some_and(|y| x.approx_eq(y, epsilon)))
+            }
+            _ => match (self.as_number(), other.as_number()) {
+                (Some(a), Some(b)) => a == b || (a - b).abs() <= epsilon || (a.is_nan() && b.is_nan()),
+                _ => self == other,
+            },
+        }
+    }
+
+    /// Convert an array of objects into a header and a matrix of rows
+    ///
+    /// The header is the sorted union of all keys, and each row holds one
+    /// object's values in header order, with null for missing fields. Unlike
+    /// tabular encoding, the objects don't need matching keys and values may
+    /// be nested. Returns `None` unless every element is an object.
+    pub fn to_records(&self) -> Option<(Vec<String>, Vec<Vec<ToonValue>>)> {
+        let objects = self
+            .as_array()?
+            .iter()
+            .map(ToonValue::as_object)
+            .collect::<Option<Vec<_>>>()?;
+        
+        let mut headers: Vec<String> = objects.iter().flat_map(|obj| obj.keys().cloned()).collect();
+        headers.sort();
+        headers.dedup();
+        
+        let rows = objects
+            .iter()
+            .map(|obj| {
+                headers
+                    .iter()
+                    .map(|key| obj.get(key).cloned().unwrap_or(ToonValue::Null))
+                    .collect()
+            })
+            .collect();
+        
+        Some((headers, rows))
+    }
+
+    /// Return the inner value of a single-key wrapper object
+    ///
+    /// Returns `Some` only if this is an object whose one and only key is
+    /// `key`, as in `{result: {...}}`.
+    pub fn unwrap_key(&self, key: &str) -> Option<&ToonValue> {
+        match self {
+            ToonValue::Object(map) if map.len() == 1 => map.get(key),
+            _ => None,
+        }
+    }
+
+    /// Project an object (or each object in an array) onto the given keys
+    ///
+    /// Keys missing from an object are simply left out. Array elements that
+    /// aren't objects, and scalar values, are returned unchanged.
+    pub fn select(&self, keys: &[&str]) -> ToonValue {
+        match self {
+            ToonValue::Object(map) => ToonValue::Object(
+                keys.iter()
+                    .filter_map(|key| map.get_key_value(*key))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+            ),
+            ToonValue::Array(arr) => ToonValue::Array(
+                arr.iter()
+                    .map(|item| match item {
+                        ToonValue::Object(_) => item.select(keys),
+                        _ => item.clone(),
+                    })
+                    .collect(),
+            ),
+            _ => self.clone(),
+        }
+    }
+
+    /// Return a copy cut down to a previewable size
+    ///
+    /// Arrays longer than `max_array` keep their first `max_array` elements
+    /// followed by a `"…+N more"` marker string, and strings longer than
+    /// `max_string` characters are cut and end in `…`. This applies at every
+    /// level; object keys are left as they are.
+    pub fn truncate(&self, max_array: usize, max_string: usize) -> ToonValue {
+        match self {
+            ToonValue::String(s) if s.chars().count() > max_string => {
+                let mut cut: String = s.chars().take(max_string).collect();
+                cut.push('…');
+                ToonValue::String(cut)
+            }
+            ToonValue::Array(arr) => {
+                let mut items: Vec<ToonValue> = arr
+                    .iter()
+                    .take(max_array)
+                    .map(|item| item.truncate(max_array, max_string))
+                    .collect();
+                if arr.len() > max_array {
+                    items.push(ToonValue::String(format!("…+{} more", arr.len() - max_array)));
                 }
+                ToonValue::Array(items)
             }
-            ToonValue::String(s) => write!(f, "\"{}\"", s.escape_default()),
+            ToonValue::Object(map) => ToonValue::Object(
+                map.iter()
+                    .map(|(k, v)| (k.clone(), v.truncate(max_array, max_string)))
+                    .collect(),
+            ),
+            _ => self.clone(),
+        }
+    }
+
+    /// Recursively rename object keys according to `mapping`
+    ///
+    /// Keys not in the mapping are kept as they are, and keys can be swapped
+    /// (`a` to `b` and `b` to `a`). If a rename would produce a key that
+    /// already exists in the same object, an error is returned and the value
+    /// is left unmodified.
+    pub fn rename_keys(&mut self, mapping: &HashMap<String, String>) -> Result<(), ToonError> {
+        self.check_renames(mapping)?;
+        self.apply_renames(mapping);
+        Ok(())
+    }
+
+    /// Like [`ToonValue::rename_keys`], also renaming the keys in `order` so
+    /// every renamed key keeps its place
+    pub fn rename_keys_ordered(
+        &mut self,
+        order: &mut KeyOrder,
+        mapping: &HashMap<String, String>,
+    ) -> Result<(), ToonError> {
+        self.rename_keys(mapping)?;
+        order.rename_keys(mapping);
+        Ok(())
+    }
+
+    fn check_renames(&self, mapping: &HashMap<String, String>) -> Result<(), ToonError> {
+        match self {
+            ToonValue::Array(arr) => arr.iter().try_for_each(|item| item.check_renames(mapping)),
+            ToonValue::Object(map) => {
+                let mut renamed = HashMap::with_capacity(map.len());
+                for key in map.keys() {
+                    let new_key = mapping.get(key).unwrap_or(key);
+                    if let Some(other) = renamed.insert(new_key, key) {
+                        let (from, to) = if mapping.contains_key(key) { (key, other) } else { (other, key) };
+                        return Err(ToonError::InvalidFormat(format!(
+                            "renaming key '{}' to '{}' collides with key '{}'",
+                            from, new_key, to
+                        )));
+                    }
+                }
+                map.values().try_for_each(|value| value.check_renames(mapping))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn apply_renames(&mut self, mapping: &HashMap<String, String>) {
+        match self {
+            ToonValue::Array(arr) => arr.iter_mut().for_each(|item| item.apply_renames(mapping)),
+            ToonValue::Object(map) => {
+                *map = std::mem::take(map)
+                    .into_iter()
+                    .map(|(key, mut value)| {
+                        value.apply_renames(mapping);
+                        (mapping.get(&key).cloned().unwrap_or(key), value)
+                    })
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+
+    /// Upsert the objects of the array `other` into this array, matching
+    /// elements on their `key` field
+    ///
+    /// An element of `other` replaces the first element of `self` whose `key`
+    /// field is equal, or is appended if there is none, so later duplicates
+    /// in `other` win. Elements of `self` that lack the field are never
+    /// matched. Both values must be arrays and every element of `other` must
+    /// be an object with the field; otherwise a `TypeError` is returned and
+    /// `self` is left unmodified.
+    pub fn merge_arrays_by_key(&mut self, other: &ToonValue, key: &str) -> Result<(), ToonError> {
+        self.merge_arrays_by_key_ordered(&mut KeyOrder::None, other, &KeyOrder::None, key)
+    }
+
+    /// Like [`ToonValue::merge_arrays_by_key`], also moving the ordering of
+    /// each element of `other`, taken from `other_order`, into `order`
+    /// along with the element
+    pub fn merge_arrays_by_key_ordered(
+        &mut self,
+        order: &mut KeyOrder,
+        other: &ToonValue,
+        other_order: &KeyOrder,
+        key: &str,
+    ) -> Result<(), ToonError> {
+        let (ToonValue::Array(base), ToonValue::Array(patch)) = (&mut *self, other) else {
+            return Err(ToonError::TypeError(
+                "merge_arrays_by_key requires two arrays".to_string(),
+            ));
+        };
+        if let Some(i) = patch.iter().position(|item| item.as_object().and_then(|map| map.get(key)).is_none()) {
+            return Err(ToonError::TypeError(format!(
+                "element {} of the patch is not an object with key '{}'",
+                i, key
+            )));
+        }
+        let mut orders = match std::mem::take(order) {
+            KeyOrder::Array(items) => items,
+            _ => Vec::new(),
+        };
+        for (i, item) in patch.iter().enumerate() {
+            let id = item.as_object().and_then(|map| map.get(key));
+            let index = match base.iter().position(|existing| existing.as_object().and_then(|map| map.get(key)) == id) {
+                Some(index) => {
+                    base[index] = item.clone();
+                    index
+                }
+                None => {
+                    base.push(item.clone());
+                    base.len() - 1
+                }
+            };
+            if orders.len() <= index {
+                orders.resize(index + 1, KeyOrder::None);
+            }
+            orders[index] = other_order.element(i).clone();
+        }
+        *order = KeyOrder::Array(orders);
+        Ok(())
+    }
+
+    /// Group an array of objects by the value of their `key` field
+    ///
+    /// Returns an object mapping each distinct value, stringified, to an
+    /// array of the objects holding it in their original order. Strings are
+    /// used as they are and other values in their `Display` form, so `1` and
+    /// `1.0` form separate groups; objects without the field are grouped
+    /// under `"null"`. Returns a `TypeError` if this isn't an array of
+    /// objects.
+    pub fn group_by(&self, key: &str) -> Result<ToonValue, ToonError> {
+        let ToonValue::Array(arr) = self else {
+            return Err(ToonError::TypeError("group_by requires an array of objects".to_string()));
+        };
+        let mut groups: HashMap<String, Vec<ToonValue>> = HashMap::new();
+        for (i, item) in arr.iter().enumerate() {
+            let ToonValue::Object(map) = item else {
+                return Err(ToonError::TypeError(format!("element {} is not an object", i)));
+            };
+            let group = match map.get(key) {
+                Some(ToonValue::String(s)) => s.clone(),
+                Some(value) => value.to_string(),
+                None => "null".to_string(),
+            };
+            groups.entry(group).or_default().push(item.clone());
+        }
+        Ok(ToonValue::Object(
+            groups.into_iter().map(|(group, members)| (group, ToonValue::Array(members))).collect(),
+        ))
+    }
+
+    /// Recursively sort every array using the total order on `ToonValue`
+    ///
+    /// Nested values are sorted first, so arrays of arrays and objects end up
+    /// equal whenever their contents are equal up to order. Encoding the
+    /// result with [`KeyOrder::sorted`] then gives a canonical form for
+    /// documents whose arrays are unordered sets.
+    pub fn sort_all_arrays(&mut self) {
+        match self {
             ToonValue::Array(arr) => {
-                write!(f, "[")?;
-                for (i, item) in arr.iter().enumerate() {
-                    if i > 0 {
-                        write!(f, ", ")?;
+                arr.iter_mut().for_each(ToonValue::sort_all_arrays);
+                arr.sort();
+            }
+            ToonValue::Object(map) => map.values_mut().for_each(ToonValue::sort_all_arrays),
+            _ => {}
+        }
+    }
+
+    /// Remove repeated elements from an array, keeping the first occurrence
+    /// of each
+    ///
+    /// Elements are compared with the `Eq` impl, so nested arrays and objects
+    /// count as repeats when their contents are equal, whatever their key
+    /// order. Only this array is deduplicated, not arrays nested inside it.
+    /// Other values are left unchanged.
+    pub fn dedup_array(&mut self) {
+        if let ToonValue::Array(arr) = self {
+            let keep: Vec<bool> = {
+                let mut seen = std::collections::HashSet::with_capacity(arr.len());
+                arr.iter().map(|item| seen.insert(item)).collect()
+            };
+            let mut keep = keep.into_iter();
+            arr.retain(|_| keep.next().unwrap_or(true));
+        }
+    }
+
+    /// Apply `f` to every primitive leaf in place, depth first
+    ///
+    /// Leaves are nulls, bools, numbers and strings; empty arrays and objects
+    /// are containers, not leaves, and are skipped. `f` may replace a leaf
+    /// with any value, but its replacement is not visited.
+    pub fn for_each_leaf_mut<F: FnMut(&mut ToonValue)>(&mut self, mut f: F) {
+        self.visit_leaves_mut(&mut f);
+    }
+
+    fn visit_leaves_mut<F: FnMut(&mut ToonValue)>(&mut self, f: &mut F) {
+        match self {
+            ToonValue::Array(arr) => arr.iter_mut().for_each(|item| item.visit_leaves_mut(f)),
+            ToonValue::Object(map) => map.values_mut().for_each(|value| value.visit_leaves_mut(f)),
+            leaf => f(leaf),
+        }
+    }
+
+    /// Recursively replace every number with its encoded string form
+    ///
+    /// Integers and floats alike become strings (`42` becomes `"42"`, `2.50`
+    /// becomes `"2.5"`). Object keys are left untouched.
+    pub fn stringify_numbers(&mut self) {
+        match self {
+            ToonValue::Integer(i) => *self = ToonValue::String(i.to_string()),
+            ToonValue::Number(n) => *self = ToonValue::String(crate::utils::format_number(*n)),
+            ToonValue::Array(arr) => arr.iter_mut().for_each(ToonValue::stringify_numbers),
+            ToonValue::Object(map) => map.values_mut().for_each(ToonValue::stringify_numbers),
+            _ => {}
+        }
+    }
+
+    /// Infer a rough schema describing the structure of this value
+    ///
+    /// Scalars become their type name (`"null"`, `"bool"`, `"number"` or
+    /// `"string"`), objects become objects of field schemas and arrays become a
+    /// one-element array holding the merged schema of all elements (or an empty
+    /// array if there are none). Mixed types are joined with `|`, e.g.
+    /// `"number|string"`, with `null` always listed last. A field missing from
+    /// some objects in an array is marked optional by merging it with `"null"`.
+    pub fn infer_schema(&self) -> ToonValue {
+        match self {
+            ToonValue::Null => ToonValue::String("null".to_string()),
+            ToonValue::Bool(_) => ToonValue::String("bool".to_string()),
+            ToonValue::Integer(_) | ToonValue::Number(_) => ToonValue::String("number".to_string()),
+            ToonValue::String(_) => ToonValue::String("string".to_string()),
+            ToonValue::Array(arr) => {
+                let merged = arr
+                    .iter()
+                    .map(ToonValue::infer_schema)
+                    .reduce(merge_schemas);
+                ToonValue::Array(merged.into_iter().collect())
+            }
+            ToonValue::Object(map) => ToonValue::Object(
+                map.iter()
+                    .map(|(k, v)| (k.clone(), v.infer_schema()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Coerce this value to fit `schema`, written in the format produced by
+    /// [`ToonValue::infer_schema`]
+    ///
+    /// Values that already fit are kept. Strings are converted where the
+    /// schema expects a number (`"42"` becomes `42`) or a boolean (`"true"`,
+    /// `"false"`) instead. Fields the schema doesn't mention are kept as
+    /// they are, a missing field is only allowed if its schema includes
+    /// `null`, and an empty array schema accepts any elements. Anything that
+    /// can't be reconciled is a `TypeError` naming the path of the offending
+    /// value, such as `users[1].age`.
+    pub fn coerce_to(&self, schema: &ToonValue) -> Result<ToonValue, ToonError> {
+        self.coerce_at(schema, "")
+    }
+
+    fn coerce_at(&self, schema: &ToonValue, path: &str) -> Result<ToonValue, ToonError> {
+        match (schema, self) {
+            (ToonValue::Object(fields), ToonValue::Object(map)) => {
+                let mut coerced = HashMap::with_capacity(map.len());
+                for (key, value) in self.iter_sorted() {
+                    let child_path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                    let value = match fields.get(key) {
+                        Some(field) => value.coerce_at(field, &child_path)?,
+                        None => value.clone(),
+                    };
+                    coerced.insert(key.clone(), value);
+                }
+                for (key, field) in schema.iter_sorted() {
+                    if !map.contains_key(key) && !schema_type_names(field).iter().any(|n| n == "null") {
+                        return Err(ToonError::TypeError(format!(
+                            "missing required field '{}' {}",
+                            key, path_label(path)
+                        )));
                     }
-                    write!(f, "{}", item)?;
                 }
-                write!(f, "]")
+                Ok(ToonValue::Object(coerced))
             }
-            ToonValue::Object(obj) => {
-                write!(f, "{{")?;
-                for (i, (k, v)) in obj.iter().enumerate() {
-                    if i > 0 {
-                        write!(f, ", ")?;
+            (ToonValue::Array(items), ToonValue::Array(arr)) => match items.first() {
+                Some(item) => arr
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| value.coerce_at(item, &format!("{}[{}]", path, i)))
+                    .collect::<Result<_, _>>()
+                    .map(ToonValue::Array),
+                None => Ok(self.clone()),
+            },
+            (ToonValue::String(names), _) => {
+                let names: Vec<&str> = names.split('|').collect();
+                if names.contains(&self.schema_type_name()) {
+                    return Ok(self.clone());
+                }
+                if let ToonValue::String(s) = self {
+                    let s = s.trim();
+                    if names.contains(&"number") {
+                        if let Ok(i) = s.parse::<i64>() {
+                            return Ok(ToonValue::Integer(i));
+                        }
+                        if let Some(n) = s.parse::<f64>().ok().filter(|n| n.is_finite()) {
+                            return Ok(ToonValue::Number(n));
+                        }
+                    }
+                    if names.contains(&"bool") {
+                        match s {
+                            "true" => return Ok(ToonValue::Bool(true)),
+                            "false" => return Ok(ToonValue::Bool(false)),
+                            _ => {}
+                        }
                     }
-                    write!(f, "\"{}\": {}", k.escape_default(), v)?;
                 }
-                write!(f, "}}")
+                Err(ToonError::TypeError(format!(
+                    "expected {} {}, found {}",
+                    names.join(" or "),
+                    path_label(path),
+                    self.schema_type_name()
+                )))
             }
+            (ToonValue::Object(_) | ToonValue::Array(_), _) => Err(ToonError::TypeError(format!(
+                "expected {} {}, found {}",
+                schema_type_names(schema).join(""),
+                path_label(path),
+                self.schema_type_name()
+            ))),
+            _ => Err(ToonError::TypeError(format!(
+                "invalid schema node {} {}",
+                schema,
+                path_label(path)
+            ))),
+        }
+    }
+
+    /// The name [`ToonValue::infer_schema`] uses for this value's type
+    pub(crate) fn schema_type_name(&self) -> &'static str {
+        match self {
+            ToonValue::Null => "null",
+            ToonValue::Bool(_) => "bool",
+            ToonValue::Integer(_) | ToonValue::Number(_) => "number",
+            ToonValue::String(_) => "string",
+            ToonValue::Array(_) => "array",
+            ToonValue::Object(_) => "object",
         }
     }
 }
 
-/// Options for encoding ToonValue to a string
-#[derive(Debug, Clone, Copy)]
-pub struct EncodeOptions {
-    /// Whether to pretty-print the output
-    pub pretty: bool,
-    /// Number of spaces to use for indentation (if pretty-printing)
-    pub indent: usize,
-    /// Whether to escape non-ASCII characters
-    pub escape_non_ascii: bool,
+/// Split a path into its key/index segments
+fn path_segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split(['/', '.', '['])
+        .map(|segment| segment.strip_suffix(']').unwrap_or(segment))
+        .filter(|segment| !segment.is_empty())
 }
 
-impl Default for EncodeOptions {
-    fn default() -> Self {
-        Self {
-            pretty: false,
-            indent: 2,
-            escape_non_ascii: false,
+/// Resolve an index path segment against an array of length `len`
+///
+/// `-N` counts from the end; indices out of range either way give `None`.
+fn array_index(segment: &str, len: usize) -> Option<usize> {
+    let index = match segment.strip_prefix('-') {
+        Some(from_end) => len.checked_sub(from_end.parse::<usize>().ok().filter(|&n| n > 0)?)?,
+        None => segment.parse::<usize>().ok()?,
+    };
+    (index < len).then_some(index)
+}
+
+/// Shared `Null` returned by the `Index` impls on a miss
+static NULL: ToonValue = ToonValue::Null;
+
+/// Key holding the payload of a value built by [`ToonValue::tagged`]
+pub const TAGGED_DATA_KEY: &str = "data";
+
+/// Index into an object by key
+///
+/// Like `serde_json`, this never panics: a missing key, or indexing a value
+/// that is not an object, yields `ToonValue::Null`. Use [`ToonValue::pointer`]
+/// or [`ToonValue::as_object`] to tell a miss apart from a stored null.
+impl Index<&str> for ToonValue {
+    type Output = ToonValue;
+
+    fn index(&self, key: &str) -> &ToonValue {
+        match self {
+            ToonValue::Object(map) => map.get(key).unwrap_or(&NULL),
+            _ => &NULL,
         }
     }
 }
 
-impl EncodeOptions {
-    /// Create a new EncodeOptions with default values
-    pub fn new() -> Self {
-        Self::default()
-    }
+/// Index into an array by position
+///
+/// Out-of-bounds indices, or indexing a value that is not an array, yield
+/// `ToonValue::Null` rather than panicking.
+impl Index<usize> for ToonValue {
+    type Output = ToonValue;
 
-    /// Set whether to pretty-print the output
-    pub fn pretty(mut self, pretty: bool) -> Self {
-        self.pretty = pretty;
-        self
+    fn index(&self, index: usize) -> &ToonValue {
+        match self {
+            ToonValue::Array(arr) => arr.get(index).unwrap_or(&NULL),
+            _ => &NULL,
+        }
     }
+}
 
-    /// Set the number of spaces to use for indentation
-    pub fn indent(mut self, indent: usize) -> Self {
-        self.indent = indent;
-        self
-    }
+/// Iterate over the elements of an array
+///
+/// Like indexing, this never panics: any value that is not an array yields
+/// no elements. Use [`ToonValue::as_array`] first to tell the two apart.
+impl IntoIterator for ToonValue {
+    type Item = ToonValue;
+    type IntoIter = std::vec::IntoIter<ToonValue>;
 
-    /// Set whether to escape non-ASCII characters
-    pub fn escape_non_ascii(mut self, escape: bool) -> Self {
-        self.escape_non_ascii = escape;
-        self
+    fn into_iter(self) -> Self::IntoIter {
+        match self {
+            ToonValue::Array(arr) => arr.into_iter(),
+            _ => Vec::new().into_iter(),
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::collections::HashMap;
+/// Iterate over references to the elements of an array, yielding nothing
+/// for any other value
+impl<'a> IntoIterator for &'a ToonValue {
+    type Item = &'a ToonValue;
+    type IntoIter = std::slice::Iter<'a, ToonValue>;
 
-    #[test]
-    fn test_toon_value_display() {
-        assert_eq!(ToonValue::Null.to_string(), "null");
-        assert_eq!(ToonValue::Bool(true).to_string(), "true");
-        assert_eq!(ToonValue::Bool(false).to_string(), "false");
-        assert_eq!(ToonValue::Number(42.0).to_string(), "42");
-        assert_eq!(ToonValue::Number(3.14).to_string(), "3.14");
-        assert_eq!(
-            ToonValue::String("hello".to_string()).to_string(),
-            "\"hello\""
-        );
-        assert_eq!(
-            ToonValue::String("qu\"ote".to_string()).to_string(),
-            "\"qu\\\"ote\""
-        );
-        
-        let array = ToonValue::Array(vec![
-            ToonValue::Number(1.0),
-            ToonValue::Number(2.0),
-            ToonValue::Number(3.0),
-        ]);
-        assert_eq!(array.to_string(), "[1, 2, 3]");
-        
-        let mut map = HashMap::new();
-        map.insert("a".to_string(), ToonValue::Number(1.0));
-        map.insert("b".to_string(), ToonValue::Number(2.0));
-        let obj = ToonValue::Object(map);
-        
-        // The order of keys is not guaranteed, so we need to check both possibilities
-        let s = obj.to_string();
-        assert!(s == "{\"a\": 1, \"b\": 2}" || s == "{\"b\": 2, \"a\": 1}");
+    fn into_iter(self) -> Self::IntoIter {
+        match self {
+            ToonValue::Array(arr) => arr.iter(),
+            _ => [].iter(),
+        }
     }
+}
 
-    #[test]
+/// Collect values into an `Array`
+impl FromIterator<ToonValue> for ToonValue {
+    fn from_iter<I: IntoIterator<Item = ToonValue>>(iter: I) -> Self {
+        ToonValue::Array(iter.into_iter().collect())
+    }
+}
+
+/// Merge two inferred schemas into one describing both
+fn merge_schemas(a: ToonValue, b: ToonValue) -> ToonValue {
+    if a == b {
+        return a;
+    }
+
+    match (a, b) {
+        (ToonValue::Object(mut a), ToonValue::Object(mut b)) => {
+            let null = ToonValue::String("null".to_string());
+            let mut merged = HashMap::with_capacity(a.len().max(b.len()));
+            for (key, schema) in a.drain() {
+                let other = b.remove(&key).unwrap_or_else(|| null.clone());
+                merged.insert(key, merge_schemas(schema, other));
+            }
+            for (key, schema) in b {
+                merged.insert(key, merge_schemas(schema, null.clone()));
+            }
+            ToonValue::Object(merged)
+        }
+        (ToonValue::Array(a), ToonValue::Array(b)) => {
+            let merged = a.into_iter().chain(b).reduce(merge_schemas);
+            ToonValue::Array(merged.into_iter().collect())
+        }
+        (a, b) => {
+            let mut names: Vec<String> = schema_type_names(&a);
+            for name in schema_type_names(&b) {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            names.sort_by_key(|name| (name == "null", name.clone()));
+            ToonValue::String(names.join("|"))
+        }
+    }
+}
+
+/// Add up the `aggregate_numbers` of each value
+fn sum_aggregates<'a>(values: impl Iterator<Item = &'a ToonValue>) -> (f64, usize) {
+    values
+        .map(ToonValue::aggregate_numbers)
+        .fold((0.0, 0), |(sum, count), (s, c)| (sum + s, count + c))
+}
+
+/// Describe where a value sits for error messages
+fn path_label(path: &str) -> String {
+    if path.is_empty() {
+        "at the top level".to_string()
+    } else {
+        format!("at '{}'", path)
+    }
+}
+
+/// List the type names a schema node stands for
+fn schema_type_names(schema: &ToonValue) -> Vec<String> {
+    match schema {
+        ToonValue::String(s) => s.split('|').map(str::to_string).collect(),
+        ToonValue::Array(_) => vec!["array".to_string()],
+        ToonValue::Object(_) => vec!["object".to_string()],
+        _ => vec![schema.to_string()],
+    }
+}
+
+impl ToonValue {
+    /// Position of this value's type in the cross-type ordering
+    fn type_rank(&self) -> u8 {
+        match self {
+            ToonValue::Null => 0,
+            ToonValue::Bool(_) => 1,
+            ToonValue::Integer(_) | ToonValue::Number(_) => 2,
+            ToonValue::String(_) => 3,
+            ToonValue::Array(_) => 4,
+            ToonValue::Object(_) => 5,
+        }
+    }
+}
+
+/// Compare floats with NaN normalized: all NaNs are equal to each other and
+/// greater than every other number, while `-0.0 == 0.0` as usual
+fn cmp_f64(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b).unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+}
+
+/// Compare an integer with a float exactly, without rounding the integer to
+/// the nearest float; NaN is greater, as in [`cmp_f64`]
+fn cmp_i64_f64(a: i64, b: f64) -> Ordering {
+    // i64::MIN is a power of two, so the bounds themselves are exact floats
+    if b.is_nan() || b >= -(i64::MIN as f64) {
+        Ordering::Less
+    } else if b < i64::MIN as f64 {
+        Ordering::Greater
+    } else {
+        a.cmp(&(b.trunc() as i64)).then_with(|| 0.0.partial_cmp(&b.fract()).unwrap_or(Ordering::Equal))
+    }
+}
+
+impl PartialEq for ToonValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ToonValue::Null, ToonValue::Null) => true,
+            (ToonValue::Bool(a), ToonValue::Bool(b)) => a == b,
+            (ToonValue::Integer(a), ToonValue::Integer(b)) => a == b,
+            (ToonValue::Number(a), ToonValue::Number(b)) => cmp_f64(*a, *b) == Ordering::Equal,
+            (ToonValue::String(a), ToonValue::String(b)) => a == b,
+            (ToonValue::Array(a), ToonValue::Array(b)) => a == b,
+            (ToonValue::Object(a), ToonValue::Object(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for ToonValue {}
+
+/// Consistent with `Eq`: `-0.0` hashes like `0.0`, all NaNs hash alike, and
+/// objects hash the same whatever their key order. `Integer(1)` and
+/// `Number(1.0)` are unequal and hash differently.
+impl Hash for ToonValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            ToonValue::Null => {}
+            ToonValue::Bool(b) => b.hash(state),
+            ToonValue::Integer(i) => i.hash(state),
+            ToonValue::Number(n) => {
+                let n = if n.is_nan() {
+                    f64::NAN
+                } else if *n == 0.0 {
+                    0.0
+                } else {
+                    *n
+                };
+                n.to_bits().hash(state);
+            }
+            ToonValue::String(s) => s.hash(state),
+            ToonValue::Array(arr) => arr.hash(state),
+            ToonValue::Object(map) => {
+                let mut entries: Vec<_> = map.iter().collect();
+                entries.sort_by(|x, y| x.0.cmp(y.0));
+                entries.hash(state);
+            }
+        }
+    }
+}
+
+impl PartialOrd for ToonValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Values of different types order as `Null < Bool < Number < String <
+/// Array < Object`. Integers and floats are compared by their exact numeric
+/// value, even past 2^53 where not every integer has a float equal to it,
+/// with an `Integer` placed just before an equal `Number` (since `1` and
+/// `1.0` are distinct values). NaN is normalized so that all NaNs are equal and sort
+/// after every other number. Arrays compare element-wise and objects compare
+/// as their key/value pairs sorted by key.
+impl Ord for ToonValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (ToonValue::Bool(a), ToonValue::Bool(b)) => a.cmp(b),
+            (ToonValue::Integer(a), ToonValue::Integer(b)) => a.cmp(b),
+            (ToonValue::Number(a), ToonValue::Number(b)) => cmp_f64(*a, *b),
+            (ToonValue::Integer(a), ToonValue::Number(b)) => {
+                cmp_i64_f64(*a, *b).then(Ordering::Less)
+            }
+            (ToonValue::Number(a), ToonValue::Integer(b)) => {
+                cmp_i64_f64(*b, *a).reverse().then(Ordering::Greater)
+            }
+            (ToonValue::String(a), ToonValue::String(b)) => a.cmp(b),
+            (ToonValue::Array(a), ToonValue::Array(b)) => a.cmp(b),
+            (ToonValue::Object(a), ToonValue::Object(b)) => {
+                let mut a: Vec<_> = a.iter().collect();
+                let mut b: Vec<_> = b.iter().collect();
+                a.sort_by(|x, y| x.0.cmp(y.0));
+                b.sort_by(|x, y| x.0.cmp(y.0));
+                a.cmp(&b)
+            }
+            _ => self.type_rank().cmp(&other.type_rank()),
+        }
+    }
+}
+
+/// Convert parsed JSON, as for [`crate::decoder::decode_json`]
+///
+/// Integers that fit in an i64 become `Integer`; all other numbers become
+/// `Number`.
+pub(crate) fn json_to_toon_value(json: serde_json::Value) -> ToonValue {
+    match json {
+        serde_json::Value::Null => ToonValue::Null,
+        serde_json::Value::Bool(b) => ToonValue::Bool(b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => ToonValue::Integer(i),
+            None => ToonValue::Number(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(s) => ToonValue::String(s),
+        serde_json::Value::Array(arr) => ToonValue::Array(arr.into_iter().map(json_to_toon_value).collect()),
+        serde_json::Value::Object(obj) => ToonValue::Object(
+            obj.into_iter().map(|(k, v)| (k, json_to_toon_value(v))).collect(),
+        ),
+    }
+}
+
+/// Integers that fit in an i64 become `Integer`; all other numbers become
+/// `Number`. Only available with the `serde` feature.
+#[cfg(feature = "serde")]
+impl From<serde_json::Value> for ToonValue {
+    fn from(json: serde_json::Value) -> Self {
+        json_to_toon_value(json)
+    }
+}
+
+/// `Integer` stays an integer and `Number` becomes a float. Like serde_json's
+/// own `From<f64>`, NaN and infinities (which JSON can't hold) become `null`;
+/// use [`crate::encoder::to_json_string`] to reject them instead. Only
+/// available with the `serde` feature.
+#[cfg(feature = "serde")]
+impl From<ToonValue> for serde_json::Value {
+    fn from(value: ToonValue) -> Self {
+        match value {
+            ToonValue::Null => serde_json::Value::Null,
+            ToonValue::Bool(b) => serde_json::Value::Bool(b),
+            ToonValue::Integer(i) => serde_json::Value::from(i),
+            ToonValue::Number(n) => serde_json::Value::from(n),
+            ToonValue::String(s) => serde_json::Value::String(s),
+            ToonValue::Array(arr) => {
+                serde_json::Value::Array(arr.into_iter().map(serde_json::Value::from).collect())
+            }
+            ToonValue::Object(obj) => serde_json::Value::Object(
+                obj.into_iter().map(|(k, v)| (k, serde_json::Value::from(v))).collect(),
+            ),
+        }
+    }
+}
+
+/// Whole-valued floats keep a trailing `.0` (`Number(3.0)` displays as
+/// `3.0`), so they can't be mistaken for an `Integer` such as `3`.
+impl fmt::Display for ToonValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToonValue::Null => write!(f, "null"),
+            ToonValue::Bool(b) => write!(f, "{}", b),
+            ToonValue::Integer(i) => write!(f, "{}", i),
+            ToonValue::Number(n) => {
+                if n.fract() == 0.0 {
+                    write!(f, "{:.1}", n)
+                } else {
+                    write!(f, "{}", n)
+                }
+            }
+            ToonValue::String(s) => write!(f, "\"{}\"", s.escape_default()),
+            ToonValue::Array(arr) => {
+                write!(f, "[")?;
+                for (i, item) in arr.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            ToonValue::Object(obj) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in obj.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "\"{}\": {}", k.escape_default(), v)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// Depth-first iterator behind [`ToonValue::walk`]
+///
+/// Uses an explicit stack so arbitrarily deep values can't overflow the call
+/// stack.
+struct Walk<'a> {
+    stack: Vec<(String, &'a ToonValue)>,
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = (String, &'a ToonValue);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, value) = self.stack.pop()?;
+        
+        // Push children in reverse so they are popped in order
+        match value {
+            ToonValue::Array(arr) => {
+                for (i, item) in arr.iter().enumerate().rev() {
+                    self.stack.push((format!("{}[{}]", path, i), item));
+                }
+            }
+            ToonValue::Object(_) => {
+                let entries: Vec<_> = value.iter_sorted().collect();
+                for (key, item) in entries.into_iter().rev() {
+                    let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                    self.stack.push((child, item));
+                }
+            }
+            _ => {}
+        }
+        
+        Some((path, value))
+    }
+}
+
+/// Conversion from a `ToonValue` leaf, used by [`ToonValue::get_path_as`]
+///
+/// `i64` accepts integers and whole numbers within range, `f64` accepts any
+/// number, `bool` accepts only booleans, and `String` accepts strings as well
+/// as numbers and booleans, which are formatted as they would be encoded.
+pub trait FromToonValue: Sized {
+    /// Convert the value, returning `None` if it has an incompatible type
+    fn from_toon_value(value: &ToonValue) -> Option<Self>;
+}
+
+impl FromToonValue for i64 {
+    fn from_toon_value(value: &ToonValue) -> Option<Self> {
+        match value {
+            ToonValue::Integer(i) => Some(*i),
+            ToonValue::Number(n) if n.fract() == 0.0 && *n >= i64::MIN as f64 && *n < i64::MAX as f64 => {
+                Some(*n as i64)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl FromToonValue for f64 {
+    fn from_toon_value(value: &ToonValue) -> Option<Self> {
+        value.as_number()
+    }
+}
+
+impl FromToonValue for bool {
+    fn from_toon_value(value: &ToonValue) -> Option<Self> {
+        value.as_bool()
+    }
+}
+
+impl FromToonValue for String {
+    fn from_toon_value(value: &ToonValue) -> Option<Self> {
+        match value {
+            ToonValue::String(s) => Some(s.clone()),
+            ToonValue::Bool(b) => Some(b.to_string()),
+            ToonValue::Integer(i) => Some(i.to_string()),
+            ToonValue::Number(n) => Some(crate::utils::format_number(*n)),
+            _ => None,
+        }
+    }
+}
+
+/// Object key order captured from an ordered source, such as a Python dict
+///
+/// The tree mirrors the shape of the `ToonValue` it describes: objects list
+/// their keys in order alongside each value's own ordering, arrays hold one
+/// entry per element, and scalars (or values without a known order) are
+/// `None`. See [`crate::encoder::encode_with_key_order`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum KeyOrder {
+    /// No ordering information
+    #[default]
+    None,
+    /// Object keys in order, each paired with the ordering of its value
+    Object(Vec<(String, KeyOrder)>),
+    /// Ordering of each array element
+    Array(Vec<KeyOrder>),
+}
+
+impl KeyOrder {
+    /// Get the ordering of the array element at `index`
+    pub fn element(&self, index: usize) -> &KeyOrder {
+        match self {
+            KeyOrder::Array(items) => items.get(index).unwrap_or(&KeyOrder::None),
+            _ => &KeyOrder::None,
+        }
+    }
+
+    /// Build the ordering that puts the keys of every object in `value` in
+    /// sorted order
+    pub fn sorted(value: &ToonValue) -> KeyOrder {
+        match value {
+            ToonValue::Object(_) => KeyOrder::Object(
+                value.iter_sorted().map(|(key, child)| (key.clone(), KeyOrder::sorted(child))).collect(),
+            ),
+            ToonValue::Array(arr) => KeyOrder::Array(arr.iter().map(KeyOrder::sorted).collect()),
+            _ => KeyOrder::None,
+        }
+    }
+
+    /// Get the ordering of the value at the path `segments` inside `value`,
+    /// adding entries for the keys and elements along the way that aren't
+    /// listed yet; stops early where the path doesn't exist
+    fn slot<'a>(&'a mut self, value: &ToonValue, segments: &[&str]) -> &'a mut KeyOrder {
+        let Some((segment, rest)) = segments.split_first() else {
+            return self;
+        };
+        match value {
+            ToonValue::Object(map) if map.contains_key(*segment) => {
+                if !matches!(self, KeyOrder::Object(_)) {
+                    *self = KeyOrder::Object(Vec::new());
+                }
+                let KeyOrder::Object(keys) = self else { unreachable!() };
+                let i = match keys.iter().position(|(key, _)| key == segment) {
+                    Some(i) => i,
+                    None => {
+                        keys.push((segment.to_string(), KeyOrder::None));
+                        keys.len() - 1
+                    }
+                };
+                keys[i].1.slot(&map[*segment], rest)
+            }
+            ToonValue::Array(arr) => match array_index(segment, arr.len()) {
+                Some(i) => {
+                    if !matches!(self, KeyOrder::Array(_)) {
+                        *self = KeyOrder::Array(Vec::new());
+                    }
+                    let KeyOrder::Array(items) = self else { unreachable!() };
+                    if items.len() <= i {
+                        items.resize(i + 1, KeyOrder::None);
+                    }
+                    items[i].slot(&arr[i], rest)
+                }
+                None => self,
+            },
+            _ => self,
+        }
+    }
+
+    fn rename_keys(&mut self, mapping: &HashMap<String, String>) {
+        match self {
+            KeyOrder::Object(keys) => {
+                for (key, child) in keys {
+                    if let Some(new_key) = mapping.get(key) {
+                        key.clone_from(new_key);
+                    }
+                    child.rename_keys(mapping);
+                }
+            }
+            KeyOrder::Array(items) => items.iter_mut().for_each(|item| item.rename_keys(mapping)),
+            KeyOrder::None => {}
+        }
+    }
+}
+
+/// Set of ASCII characters that must be escaped inside quoted strings
+///
+/// The default table escapes `"`, `\` and all ASCII control characters,
+/// which is the minimum needed for valid output. Extra characters can be
+/// added, e.g. `<`, `>` and `&` for safe embedding in HTML. Non-ASCII control
+/// characters are always escaped; other non-ASCII characters never are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EscapeTable {
+    ascii: u128,
+}
+
+impl Default for EscapeTable {
+    fn default() -> Self {
+        let controls = (1u128 << 0x20) - 1;
+        Self {
+            ascii: controls | 1 << 0x7f | 1 << b'"' | 1 << b'\\',
+        }
+    }
+}
+
+impl EscapeTable {
+    /// Create a table with the default set of escaped characters
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also escape `c`; non-ASCII characters are ignored
+    pub fn with(mut self, c: char) -> Self {
+        if c.is_ascii() {
+            self.ascii |= 1 << c as u32;
+        }
+        self
+    }
+
+    /// Check whether `c` should be escaped
+    pub fn should_escape(&self, c: char) -> bool {
+        if c.is_ascii() {
+            self.ascii & (1 << c as u32) != 0
+        } else {
+            c.is_control()
+        }
+    }
+}
+
+/// Whitespace written around the `:` between an object key and its value
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColonSpacing {
+    /// `key:value`
+    None,
+    /// `key: value`
+    #[default]
+    After,
+    /// `key : value`
+    Around,
+}
+
+impl ColonSpacing {
+    /// The separator to write between a key and its value
+    pub fn separator(self) -> &'static str {
+        match self {
+            ColonSpacing::None => ":",
+            ColonSpacing::After => ": ",
+            ColonSpacing::Around => " : ",
+        }
+    }
+}
+
+/// Newline style used between lines of output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`
+    #[default]
+    Lf,
+    /// `\r\n`
+    CrLf,
+}
+
+impl LineEnding {
+    /// The characters ending each line
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+/// Order of the columns in a tabular array
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TabularFieldOrder {
+    /// Field names in sorted order
+    #[default]
+    Sorted,
+    /// Fields in the order they first appear, scanning each object's keys in
+    /// turn. Objects only have a key order when one was captured (see
+    /// [`KeyOrder`]), as for values from Python dicts; otherwise their keys
+    /// come in arbitrary map order.
+    FirstSeen,
+}
+
+/// How bare `true`, `false` and `null` decode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BareKeywords {
+    /// As `Bool` and `Null`
+    #[default]
+    AsTyped,
+    /// As the strings `"true"`, `"false"` and `"null"`, for data where
+    /// those are meaningful tokens. Any other bare word starting with `t`,
+    /// `f` or `n` also decodes as a string in this mode.
+    AsString,
+}
+
+/// Options for encoding ToonValue to a string
+#[derive(Debug, Clone, Copy)]
+pub struct EncodeOptions {
+    /// Whether to pretty-print the output
+    pub pretty: bool,
+    /// Number of spaces to use for indentation (if pretty-printing)
+    pub indent: usize,
+    /// Whether to escape non-ASCII characters
+    pub escape_non_ascii: bool,
+    /// Whether to render empty arrays/objects expanded over two lines
+    /// (`[\n]`, `{\n}`) instead of compactly (`[]`, `{}`)
+    pub expand_empty: bool,
+    /// Whether to escape `/` as `\/`, so output can be embedded in HTML
+    /// `<script>` tags without producing a `</` sequence
+    pub escape_forward_slash: bool,
+    /// Characters to escape inside quoted strings
+    pub escape_table: EscapeTable,
+    /// Maximum length of the output in bytes; encoding fails once it is
+    /// exceeded
+    pub max_output_len: Option<usize>,
+    /// Whether arrays of uniform objects are written in tabular form (a
+    /// header row of field names followed by one row of values per object).
+    /// Only arrays at the top level or as the value of a top-level entry are,
+    /// since the rows take whole lines; nested ones stay inline.
+    pub tabular: bool,
+    /// Whether the tabular header row is wrapped in brackets (`[id, name]`)
+    /// rather than written as a bare CSV-style line (`id, name`)
+    pub tabular_bracket_header: bool,
+    /// Whether arrays of objects with differing key sets may still be written
+    /// in tabular form, with `null` cells for missing fields
+    pub tabular_fill_missing: bool,
+    /// Order of the columns in tabular arrays
+    pub tabular_field_order: TabularFieldOrder,
+    /// Maximum number of columns in a tabular array; arrays of wider
+    /// objects are written inline instead, since wide tables read poorly
+    pub tabular_max_cols: Option<usize>,
+    /// Spacing around the colon after object keys
+    pub colon_spacing: ColonSpacing,
+    /// `(low, high)` magnitude range outside which floats are written in
+    /// scientific notation (`1e20`, `2.5e-7`); integers are never affected
+    pub scientific_threshold: Option<(f64, f64)>,
+    /// Whether to leave strings bare whenever the decoder would still read
+    /// them back unchanged, instead of quoting every reserved word
+    pub minimize_quotes: bool,
+    /// Newline style between the lines of top-level, tabular and
+    /// pretty-printed output
+    pub line_ending: LineEnding,
+    /// Whether whole-valued floats are written like integers (`5`) rather
+    /// than with a trailing `.0` (`5.0`), which keeps them floats on decode
+    pub coerce_whole_floats_to_int: bool,
+    /// Whether multi-line strings are written as heredoc blocks (`<<END`,
+    /// the lines, then `END`), which decode only with
+    /// [`DecodeOptions::allow_heredoc`]. Has no effect with
+    /// [`LineEnding::CrLf`], and arrays holding such strings are never tabular.
+    pub block_strings: bool,
+    /// Whether nested objects in a top-level object are written as dotted
+    /// keys (`address.city: X`) rather than inline objects, giving a flat
+    /// key-value document. Arrays, and objects inside them, are unchanged.
+    pub flatten_keys: bool,
+    /// Whether multi-line arrays end with a comma after their last item, so
+    /// appending an item touches one line; reading it back needs
+    /// [`DecodeOptions::allow_trailing_comma`]
+    pub trailing_comma: bool,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            pretty: false,
+            indent: 2,
+            escape_non_ascii: false,
+            expand_empty: false,
+            escape_forward_slash: false,
+            escape_table: EscapeTable::default(),
+            max_output_len: None,
+            tabular: true,
+            tabular_bracket_header: true,
+            tabular_fill_missing: false,
+            tabular_field_order: TabularFieldOrder::default(),
+            tabular_max_cols: None,
+            colon_spacing: ColonSpacing::default(),
+            scientific_threshold: None,
+            minimize_quotes: false,
+            line_ending: LineEnding::default(),
+            coerce_whole_floats_to_int: true,
+            block_strings: false,
+            flatten_keys: false,
+            trailing_comma: false,
+        }
+    }
+}
+
+impl EncodeOptions {
+    /// Create a new EncodeOptions with default values
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether to pretty-print the output
+    pub fn pretty(mut self, pretty: bool) -> Self {
+        self.pretty = pretty;
+        self
+    }
+
+    /// Set the number of spaces to use for indentation
+    pub fn indent(mut self, indent: usize) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    /// Set whether to escape non-ASCII characters
+    pub fn escape_non_ascii(mut self, escape: bool) -> Self {
+        self.escape_non_ascii = escape;
+        self
+    }
+
+    /// Set whether to expand empty arrays/objects over two lines
+    pub fn expand_empty(mut self, expand: bool) -> Self {
+        self.expand_empty = expand;
+        self
+    }
+
+    /// Set whether to escape forward slashes in strings
+    pub fn escape_forward_slash(mut self, escape: bool) -> Self {
+        self.escape_forward_slash = escape;
+        self
+    }
+
+    /// Set the table of characters to escape inside quoted strings
+    pub fn escape_table(mut self, table: EscapeTable) -> Self {
+        self.escape_table = table;
+        self
+    }
+
+    /// Set the maximum output length in bytes (`None` for no limit)
+    pub fn max_output_len(mut self, max: Option<usize>) -> Self {
+        self.max_output_len = max;
+        self
+    }
+
+    /// Set whether arrays of uniform objects may be written in tabular form
+    pub fn tabular(mut self, tabular: bool) -> Self {
+        self.tabular = tabular;
+        self
+    }
+
+    /// Set whether the tabular header row is wrapped in brackets
+    pub fn tabular_bracket_header(mut self, bracket: bool) -> Self {
+        self.tabular_bracket_header = bracket;
+        self
+    }
+
+    /// Set whether tabular arrays may fill missing fields with `null`
+    pub fn tabular_fill_missing(mut self, fill: bool) -> Self {
+        self.tabular_fill_missing = fill;
+        self
+    }
+
+    /// Set the order of the columns in tabular arrays
+    pub fn tabular_field_order(mut self, order: TabularFieldOrder) -> Self {
+        self.tabular_field_order = order;
+        self
+    }
+    
+    /// Set the maximum number of columns in tabular arrays
+    pub fn tabular_max_cols(mut self, max_cols: Option<usize>) -> Self {
+        self.tabular_max_cols = max_cols;
+        self
+    }
+
+    /// Set the spacing around the colon after object keys
+    pub fn colon_spacing(mut self, spacing: ColonSpacing) -> Self {
+        self.colon_spacing = spacing;
+        self
+    }
+
+    /// Set the magnitude range outside which floats use scientific notation
+    pub fn scientific_threshold(mut self, threshold: Option<(f64, f64)>) -> Self {
+        self.scientific_threshold = threshold;
+        self
+    }
+
+    /// Set whether to leave strings bare whenever they round-trip unquoted
+    pub fn minimize_quotes(mut self, minimize_quotes: bool) -> Self {
+        self.minimize_quotes = minimize_quotes;
+        self
+    }
+
+    /// Set the newline style
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Set whether whole-valued floats are written like integers
+    pub fn coerce_whole_floats_to_int(mut self, coerce: bool) -> Self {
+        self.coerce_whole_floats_to_int = coerce;
+        self
+    }
+
+    /// Set whether multi-line strings are written as heredoc blocks
+    pub fn block_strings(mut self, enable: bool) -> Self {
+        self.block_strings = enable;
+        self
+    }
+
+    /// Set whether nested objects are written as dotted top-level keys
+    pub fn flatten_keys(mut self, flatten: bool) -> Self {
+        self.flatten_keys = flatten;
+        self
+    }
+
+    /// Set whether multi-line arrays end with a trailing comma
+    pub fn trailing_comma(mut self, trailing_comma: bool) -> Self {
+        self.trailing_comma = trailing_comma;
+        self
+    }
+
+    /// The escape table with all escaping-related options applied
+    pub(crate) fn effective_escape_table(&self) -> EscapeTable {
+        if self.escape_forward_slash {
+            self.escape_table.with('/')
+        } else {
+            self.escape_table
+        }
+    }
+}
+
+/// Options for decoding a TOON string into a ToonValue
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DecodeOptions {
+    /// Whether to accept (and ignore) a leading `+` on numbers
+    pub allow_plus_sign: bool,
+    /// Whether a newline between array items may stand in for a comma
+    pub newline_as_separator: bool,
+    /// Whether number literals may overflow to infinity or underflow to zero
+    /// instead of being rejected as out of range
+    pub allow_non_finite: bool,
+    /// Whether number literals decode to strings holding their exact text,
+    /// so callers can parse them with a decimal library without f64 rounding
+    pub numbers_as_strings: bool,
+    /// Whether decimals may omit the digits before or after the point, as
+    /// in `.5` and `5.`
+    pub allow_bare_decimals: bool,
+    /// Whether object keys may be followed by `=` as well as `:`, as in
+    /// `.env` and `.ini` style files
+    pub allow_equals_separator: bool,
+    /// Whether strings may be written as heredoc blocks, `<<END` followed by
+    /// lines taken literally up to a line starting with `END`
+    pub allow_heredoc: bool,
+    /// Whether the last element of an array or object may be followed by a
+    /// comma, as in `[1, 2,]`
+    pub allow_trailing_comma: bool,
+    /// Maximum length of an object key (or tabular header field) in
+    /// characters, so untrusted input can't smuggle in huge keys
+    pub max_key_len: Option<usize>,
+    /// Maximum length of a string value in characters
+    pub max_string_len: Option<usize>,
+    /// How bare `true`, `false` and `null` decode; quoted ones are always
+    /// strings
+    pub bare_keywords: BareKeywords,
+    /// Inclusive `(min, max)` range every number literal must fall within,
+    /// so validators can reject e.g. negative quantities at parse time
+    pub number_range: Option<(f64, f64)>,
+}
+
+impl DecodeOptions {
+    /// Create a new DecodeOptions with default values
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether to accept a leading `+` on numbers
+    pub fn allow_plus_sign(mut self, allow: bool) -> Self {
+        self.allow_plus_sign = allow;
+        self
+    }
+
+    /// Set whether newlines separate array items when no comma is present
+    pub fn newline_as_separator(mut self, enable: bool) -> Self {
+        self.newline_as_separator = enable;
+        self
+    }
+
+    /// Set whether out-of-range number literals are accepted
+    pub fn allow_non_finite(mut self, allow: bool) -> Self {
+        self.allow_non_finite = allow;
+        self
+    }
+
+    /// Set whether number literals decode to their exact text as strings
+    pub fn numbers_as_strings(mut self, enable: bool) -> Self {
+        self.numbers_as_strings = enable;
+        self
+    }
+
+    /// Set whether `.5` and `5.` are accepted as numbers
+    pub fn allow_bare_decimals(mut self, allow: bool) -> Self {
+        self.allow_bare_decimals = allow;
+        self
+    }
+
+    /// Set whether `key = value` entries are accepted alongside `key: value`
+    pub fn allow_equals_separator(mut self, allow: bool) -> Self {
+        self.allow_equals_separator = allow;
+        self
+    }
+
+    /// Set whether `<<END` heredoc block strings are accepted
+    pub fn allow_heredoc(mut self, allow: bool) -> Self {
+        self.allow_heredoc = allow;
+        self
+    }
+
+    /// Set whether a comma may follow the last element of a container
+    pub fn allow_trailing_comma(mut self, allow: bool) -> Self {
+        self.allow_trailing_comma = allow;
+        self
+    }
+
+    /// Set the maximum length of object keys
+    pub fn max_key_len(mut self, max_key_len: Option<usize>) -> Self {
+        self.max_key_len = max_key_len;
+        self
+    }
+
+    /// Set the maximum length of string values
+    pub fn max_string_len(mut self, max_string_len: Option<usize>) -> Self {
+        self.max_string_len = max_string_len;
+        self
+    }
+
+    /// Set how bare `true`, `false` and `null` decode
+    pub fn bare_keywords(mut self, bare_keywords: BareKeywords) -> Self {
+        self.bare_keywords = bare_keywords;
+        self
+    }
+
+    /// Set the range number literals must fall within
+    pub fn number_range(mut self, range: Option<(f64, f64)>) -> Self {
+        self.number_range = range;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_toon_value_display() {
+        assert_eq!(ToonValue::Null.to_string(), "null");
+        assert_eq!(ToonValue::Bool(true).to_string(), "true");
+        assert_eq!(ToonValue::Bool(false).to_string(), "false");
+        assert_eq!(ToonValue::Integer(42).to_string(), "42");
+        assert_eq!(ToonValue::Number(42.0).to_string(), "42.0");
+        assert_eq!(ToonValue::Number(2.5).to_string(), "2.5");
+        assert_eq!(ToonValue::Number(-0.0).to_string(), "-0.0");
+        assert_eq!(ToonValue::Number(f64::INFINITY).to_string(), "inf");
+        assert_eq!(
+            ToonValue::String("hello".to_string()).to_string(),
+            "\"hello\""
+        );
+        assert_eq!(
+            ToonValue::String("qu\"ote".to_string()).to_string(),
+            "\"qu\\\"ote\""
+        );
+        
+        let array = ToonValue::Array(vec![
+            ToonValue::Integer(1),
+            ToonValue::Number(2.0),
+            ToonValue::Number(3.5),
+        ]);
+        assert_eq!(array.to_string(), "[1, 2.0, 3.5]");
+        
+        let mut map = HashMap::new();
+        map.insert("a".to_string(), ToonValue::Integer(1));
+        map.insert("b".to_string(), ToonValue::Number(2.0));
+        let obj = ToonValue::Object(map);
+        
+        // The order of keys is not guaranteed, so we need to check both possibilities
+        let s = obj.to_string();
+        assert!(s == "{\"a\": 1, \"b\": 2.0}" || s == "{\"b\": 2.0, \"a\": 1}");
+    }
+
+    #[test]
+    fn test_as_f64_lossy() {
+        let s = |v: &str| ToonValue::String(v.to_string());
+        assert_eq!(s("42").as_f64_lossy(), Some(42.0));
+        assert_eq!(s(" -2.5e1 ").as_f64_lossy(), Some(-25.0));
+        assert_eq!(ToonValue::Bool(true).as_f64_lossy(), Some(1.0));
+        assert_eq!(ToonValue::Bool(false).as_f64_lossy(), Some(0.0));
+        assert_eq!(ToonValue::Integer(7).as_f64_lossy(), Some(7.0));
+        assert_eq!(ToonValue::Number(0.5).as_f64_lossy(), Some(0.5));
+        
+        assert_eq!(s("abc").as_f64_lossy(), None);
+        assert_eq!(s("").as_f64_lossy(), None);
+        assert_eq!(s("nan").as_f64_lossy(), None);
+        assert_eq!(s("12px").as_f64_lossy(), None);
+        assert_eq!(ToonValue::Null.as_f64_lossy(), None);
+        assert_eq!(ToonValue::Array(vec![ToonValue::Integer(1)]).as_f64_lossy(), None);
+    }
+    
+    #[test]
     fn test_as_methods() {
         let null = ToonValue::Null;
         assert!(null.is_null());
@@ -224,6 +1933,11 @@ mod tests {
         
         let num = ToonValue::Number(42.0);
         assert_eq!(num.as_number(), Some(42.0));
+        assert_eq!(num.as_integer(), None);
+        
+        let int = ToonValue::Integer(42);
+        assert_eq!(int.as_integer(), Some(42));
+        assert_eq!(int.as_number(), Some(42.0));
         
         let s = ToonValue::String("test".to_string());
         assert_eq!(s.as_str(), Some("test"));
@@ -236,4 +1950,830 @@ mod tests {
         let obj = ToonValue::Object(map);
         assert_eq!(obj.as_object().map(|m| m.len()), Some(1));
     }
+
+    #[test]
+    fn test_entry_api() {
+        let mut obj = ToonValue::Object(HashMap::new());
+        
+        // Insert if absent, then accumulate into the inserted array
+        for n in 1..=3 {
+            let list = obj
+                .get_or_insert_with("items", || ToonValue::Array(Vec::new()))
+                .unwrap();
+            list.as_array_mut().unwrap().push(ToonValue::Integer(n));
+        }
+        assert_eq!(
+            obj.as_object().unwrap().get("items"),
+            Some(&ToonValue::Array(vec![
+                ToonValue::Integer(1),
+                ToonValue::Integer(2),
+                ToonValue::Integer(3),
+            ]))
+        );
+        
+        // Existing values are left untouched
+        obj.entry("name").unwrap().or_insert(ToonValue::String("first".to_string()));
+        let name = obj.get_or_insert_with("name", || ToonValue::String("second".to_string()));
+        assert_eq!(name, Some(&mut ToonValue::String("first".to_string())));
+        
+        // Non-objects have no entries
+        let mut arr = ToonValue::Array(vec![]);
+        assert!(arr.entry("key").is_none());
+        assert!(arr.get_or_insert_with("key", || ToonValue::Null).is_none());
+    }
+
+    #[test]
+    fn test_coerce_to() {
+        let schema = crate::decoder::decode(
+            "{id: \"number\", active: bool, name: string, note: \"string|null\", \
+             scores: [\"number\"], tags: [], meta: {rank: \"number|string\"}}",
+        )
+        .unwrap();
+        let value = crate::decoder::decode(
+            "{id: \"42\", active: \"true\", name: Ann, scores: [\"1.5\", 2, \" 3 \"], \
+             tags: [x, 1], meta: {rank: high}, extra: \"7\"}",
+        )
+        .unwrap();
+        let expected = crate::decoder::decode(
+            "{id: 42, active: true, name: Ann, scores: [1.5, 2, 3], \
+             tags: [x, 1], meta: {rank: high}, extra: \"7\"}",
+        )
+        .unwrap();
+        assert_eq!(value.coerce_to(&schema).unwrap(), expected);
+        
+        // A schema inferred from clean data normalizes loosely typed data
+        let clean = crate::decoder::decode("[{n: 1, ok: false}]").unwrap();
+        let loose = crate::decoder::decode("[{n: \"2\", ok: \"true\"}]").unwrap();
+        let coerced = loose.coerce_to(&clean.infer_schema()).unwrap();
+        assert_eq!(coerced, crate::decoder::decode("[{n: 2, ok: true}]").unwrap());
+    }
+    
+    #[test]
+    fn test_coerce_to_errors() {
+        let schema = crate::decoder::decode("{users: [{age: \"number\"}]}").unwrap();
+        let value = crate::decoder::decode("{users: [{age: 3}, {age: \"old\"}]}").unwrap();
+        let err = value.coerce_to(&schema).unwrap_err().to_string();
+        assert!(err.contains("expected number at 'users[1].age', found string"), "{}", err);
+        
+        let value = crate::decoder::decode("{users: [{name: Ann}]}").unwrap();
+        let err = value.coerce_to(&schema).unwrap_err().to_string();
+        assert!(err.contains("missing required field 'age' at 'users[0]'"), "{}", err);
+        
+        let err = ToonValue::Integer(1).coerce_to(&schema).unwrap_err().to_string();
+        assert!(err.contains("expected object at the top level, found number"), "{}", err);
+        assert!(ToonValue::Null.coerce_to(&ToonValue::Integer(1)).is_err());
+    }
+    
+    #[test]
+    fn test_infer_schema() {
+        let input = r#"{
+            "name": "John",
+            "age": 30,
+            "address": {"street": "123 Main St", "city": "Anytown"},
+            "hobbies": ["reading", "swimming", "coding"],
+            "scores": [1, 2.5, null],
+            "tags": [],
+            "pets": [{"kind": "cat", "age": 3}, {"kind": "dog"}, "none"]
+        }"#;
+        let schema = crate::decoder::decode(input).unwrap().infer_schema();
+        let schema = schema.as_object().unwrap();
+        let s = |v: &str| ToonValue::String(v.to_string());
+        
+        assert_eq!(schema["name"], s("string"));
+        assert_eq!(schema["age"], s("number"));
+        assert_eq!(schema["hobbies"], ToonValue::Array(vec![s("string")]));
+        assert_eq!(schema["scores"], ToonValue::Array(vec![s("number|null")]));
+        assert_eq!(schema["tags"], ToonValue::Array(vec![]));
+        
+        let mut address = HashMap::new();
+        address.insert("street".to_string(), s("string"));
+        address.insert("city".to_string(), s("string"));
+        assert_eq!(schema["address"], ToonValue::Object(address));
+        
+        // Objects merged with a scalar collapse into a type union
+        assert_eq!(schema["pets"], ToonValue::Array(vec![s("object|string")]));
+        
+        // Fields missing from some objects become optional
+        let records = crate::decoder::decode("[{a: 1, b: x}, {a: 2}]").unwrap();
+        let mut expected = HashMap::new();
+        expected.insert("a".to_string(), s("number"));
+        expected.insert("b".to_string(), s("string|null"));
+        assert_eq!(
+            records.infer_schema(),
+            ToonValue::Array(vec![ToonValue::Object(expected)])
+        );
+    }
+
+    #[test]
+    fn test_index_and_pointer() {
+        let value = crate::decoder::decode(
+            r#"{"address": {"city": "Anytown"}, "hobbies": ["reading", "coding"], "1": "key"}"#,
+        )
+        .unwrap();
+        
+        assert_eq!(value["address"]["city"], ToonValue::String("Anytown".to_string()));
+        assert_eq!(value["hobbies"][1], ToonValue::String("coding".to_string()));
+        
+        // Misses yield Null instead of panicking
+        assert_eq!(value["missing"]["deeper"], ToonValue::Null);
+        assert_eq!(value["hobbies"][5], ToonValue::Null);
+        assert_eq!(value["address"][0], ToonValue::Null);
+        
+        assert_eq!(
+            value.pointer("address/city"),
+            Some(&ToonValue::String("Anytown".to_string()))
+        );
+        assert_eq!(value.pointer("/hobbies/0"), value.pointer("hobbies[0]"));
+        assert_eq!(value.pointer("address.city"), value.pointer("address/city"));
+        assert_eq!(value.pointer("1"), Some(&ToonValue::String("key".to_string())));
+        assert_eq!(value.pointer(""), Some(&value));
+        assert_eq!(value.pointer("hobbies/5"), None);
+        assert_eq!(value.pointer("address/city/name"), None);
+        
+        // Negative indices count from the end
+        let value = crate::decoder::decode("{hobbies: [reading, coding, chess]}").unwrap();
+        assert_eq!(value.pointer("hobbies/-1"), value.pointer("hobbies/2"));
+        assert_eq!(value.pointer("hobbies[-3]"), value.pointer("hobbies[0]"));
+        assert_eq!(value.pointer("hobbies/-5"), None);
+        assert_eq!(value.pointer("hobbies/-0"), None);
+    }
+
+    #[test]
+    fn test_aggregate_numbers() {
+        let value = crate::decoder::decode(
+            "{a: 1, b: [2.5, {c: -1, d: [10]}], e: \"7\", f: true, g: null, h: [], i: x}",
+        )
+        .unwrap();
+        assert_eq!(value.aggregate_numbers(), (12.5, 4));
+        
+        assert_eq!(ToonValue::Integer(3).aggregate_numbers(), (3.0, 1));
+        assert_eq!(ToonValue::String("3".to_string()).aggregate_numbers(), (0.0, 0));
+        assert_eq!(crate::decoder::decode("[{}, []]").unwrap().aggregate_numbers(), (0.0, 0));
+        
+        let (sum, count) = ToonValue::Array(vec![ToonValue::Integer(1), ToonValue::Number(f64::NAN)])
+            .aggregate_numbers();
+        assert!(sum.is_nan());
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_into_iter_and_collect() {
+        let value = crate::decoder::decode("[1, 2, 3]").unwrap();
+        
+        let mut sum = 0;
+        for item in &value {
+            sum += item.as_integer().unwrap();
+        }
+        assert_eq!(sum, 6);
+        
+        let doubled: ToonValue = value
+            .into_iter()
+            .map(|item| ToonValue::Integer(item.as_integer().unwrap() * 2))
+            .collect();
+        assert_eq!(doubled, crate::decoder::decode("[2, 4, 6]").unwrap());
+        
+        // Non-arrays yield nothing
+        let obj = crate::decoder::decode("{a: [1]}").unwrap();
+        assert_eq!((&obj).into_iter().count(), 0);
+        assert_eq!(ToonValue::Integer(1).into_iter().count(), 0);
+        assert_eq!(ToonValue::Null.into_iter().next(), None);
+        
+        let empty: ToonValue = std::iter::empty().collect();
+        assert_eq!(empty, ToonValue::Array(vec![]));
+    }
+
+    #[test]
+    fn test_iter_sorted() {
+        let mut map = HashMap::new();
+        for key in ["delta", "alpha", "charlie", "bravo"] {
+            map.insert(key.to_string(), ToonValue::String(key.to_uppercase()));
+        }
+        let obj = ToonValue::Object(map);
+        
+        let keys: Vec<&str> = obj.iter_sorted().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, vec!["alpha", "bravo", "charlie", "delta"]);
+        assert_eq!(
+            obj.iter_sorted().next().map(|(_, v)| v),
+            Some(&ToonValue::String("ALPHA".to_string()))
+        );
+        
+        assert_eq!(ToonValue::Array(vec![ToonValue::Null]).iter_sorted().count(), 0);
+        assert_eq!(ToonValue::Null.iter_sorted().count(), 0);
+    }
+
+    #[test]
+    fn test_depth_and_node_count() {
+        assert_eq!(ToonValue::Null.depth(), 0);
+        assert_eq!(ToonValue::Integer(1).node_count(), 1);
+        
+        let flat = crate::decoder::decode("[1, 2, 3]").unwrap();
+        assert_eq!(flat.depth(), 1);
+        assert_eq!(flat.node_count(), 4);
+        assert_eq!(ToonValue::Array(vec![]).depth(), 1);
+        
+        let nested = crate::decoder::decode("{a: {b: [1, {c: [[]]}]}, d: 2}").unwrap();
+        assert_eq!(nested.depth(), 6);
+        assert_eq!(nested.node_count(), 8);
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let value = crate::decoder::decode(
+            "{created: \"2024-06-01T12:30:00Z\", id: \"2024-06-01\", \
+             events: [{at: \"2024-02-30\"}, {at: \"2024-02-29T08:00:00.25+01:00\"}], \
+             note: \"on 2024-06-01\", year: 2024}",
+        )
+        .unwrap();
+        assert_eq!(
+            value.timestamps(),
+            vec![
+                ("created".to_string(), "2024-06-01T12:30:00Z"),
+                ("events[1].at".to_string(), "2024-02-29T08:00:00.25+01:00"),
+                ("id".to_string(), "2024-06-01"),
+            ]
+        );
+        assert!(value.pointer("id").unwrap().looks_like_timestamp());
+        assert!(!value.pointer("events[0].at").unwrap().looks_like_timestamp());
+        assert!(!value.pointer("year").unwrap().looks_like_timestamp());
+        assert!(ToonValue::Null.timestamps().is_empty());
+    }
+    
+    #[test]
+    fn test_set() {
+        let mut value = crate::decoder::decode("{user: {name: Ann, tags: [a, b]}, n: 1}").unwrap();
+        value.set("user.address.geo.lat", ToonValue::Number(48.85)).unwrap();
+        value.set("user.name", ToonValue::String("Bea".to_string())).unwrap();
+        value.set("user.tags[-1]", ToonValue::Integer(2)).unwrap();
+        value.set("user/tags/0", ToonValue::Null).unwrap();
+        let expected = crate::decoder::decode(
+            "{user: {name: Bea, tags: [null, 2], address: {geo: {lat: 48.85}}}, n: 1}",
+        )
+        .unwrap();
+        assert_eq!(value, expected);
+        
+        let mut root = ToonValue::Null;
+        root.set("", ToonValue::Integer(5)).unwrap();
+        assert_eq!(root, ToonValue::Integer(5));
+        
+        // Conflicts leave the value untouched
+        let err = value.set("n.x", ToonValue::Null).unwrap_err().to_string();
+        assert!(err.contains("cannot set 'x' inside a number at 'n'"), "{}", err);
+        let err = value.set("user.tags[2]", ToonValue::Null).unwrap_err().to_string();
+        assert!(err.contains("index 2 is out of range for the array of length 2 at 'user.tags'"), "{}", err);
+        assert!(value.set("user.tags.0.x", ToonValue::Null).is_err());
+        assert_eq!(value, expected);
+        
+        let mut value = crate::decoder::decode("{b: 1, list: [{y: 2}]}").unwrap();
+        let mut order = KeyOrder::Object(vec![
+            ("b".to_string(), KeyOrder::None),
+            ("list".to_string(), KeyOrder::None),
+        ]);
+        let inner = KeyOrder::Object(vec![("z".to_string(), KeyOrder::None)]);
+        value.set_ordered(&mut order, "list[0].a", ToonValue::Object(HashMap::new()), inner.clone()).unwrap();
+        value.set_ordered(&mut order, "b", ToonValue::Integer(3), KeyOrder::None).unwrap();
+        assert_eq!(
+            order,
+            KeyOrder::Object(vec![
+                ("b".to_string(), KeyOrder::None),
+                ("list".to_string(), KeyOrder::Array(vec![KeyOrder::Object(vec![("a".to_string(), inner)])])),
+            ])
+        );
+    }
+    
+    #[test]
+    fn test_remove() {
+        let mut value = crate::decoder::decode(
+            "{user: {name: Ann, address: {city: Paris, zip: \"75001\"}}, tags: [a, b, c]}",
+        )
+        .unwrap();
+        assert_eq!(value.remove("user.address.zip"), Some(ToonValue::String("75001".to_string())));
+        assert_eq!(value.remove("tags[0]"), Some(ToonValue::String("a".to_string())));
+        assert_eq!(value.remove("tags/-1"), Some(ToonValue::String("c".to_string())));
+        let expected = crate::decoder::decode("{user: {name: Ann, address: {city: Paris}}, tags: [b]}").unwrap();
+        assert_eq!(value, expected);
+        
+        for path in ["", "user.age", "tags[5]", "user.name.x", "missing.key"] {
+            assert_eq!(value.remove(path), None, "{}", path);
+        }
+        assert_eq!(value, expected);
+        
+        // Whole subtrees come out in one piece
+        assert_eq!(value.remove("user"), crate::decoder::decode("{name: Ann, address: {city: Paris}}").ok());
+        
+        let mut value = crate::decoder::decode("{a: {c: 1}, b: [{x: 1}, {y: 2}]}").unwrap();
+        let inner = |key: &str| KeyOrder::Object(vec![(key.to_string(), KeyOrder::None)]);
+        let mut order = KeyOrder::Object(vec![
+            ("a".to_string(), inner("c")),
+            ("b".to_string(), KeyOrder::Array(vec![inner("x"), inner("y")])),
+        ]);
+        assert_eq!(value.remove_ordered(&mut order, "missing"), None);
+        assert_eq!(value.remove_ordered(&mut order, "b[0]").unwrap().1, inner("x"));
+        assert_eq!(value.remove_ordered(&mut order, "a").unwrap().1, inner("c"));
+        assert_eq!(value, crate::decoder::decode("{b: [{y: 2}]}").unwrap());
+        assert_eq!(order, KeyOrder::Object(vec![("b".to_string(), KeyOrder::Array(vec![inner("y")]))]));
+    }
+    
+    #[test]
+    fn test_contains_path() {
+        let value = crate::decoder::decode(
+            "{user: {address: {city: Paris, zip: null}, tags: [a, {k: 1}]}}",
+        )
+        .unwrap();
+        for path in ["", "user", "user.address.city", "user/address/zip", "user.tags[1].k", "user.tags/-2"] {
+            assert!(value.contains_path(path), "{}", path);
+        }
+        for path in ["nobody", "user.address.street", "user.tags[2]", "user.tags[0].k", "user.address.city.x"] {
+            assert!(!value.contains_path(path), "{}", path);
+        }
+    }
+    
+    #[test]
+    fn test_get_or() {
+        let config = crate::decoder::decode("{host: db1, port: 5432, debug: true, retry: {delay: 0.5}}").unwrap();
+        
+        assert_eq!(config.get_str_or("host", "localhost"), "db1");
+        assert_eq!(config.get_number_or("port", 80.0), 5432.0);
+        assert_eq!(config.get_number_or("retry.delay", 1.0), 0.5);
+        assert!(config.get_bool_or("debug", false));
+        
+        // Missing fields and wrong types fall back to the default
+        assert_eq!(config.get_str_or("user", "admin"), "admin");
+        assert_eq!(config.get_str_or("port", "none"), "none");
+        assert_eq!(config.get_number_or("host", 1.5), 1.5);
+        assert!(!config.get_bool_or("verbose", false));
+        assert!(config.get_bool_or("port", true));
+    }
+    
+    #[test]
+    fn test_get_path_as() {
+        let value = crate::decoder::decode(
+            "{user: {id: 7, score: 9.5, whole: 3.0, active: true, name: Ann, tags: [a, b]}}",
+        )
+        .unwrap();
+        
+        assert_eq!(value.get_path_as::<i64>("user.id"), Some(7));
+        assert_eq!(value.get_path_as::<i64>("user.whole"), Some(3));
+        assert_eq!(value.get_path_as::<f64>("user.id"), Some(7.0));
+        assert_eq!(value.get_path_as::<f64>("user/score"), Some(9.5));
+        assert_eq!(value.get_path_as::<bool>("user.active"), Some(true));
+        assert_eq!(value.get_path_as::<String>("user.tags[1]"), Some("b".to_string()));
+        assert_eq!(value.get_path_as::<String>("user.score"), Some("9.5".to_string()));
+        
+        // Misses and incompatible types
+        assert_eq!(value.get_path_as::<i64>("user.missing"), None);
+        assert_eq!(value.get_path_as::<i64>("user.tags[5]"), None);
+        assert_eq!(value.get_path_as::<i64>("user.score"), None);
+        assert_eq!(value.get_path_as::<i64>("user.name"), None);
+        assert_eq!(value.get_path_as::<bool>("user.id"), None);
+        assert_eq!(value.get_path_as::<String>("user.tags"), None);
+    }
+
+    #[test]
+    fn test_unwrap_key() {
+        let value = crate::decoder::decode("{result: {id: 1}}").unwrap();
+        assert_eq!(value.unwrap_key("result"), Some(&crate::decoder::decode("{id: 1}").unwrap()));
+        assert_eq!(value.unwrap_key("data"), None);
+        
+        let value = crate::decoder::decode("{result: 1, error: null}").unwrap();
+        assert_eq!(value.unwrap_key("result"), None);
+        assert_eq!(ToonValue::Array(vec![]).unwrap_key("result"), None);
+    }
+    
+    #[test]
+    fn test_truncate() {
+        let value = crate::decoder::decode(
+            "{ids: [1, 2, 3, 4, 5], note: \"hello world\", nested: [[a, b, c]]}",
+        )
+        .unwrap();
+        let preview = value.truncate(2, 5);
+        
+        let expected = crate::decoder::decode(
+            "{ids: [1, 2, \"…+3 more\"], note: \"hello…\", nested: [[a, b, \"…+1 more\"]]}",
+        )
+        .unwrap();
+        assert_eq!(preview, expected);
+        
+        // The original is left as it was, and small values are copied as-is
+        assert_eq!(value.pointer("ids").and_then(ToonValue::as_array).map(<[_]>::len), Some(5));
+        assert_eq!(value.truncate(10, 100), value);
+    }
+    
+    #[test]
+    fn test_merge_arrays_by_key() {
+        let mut base = crate::decoder::decode(
+            "[{id: 1, qty: 5}, {id: 2, qty: 3}, {sku: x}]",
+        )
+        .unwrap();
+        let patch = crate::decoder::decode(
+            "[{id: 2, qty: 9, note: restocked}, {id: 3, qty: 1}, {id: 3, qty: 4}]",
+        )
+        .unwrap();
+        base.merge_arrays_by_key(&patch, "id").unwrap();
+        let expected = crate::decoder::decode(
+            "[{id: 1, qty: 5}, {id: 2, qty: 9, note: restocked}, {sku: x}, {id: 3, qty: 4}]",
+        )
+        .unwrap();
+        assert_eq!(base, expected);
+        
+        // Keys match by value, so `1` and `"1"` are different records
+        let mut base = crate::decoder::decode("[{id: 1}]").unwrap();
+        let patch = crate::decoder::decode("[{id: \"1\"}]").unwrap();
+        base.merge_arrays_by_key(&patch, "id").unwrap();
+        assert_eq!(base.as_array().unwrap().len(), 2);
+        
+        let mut base = crate::decoder::decode("[{id: 1}]").unwrap();
+        let bad_patch = crate::decoder::decode("[{id: 2}, {sku: x}]").unwrap();
+        let err = base.merge_arrays_by_key(&bad_patch, "id").unwrap_err();
+        assert!(err.to_string().contains("element 1 of the patch"), "{}", err);
+        assert_eq!(base, crate::decoder::decode("[{id: 1}]").unwrap());
+        assert!(base.merge_arrays_by_key(&ToonValue::Null, "id").is_err());
+        
+        let mut base = crate::decoder::decode("[{id: 1}, {id: 2}]").unwrap();
+        let mut order = KeyOrder::Array(vec![KeyOrder::None, KeyOrder::Object(vec![("id".to_string(), KeyOrder::None)])]);
+        let patch = crate::decoder::decode("[{id: 1, a: x}, {id: 3}]").unwrap();
+        let keys = |names: &[&str]| KeyOrder::Object(names.iter().map(|k| (k.to_string(), KeyOrder::None)).collect());
+        let patch_order = KeyOrder::Array(vec![keys(&["a", "id"]), keys(&["id"])]);
+        base.merge_arrays_by_key_ordered(&mut order, &patch, &patch_order, "id").unwrap();
+        assert_eq!(order, KeyOrder::Array(vec![keys(&["a", "id"]), keys(&["id"]), keys(&["id"])]));
+    }
+    
+    #[test]
+    fn test_group_by() {
+        let records = crate::decoder::decode(
+            "[{id: 1, team: red}, {id: 2, team: blue}, {id: 3, team: red}, \
+             {id: 4, team: 7}, {id: 5}]",
+        )
+        .unwrap();
+        let expected = crate::decoder::decode(
+            "{red: [{id: 1, team: red}, {id: 3, team: red}], blue: [{id: 2, team: blue}], \
+             \"7\": [{id: 4, team: 7}], \"null\": [{id: 5}]}",
+        )
+        .unwrap();
+        assert_eq!(records.group_by("team").unwrap(), expected);
+        assert_eq!(
+            ToonValue::Array(Vec::new()).group_by("team").unwrap(),
+            ToonValue::Object(HashMap::new())
+        );
+        
+        let err = crate::decoder::decode("[{id: 1}, 2]").unwrap().group_by("id").unwrap_err();
+        assert!(err.to_string().contains("element 1 is not an object"), "{}", err);
+        assert!(records.pointer("0").unwrap().group_by("id").is_err());
+    }
+    
+    #[test]
+    fn test_sort_all_arrays() {
+        let mut a = crate::decoder::decode(
+            "{tags: [b, a, c], groups: [[3, 1], [2]], users: [{id: 2, roles: [y, x]}, {id: 1, roles: []}]}",
+        )
+        .unwrap();
+        let mut b = crate::decoder::decode(
+            "{users: [{id: 1, roles: []}, {id: 2, roles: [x, y]}], groups: [[2], [1, 3]], tags: [c, b, a]}",
+        )
+        .unwrap();
+        assert_ne!(a, b);
+        a.sort_all_arrays();
+        b.sort_all_arrays();
+        assert_eq!(a, b);
+        assert_eq!(a.pointer("tags"), crate::decoder::decode("[a, b, c]").ok().as_ref());
+        assert_eq!(a.pointer("groups/0"), crate::decoder::decode("[1, 3]").ok().as_ref());
+        
+        let options = EncodeOptions::new().tabular(false);
+        let encoded = |v: &ToonValue| {
+            crate::encoder::encode_with_key_order(v, &options, &KeyOrder::sorted(v)).unwrap()
+        };
+        assert_eq!(encoded(&a), encoded(&b));
+        assert!(encoded(&a).starts_with("groups: [[1, 3], [2]]\ntags: [a, b, c]\nusers: "));
+    }
+    
+    #[test]
+    fn test_find_all() {
+        let value = crate::decoder::decode(
+            r#"{"name": "Ann", "address": {"city": "Anytown", "zip": 12345}, "hobbies": ["reading", 7, ["chess"]]}"#,
+        )
+        .unwrap();
+        
+        let strings: Vec<(String, &str)> = value
+            .find_all(|v| v.as_str().is_some())
+            .into_iter()
+            .map(|(path, v)| (path, v.as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            strings,
+            vec![
+                ("address.city".to_string(), "Anytown"),
+                ("hobbies[0]".to_string(), "reading"),
+                ("hobbies[2][0]".to_string(), "chess"),
+                ("name".to_string(), "Ann"),
+            ]
+        );
+        
+        let containers: Vec<String> = value
+            .find_all(|v| matches!(v, ToonValue::Object(_) | ToonValue::Array(_)))
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(containers, vec!["", "address", "hobbies", "hobbies[2]"]);
+        assert!(value.find_all(|v| v.is_null()).is_empty());
+    }
+
+    #[test]
+    fn test_dedup_array() {
+        let mut value = crate::decoder::decode(
+            "[{id: 1, tags: [a, b]}, 2, {tags: [a, b], id: 1}, [2], 2, {id: 1, tags: [b, a]}, [2], 2.0]",
+        )
+        .unwrap();
+        value.dedup_array();
+        assert_eq!(
+            value,
+            crate::decoder::decode("[{id: 1, tags: [a, b]}, 2, [2], {id: 1, tags: [b, a]}, 2.0]").unwrap()
+        );
+        
+        // Nested arrays are left alone
+        let mut value = crate::decoder::decode("[[1, 1], [1, 1]]").unwrap();
+        value.dedup_array();
+        assert_eq!(value, crate::decoder::decode("[[1, 1]]").unwrap());
+        
+        let mut value = crate::decoder::decode("{a: [1, 1]}").unwrap();
+        value.dedup_array();
+        assert_eq!(value, crate::decoder::decode("{a: [1, 1]}").unwrap());
+    }
+
+    #[test]
+    fn test_for_each_leaf_mut() {
+        let mut value = crate::decoder::decode(
+            "{price: 9.876, items: [{w: 0.125, n: 3}, [1.5555, null, x]], ok: true, empty: []}",
+        )
+        .unwrap();
+        value.for_each_leaf_mut(|leaf| {
+            if let ToonValue::Number(n) = leaf {
+                *n = (*n * 100.0).round() / 100.0;
+            }
+        });
+        let expected = crate::decoder::decode(
+            "{price: 9.88, items: [{w: 0.13, n: 3}, [1.56, null, x]], ok: true, empty: []}",
+        )
+        .unwrap();
+        assert_eq!(value, expected);
+        
+        let mut leaves = 0;
+        value.for_each_leaf_mut(|_| leaves += 1);
+        assert_eq!(leaves, 7);
+        
+        // A root scalar is its own only leaf
+        let mut value = ToonValue::Bool(true);
+        value.for_each_leaf_mut(|leaf| *leaf = ToonValue::Null);
+        assert_eq!(value, ToonValue::Null);
+    }
+    
+    #[test]
+    fn test_stringify_numbers() {
+        let mut value = crate::decoder::decode(
+            "{ids: [1, 2.5, x], user: {id: 42, ok: true, \"7\": null}}",
+        )
+        .unwrap();
+        value.stringify_numbers();
+        
+        let expected = crate::decoder::decode(
+            "{ids: [\"1\", \"2.5\", x], user: {id: \"42\", ok: true, \"7\": null}}",
+        )
+        .unwrap();
+        assert_eq!(value, expected);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_json_conversions() {
+        let json = serde_json::json!({"id": 7, "score": 2.0, "tags": ["a", null], "meta": {"ok": true}});
+        let value = ToonValue::from(json.clone());
+        assert_eq!(
+            value,
+            crate::decoder::decode("{id: 7, score: 2.0, tags: [a, null], meta: {ok: true}}").unwrap()
+        );
+        assert_eq!(value.pointer("id"), Some(&ToonValue::Integer(7)));
+        assert_eq!(value.pointer("score"), Some(&ToonValue::Number(2.0)));
+        
+        let back = serde_json::Value::from(value);
+        assert_eq!(back, json);
+        assert!(back["id"].is_i64());
+        assert!(back["score"].is_f64());
+        
+        assert_eq!(serde_json::Value::from(ToonValue::Number(f64::NAN)), serde_json::Value::Null);
+    }
+    
+    #[test]
+    fn test_toon_value_hash() {
+        use std::collections::HashSet;
+        
+        let mut set = HashSet::new();
+        set.insert(crate::decoder::decode("{a: 1, b: [x, 0.0]}").unwrap());
+        set.insert(crate::decoder::decode("{b: [x, -0.0], a: 1}").unwrap());
+        assert_eq!(set.len(), 1);
+        
+        set.insert(ToonValue::Number(f64::NAN));
+        set.insert(ToonValue::Number(-f64::NAN));
+        set.insert(ToonValue::Integer(1));
+        set.insert(ToonValue::Number(1.0));
+        assert_eq!(set.len(), 4);
+    }
+    
+    #[test]
+    fn test_toon_value_ordering() {
+        let mut values = crate::decoder::decode(
+            "[{a: 1}, [2], b, 2.5, 2, true, null, false, [1, 9], a, {a: 0, b: 1}, -1]",
+        )
+        .unwrap()
+        .as_array()
+        .unwrap()
+        .to_vec();
+        values.push(ToonValue::Number(f64::NAN));
+        values.push(ToonValue::Number(2.0));
+        values.sort();
+        
+        let mut expected = crate::decoder::decode(
+            "[null, false, true, -1, 2, 2.0, 2.5, a, b, [1, 9], [2], {a: 0, b: 1}, {a: 1}]",
+        )
+        .unwrap()
+        .as_array()
+        .unwrap()
+        .to_vec();
+        expected.insert(7, ToonValue::Number(f64::NAN));
+        assert_eq!(values, expected);
+        
+        // NaN is normalized, and integers stay distinct from equal floats
+        assert_eq!(ToonValue::Number(f64::NAN), ToonValue::Number(f64::NAN));
+        assert_eq!(ToonValue::Number(-0.0), ToonValue::Number(0.0));
+        assert_ne!(ToonValue::Integer(2), ToonValue::Number(2.0));
+        
+        // Big integers compare exactly rather than as the nearest float
+        let two_53 = 9_007_199_254_740_992i64;
+        assert!(ToonValue::Integer(two_53 + 1) > ToonValue::Number(two_53 as f64));
+        assert!(ToonValue::Number(two_53 as f64) < ToonValue::Integer(two_53 + 1));
+        assert!(ToonValue::Integer(i64::MAX) < ToonValue::Number(i64::MAX as f64));
+        assert!(ToonValue::Integer(i64::MIN) < ToonValue::Number(i64::MIN as f64));
+        assert!(ToonValue::Integer(i64::MIN) > ToonValue::Number(f64::NEG_INFINITY));
+        assert!(ToonValue::Integer(-3) < ToonValue::Number(-2.5));
+        assert!(ToonValue::Integer(-2) > ToonValue::Number(-2.5));
+        
+        let set: std::collections::BTreeSet<_> = values.into_iter().collect();
+        assert_eq!(set.len(), 14);
+    }
+
+    #[test]
+    fn test_select() {
+        let user = crate::decoder::decode("{id: 1, name: Ann, email: \"a@x\", tags: [a]}").unwrap();
+        assert_eq!(
+            user.select(&["name", "id", "missing"]),
+            crate::decoder::decode("{id: 1, name: Ann}").unwrap()
+        );
+        assert_eq!(user.select(&[]), ToonValue::Object(HashMap::new()));
+        
+        let users = crate::decoder::decode("[{id: 1, name: Ann}, {id: 2, age: 40}, 3]").unwrap();
+        assert_eq!(
+            users.select(&["id", "name"]),
+            crate::decoder::decode("[{id: 1, name: Ann}, {id: 2}, 3]").unwrap()
+        );
+        assert_eq!(ToonValue::Integer(5).select(&["id"]), ToonValue::Integer(5));
+    }
+
+    #[test]
+    fn test_rename_keys() {
+        let mapping: HashMap<String, String> = [("id", "user_id"), ("a", "b"), ("b", "a")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        
+        let mut value = crate::decoder::decode("{id: 1, a: x, b: y, list: [{id: 2}, 3]}").unwrap();
+        value.rename_keys(&mapping).unwrap();
+        assert_eq!(
+            value,
+            crate::decoder::decode("{user_id: 1, b: x, a: y, list: [{user_id: 2}, 3]}").unwrap()
+        );
+        
+        // A collision anywhere is an error and leaves the value untouched
+        let original = crate::decoder::decode("{id: 1, nested: {id: 2, user_id: 3}}").unwrap();
+        let mut value = original.clone();
+        let err = value.rename_keys(&mapping).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid TOON format: renaming key 'id' to 'user_id' collides with key 'user_id'"
+        );
+        assert_eq!(value, original);
+        
+        let mut value = crate::decoder::decode("{b: y, id: 1, list: [{id: 2}]}").unwrap();
+        let mut order = KeyOrder::Object(vec![
+            ("b".to_string(), KeyOrder::None),
+            ("id".to_string(), KeyOrder::None),
+            ("list".to_string(), KeyOrder::Array(vec![KeyOrder::Object(vec![("id".to_string(), KeyOrder::None)])])),
+        ]);
+        value.rename_keys_ordered(&mut order, &mapping).unwrap();
+        assert_eq!(
+            order,
+            KeyOrder::Object(vec![
+                ("a".to_string(), KeyOrder::None),
+                ("user_id".to_string(), KeyOrder::None),
+                (
+                    "list".to_string(),
+                    KeyOrder::Array(vec![KeyOrder::Object(vec![("user_id".to_string(), KeyOrder::None)])])
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_approx_eq() {
+        let sum = ToonValue::Number(0.1 + 0.2);
+        let expected = ToonValue::Number(0.3);
+        assert_ne!(sum, expected);
+        assert!(sum.approx_eq(&expected, 1e-9));
+        assert!(!sum.approx_eq(&expected, 0.0));
+        
+        let a = crate::decoder::decode("{xs: [1, 2.0000001, x], y: {z: null}}").unwrap();
+        let b = crate::decoder::decode("{xs: [1.0, 2, x], y: {z: null}}").unwrap();
+        assert!(a.approx_eq(&b, 1e-6));
+        assert!(!a.approx_eq(&b, 1e-9));
+        
+        // Structure and non-numeric values must still match exactly
+        let c = crate::decoder::decode("{xs: [1, 2, y], y: {z: null}}").unwrap();
+        assert!(!a.approx_eq(&c, 1.0));
+        let d = crate::decoder::decode("{xs: [1, 2], y: {z: null}}").unwrap();
+        assert!(!a.approx_eq(&d, 1.0));
+        assert!(!ToonValue::Number(f64::INFINITY).approx_eq(&ToonValue::Number(f64::NEG_INFINITY), 1.0));
+        assert!(ToonValue::Number(f64::NAN).approx_eq(&ToonValue::Number(f64::NAN), 0.0));
+    }
+
+    #[test]
+    fn test_tagged() {
+        let circle = ToonValue::tagged(
+            "type",
+            "Circle",
+            crate::decoder::decode("{radius: 2}").unwrap(),
+        );
+        assert_eq!(circle, crate::decoder::decode("{type: Circle, data: {radius: 2}}").unwrap());
+        
+        let (tag, data) = circle.as_tagged("type").unwrap();
+        assert_eq!(tag, "Circle");
+        assert_eq!(data["radius"], ToonValue::Integer(2));
+        
+        // Scalar payloads, unit variants and untagged values
+        let id = ToonValue::tagged("kind", "Id", ToonValue::Integer(7));
+        assert_eq!(id.as_tagged("kind"), Some(("Id", &ToonValue::Integer(7))));
+        let unit = crate::decoder::decode("{type: Empty}").unwrap();
+        assert_eq!(unit.as_tagged("type"), Some(("Empty", &ToonValue::Null)));
+        assert_eq!(circle.as_tagged("kind"), None);
+        assert_eq!(crate::decoder::decode("{type: 1}").unwrap().as_tagged("type"), None);
+        assert_eq!(ToonValue::Null.as_tagged("type"), None);
+    }
+
+    #[test]
+    fn test_walk() {
+        let value = crate::decoder::decode(r#"{
+            "name": "John",
+            "age": 30,
+            "address": {
+                "street": "123 Main St",
+                "city": "Anytown"
+            },
+            "hobbies": ["reading", "swimming", "coding"]
+        }"#)
+        .unwrap();
+        
+        let paths: Vec<String> = value.walk().map(|(path, _)| path).collect();
+        assert_eq!(
+            paths,
+            [
+                "", "address", "address.city", "address.street", "age",
+                "hobbies", "hobbies[0]", "hobbies[1]", "hobbies[2]", "name",
+            ]
+        );
+        
+        // Every path leads back to the node it was yielded with
+        for (path, node) in value.walk() {
+            assert_eq!(value.pointer(&path), Some(node));
+        }
+        assert_eq!(value.walk().count(), value.node_count());
+        
+        let nested = crate::decoder::decode("[[{a: 1}]]").unwrap();
+        let paths: Vec<String> = nested.walk().map(|(path, _)| path).collect();
+        assert_eq!(paths, ["", "[0]", "[0][0]", "[0][0].a"]);
+    }
+
+    #[test]
+    fn test_to_records() {
+        let value = crate::decoder::decode("[{id: 1, name: Ann}, {id: 2, tags: [x]}, {}]").unwrap();
+        let (headers, rows) = value.to_records().unwrap();
+        
+        assert_eq!(headers, ["id", "name", "tags"]);
+        assert_eq!(
+            rows,
+            vec![
+                vec![ToonValue::Integer(1), ToonValue::String("Ann".to_string()), ToonValue::Null],
+                vec![ToonValue::Integer(2), ToonValue::Null, crate::decoder::decode("[x]").unwrap()],
+                vec![ToonValue::Null, ToonValue::Null, ToonValue::Null],
+            ]
+        );
+        
+        assert_eq!(ToonValue::Array(vec![]).to_records(), Some((vec![], vec![])));
+        assert_eq!(crate::decoder::decode("[{a: 1}, 2]").unwrap().to_records(), None);
+        assert_eq!(crate::decoder::decode("{a: 1}").unwrap().to_records(), None);
+    }
 }
@@ -1,22 +1,39 @@
 //! Utility functions for the TOON format implementation
 
+use std::borrow::Cow;
 use std::fmt::Write;
 
+use crate::types::EscapeTable;
+
 /// Escape a string for use in TOON format
-pub(crate) fn escape_str(s: &str) -> String {
+///
+/// Characters selected by `table` use their short escape (`\n`, `\/`, ...)
+/// where one exists and a `\uXXXX` escape otherwise. Strings with nothing
+/// to escape, the common case, are returned as they are without allocating.
+pub(crate) fn escape_str<'a>(s: &'a str, table: &EscapeTable) -> Cow<'a, str> {
+    if !needs_escaping(s, table) {
+        return Cow::Borrowed(s);
+    }
+    
     let mut result = String::with_capacity(s.len() * 2);
     
     for c in s.chars() {
+        if !table.should_escape(c) {
+            result.push(c);
+            continue;
+        }
+        
         match c {
             '\\' => result.push_str("\\\\"),
             '\"' => result.push_str("\\\""),
+            '/' => result.push_str("\\/"),
             '\n' => result.push_str("\\n"),
             '\r' => result.push_str("\\r"),
             '\t' => result.push_str("\\t"),
             '\0' => result.push_str("\\0"),
             '\x08' => result.push_str("\\b"),
             '\x0c' => result.push_str("\\f"),
-            c if c.is_control() => {
+            c => {
                 let code = c as u32;
                 if code <= 0xFFFF {
                     write!(&mut result, "\\u{:04x}", code).unwrap();
@@ -24,11 +41,26 @@ pub(crate) fn escape_str(s: &str) -> String {
                     write!(&mut result, "\\U{:08x}", code).unwrap();
                 }
             }
-            c => result.push(c),
         }
     }
     
-    result
+    Cow::Owned(result)
+}
+
+/// Check whether any character of `s` is selected by `table`, scanning bytes
+///
+/// Non-ASCII characters are only escaped if they are controls, and the only
+/// ones (U+0080 to U+009F) are encoded as `0xC2` followed by `0x80..=0x9F`.
+/// `0xC2` never appears as a continuation byte, so this can't misfire.
+fn needs_escaping(s: &str, table: &EscapeTable) -> bool {
+    let bytes = s.as_bytes();
+    bytes.iter().enumerate().any(|(i, &b)| {
+        if b.is_ascii() {
+            table.should_escape(b as char)
+        } else {
+            b == 0xC2 && matches!(bytes.get(i + 1), Some(0x80..=0x9F))
+        }
+    })
 }
 
 /// Unescape a string from TOON format
@@ -51,6 +83,7 @@ pub(crate) fn unescape_str(s: &str) -> Result<String, String> {
             Some('n') => result.push('\n'),
             Some('r') => result.push('\r'),
             Some('t') => result.push('\t'),
+            Some('0') => result.push('\0'),
             Some('u') => {
                 // Parse unicode escape sequence \uXXXX
                 let hex_str: String = chars.by_ref().take(4).collect();
@@ -97,8 +130,14 @@ pub(crate) fn is_ident_continue(c: char) -> bool {
 }
 
 /// Format a number as a string, removing unnecessary decimal places
+///
+/// Non-finite values use the reserved `nan`, `inf` and `-inf` spellings.
 pub(crate) fn format_number(n: f64) -> String {
-    if n.fract() == 0.0 {
+    if n.is_nan() {
+        "nan".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else if n.fract() == 0.0 {
         format!("{:.0}", n)
     } else {
         // Remove trailing zeros and decimal point if not needed
@@ -136,16 +175,141 @@ pub(crate) fn needs_quotes(s: &str) -> bool {
     )
 }
 
+/// Check if a string must be quoted for the decoder to read it back unchanged
+///
+/// Keys (and tabular header fields) only need to be identifiers, since bare
+/// `true`, `false` and `null` keys decode as strings. Values must also not
+/// start with `t`, `f` or `n`, which the decoder reads as keywords; other
+/// reserved words such as `inf` decode as plain strings.
+pub(crate) fn needs_quotes_minimal(s: &str, is_key: bool) -> bool {
+    let mut chars = s.chars();
+    let first = match chars.next() {
+        Some(c) if is_ident_start(c) => c,
+        _ => return true,
+    };
+    
+    if !chars.all(is_ident_continue) {
+        return true;
+    }
+    
+    !is_key && matches!(first, 't' | 'f' | 'n')
+}
+
+/// Check if a string is a strict ISO-8601 calendar date or date-time
+///
+/// Accepts `YYYY-MM-DD`, optionally followed by `THH:MM:SS`, a fraction of
+/// a second (`.123`) and a `Z` or `±HH:MM` offset. The date must exist, so
+/// `2023-02-29` is rejected while `2024-02-29` is not.
+pub(crate) fn is_iso8601_timestamp(s: &str) -> bool {
+    let b = s.as_bytes();
+    let digits = |start: usize, len: usize| -> Option<u32> {
+        b.get(start..start + len)?.iter().try_fold(0, |n, &d| {
+            d.is_ascii_digit().then(|| n * 10 + u32::from(d - b'0'))
+        })
+    };
+    let sep = |i: usize, c: u8| b.get(i) == Some(&c);
+    
+    let (Some(year), Some(month), Some(day)) = (digits(0, 4), digits(5, 2), digits(8, 2)) else {
+        return false;
+    };
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return false,
+    };
+    if !sep(4, b'-') || !sep(7, b'-') || day == 0 || day > days {
+        return false;
+    }
+    if b.len() == 10 {
+        return true;
+    }
+    
+    let (Some(hour), Some(minute), Some(second)) = (digits(11, 2), digits(14, 2), digits(17, 2)) else {
+        return false;
+    };
+    if !sep(10, b'T') || !sep(13, b':') || !sep(16, b':') || hour > 23 || minute > 59 || second > 59 {
+        return false;
+    }
+    
+    let mut i = 19;
+    if sep(i, b'.') {
+        let fraction = b[i + 1..].iter().take_while(|d| d.is_ascii_digit()).count();
+        if fraction == 0 {
+            return false;
+        }
+        i += 1 + fraction;
+    }
+    match b.get(i) {
+        None => true,
+        Some(b'Z') => i + 1 == b.len(),
+        Some(b'+' | b'-') => {
+            i + 6 == b.len()
+                && sep(i + 3, b':')
+                && digits(i + 1, 2).is_some_and(|h| h <= 23)
+                && digits(i + 4, 2).is_some_and(|m| m <= 59)
+        }
+        Some(_) => false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     
     #[test]
     fn test_escape_str() {
-        assert_eq!(escape_str("hello"), "hello");
-        assert_eq!(escape_str("hello\nworld"), "hello\\nworld");
-        assert_eq!(escape_str("qu\"ote"), "qu\\\"ote");
-        assert_eq!(escape_str("back\\slash"), "back\\\\\\\\slash");
+        let table = EscapeTable::default();
+        assert_eq!(escape_str("hello", &table), "hello");
+        assert_eq!(escape_str("hello\nworld", &table), "hello\\nworld");
+        assert_eq!(escape_str("qu\"ote", &table), "qu\\\"ote");
+        assert_eq!(escape_str("back\\slash", &table), "back\\\\slash");
+        assert_eq!(escape_str("bell\x07", &table), "bell\\u0007");
+        assert_eq!(escape_str("a</b>", &table), "a</b>");
+        
+        let table = EscapeTable::default().with('/');
+        assert_eq!(escape_str("a</b>", &table), "a<\\/b>");
+    }
+    
+    #[test]
+    fn test_escape_str_borrows_when_unchanged() {
+        let table = EscapeTable::default();
+        assert!(matches!(escape_str("plain text, café ☕ 𝄞", &table), Cow::Borrowed(_)));
+        assert!(matches!(escape_str("", &table), Cow::Borrowed(_)));
+        assert!(matches!(escape_str("tab\there", &table), Cow::Owned(_)));
+        assert!(matches!(escape_str("a</b>", &table.with('/')), Cow::Owned(_)));
+        
+        // The byte scan agrees with the per-character check for every
+        // character, alone and surrounded by multi-byte neighbours
+        let tables = [table, table.with('/').with('<')];
+        let chars = (0..0x3000).chain(0x1_0000..0x1_0100).filter_map(char::from_u32);
+        for c in chars {
+            for table in &tables {
+                for s in [c.to_string(), format!("é{}ß", c)] {
+                    let expected = s.chars().any(|c| table.should_escape(c));
+                    let escaped = escape_str(&s, table);
+                    assert_eq!(matches!(escaped, Cow::Owned(_)), expected, "{:?}", s);
+                    if !expected {
+                        assert_eq!(escaped, s);
+                    }
+                }
+            }
+        }
+        assert_eq!(escape_str("x\u{85}y", &table), "x\\u0085y");
+    }
+    
+    #[test]
+    fn test_escape_str_custom_table() {
+        let table = EscapeTable::default().with('<').with('>').with('&');
+        let escaped = escape_str("<b>Tom & Jerry</b>", &table);
+        assert_eq!(escaped, "\\u003cb\\u003eTom \\u0026 Jerry\\u003c/b\\u003e");
+        assert_eq!(unescape_str(&escaped).unwrap(), "<b>Tom & Jerry</b>");
+        
+        // Quotes and backslashes are always escaped, and non-ASCII is ignored
+        let table = EscapeTable::default().with('é');
+        assert_eq!(escape_str("\"é\\", &table), "\\\"é\\\\");
     }
     
     #[test]
@@ -153,7 +317,7 @@ mod tests {
         assert_eq!(unescape_str("hello").unwrap(), "hello");
         assert_eq!(unescape_str("hello\\nworld").unwrap(), "hello\nworld");
         assert_eq!(unescape_str("qu\\\"ote").unwrap(), "qu\"ote");
-        assert_eq!(unescape_str("back\\\\\\\\slash").unwrap(), "back\\slash");
+        assert_eq!(unescape_str("back\\\\slash").unwrap(), "back\\slash");
         assert_eq!(unescape_str("unicode\\u0041").unwrap(), "unicodeA");
         
         // Test error cases
@@ -179,12 +343,69 @@ mod tests {
         assert!(needs_quotes("inf"));
     }
     
+    #[test]
+    fn test_needs_quotes_minimal() {
+        assert!(!needs_quotes_minimal("hello", false));
+        assert!(!needs_quotes_minimal("inf", false));
+        assert!(!needs_quotes_minimal("infinity", false));
+        assert!(!needs_quotes_minimal("true", true));
+        assert!(!needs_quotes_minimal("null", true));
+        
+        assert!(needs_quotes_minimal("true", false));
+        assert!(needs_quotes_minimal("name", false));
+        assert!(needs_quotes_minimal("nan", false));
+        assert!(needs_quotes_minimal("", true));
+        assert!(needs_quotes_minimal("hello world", true));
+        assert!(needs_quotes_minimal("123", true));
+    }
+    
     #[test]
     fn test_format_number() {
         assert_eq!(format_number(42.0), "42");
-        assert_eq!(format_number(3.14), "3.14");
+        assert_eq!(format_number(2.75), "2.75");
         assert_eq!(format_number(2.0), "2");
         assert_eq!(format_number(0.0), "0");
         assert_eq!(format_number(1.2300), "1.23");
+        assert_eq!(format_number(f64::NAN), "nan");
+        assert_eq!(format_number(f64::INFINITY), "inf");
+        assert_eq!(format_number(f64::NEG_INFINITY), "-inf");
+    }
+    
+    #[test]
+    fn test_is_iso8601_timestamp() {
+        for s in [
+            "2024-01-31",
+            "2024-02-29",
+            "2000-02-29",
+            "2024-06-01T12:30:00",
+            "2024-06-01T23:59:59Z",
+            "2024-06-01T00:00:00.5+02:00",
+            "2024-06-01T00:00:00.123456-05:30",
+        ] {
+            assert!(is_iso8601_timestamp(s), "{}", s);
+        }
+        for s in [
+            "",
+            "2024",
+            "2024-1-31",
+            "2024/01/31",
+            "2023-02-29",
+            "1900-02-29",
+            "2024-04-31",
+            "2024-13-01",
+            "2024-00-10",
+            "2024-01-31 12:00:00",
+            "2024-01-31T12:00",
+            "2024-01-31T24:00:00",
+            "2024-01-31T12:60:00",
+            "2024-01-31T12:00:00.",
+            "2024-01-31T12:00:00Zx",
+            "2024-01-31T12:00:00+0200",
+            "2024-01-31T12:00:00+24:00",
+            "2024-01-31x",
+            "２０２４-01-31",
+        ] {
+            assert!(!is_iso8601_timestamp(s), "{}", s);
+        }
     }
 }